@@ -1,7 +1,14 @@
 mod utils;
+pub mod shader;
+pub mod material;
+pub mod renderer;
+pub mod postprocess;
+pub mod math;
+pub mod mesh;
+mod viewer;
 
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     rc::Rc,
     f32::consts::PI,
 };
@@ -9,26 +16,30 @@ use std::{
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{
-    WebGlProgram, WebGlRenderingContext, WebGlShader,
-    WebGlUniformLocation, WebGlBuffer,
-    EventTarget, MouseEvent,
+    WebGlRenderingContext,
+    console, Event, EventTarget, MouseEvent,
 };
-use js_sys::WebAssembly;
 
-const AMORTIZATION: f32 = 0.95;
+use material::{Material, MaterialParam, ProgramCache, RenderState};
+use mesh::{
+    split_mesh, wireframe_edges, Bounds, Bvh, CullStats, Frustum, LineCap, LineJoin, LineStyle,
+    OctreeSettings, PointCloud, PointOctree, Polylines, Ray, TriangleMesh, MAX_U16_VERTICES,
+};
+use renderer::{
+    supports_u32_indices, view_depth, BufferHandle, DrawItem, DrawKey, GlState, IndexBuffer,
+    IndexType, Light, LineMesh, Picker, PointCloudMesh, PointCloudStats, PrimitiveMode, RenderQueue,
+    RenderTarget, ResourceManager, ShadowMap, ShadowSettings, DEFAULT_POINT_BUDGET,
+};
+use postprocess::PostProcessStack;
+pub use viewer::Viewer;
+use viewer::{PickFn, RaycastFn};
 
-#[derive(Debug, Clone)]
-struct ProgramInfo(
-    WebGlProgram,
-    (u32, u32),
-    (
-        Result<WebGlUniformLocation, String>,
-        Result<WebGlUniformLocation, String>,
-    ),
-);
+const AMORTIZATION: f32 = 0.95;
 
 #[wasm_bindgen()]
-pub fn start(canvas_id: &str) -> Result<(), JsValue> {
+pub fn start(canvas_id: &str) -> Result<Viewer, JsValue> {
+    utils::set_panic_hook();
+
     // Create a canvas
     let document = web_sys::window().unwrap().document().unwrap();
     let canvas = document
@@ -41,62 +52,64 @@ pub fn start(canvas_id: &str) -> Result<(), JsValue> {
         .unwrap()
         .dyn_into::<WebGlRenderingContext>()?;
 
-    // Compile the shader program
-    let vert_shader = compile_shader(
-        &context,
-        WebGlRenderingContext::VERTEX_SHADER,
-        r#"
-        attribute vec4 position;
-        attribute vec4 color;
+    // GPU objects are owned by the resource manager and freed when their
+    // handles are dropped.
+    let resources = ResourceManager::new(&context);
 
-        uniform mat4 projection_matrix;
-        uniform mat4 model_view_matrix;
+    // Programs are cached by their source, so materials using the same
+    // shaders share one.
+    let program_cache = Rc::new(RefCell::new(ProgramCache::new(&resources)));
 
-        varying lowp vec4 vColor;
+    // Post-processing effects, all off until toggled from JS.
+    let post_process = Rc::new(RefCell::new(PostProcessStack::with_builtin_effects(
+        &resources,
+        &mut program_cache.borrow_mut(),
+    )?));
 
-        void main() {
-            gl_Position = projection_matrix * model_view_matrix * position;
-            vColor = color;
-        }
-    "#,
-    )?;
-    let frag_shader = compile_shader(
-        &context,
-        WebGlRenderingContext::FRAGMENT_SHADER,
-        r#"
-        varying lowp vec4 vColor;
+    // Shadows from a directional light above the cube, cast onto the
+    // ground. Off until enabled from JS.
+    let shadow_map = Rc::new(RefCell::new(ShadowMap::new(
+        &resources,
+        &mut program_cache.borrow_mut(),
+        Light::Directional {
+            direction: [-0.5, -1.0, -0.75],
+            target: [0.0, 0.0, 0.0],
+            extent: 3.0,
+        },
+        ShadowSettings::default(),
+    )?));
 
-        void main() {
-            gl_FragColor = vColor;
-        }
-    "#,
-    )?;
-    let program = link_program(&context, &vert_shader, &frag_shader)?;
-    context.use_program(Some(&program));
-
-    // Collect all the info needed to use the shader program.
-    // Look up which attributes the program is using for "posision", "color",
-    // and also look up uniform locations.
-    let program_info = {
-        let vertex_position = context
-            .get_attrib_location(&program, "position") as u32;
-        let vertex_color = context
-            .get_attrib_location(&program, "color") as u32;
-        let projection_matrix = context
-            .get_uniform_location(&program, "projection_matrix")
-            .ok_or_else(|| String::from("cannot get projection_matrix"));
-        let model_view_matrix = context
-            .get_uniform_location(&program, "model_view_matrix")
-            .ok_or_else(|| String::from("cannot get model_view_matrix"));
-        ProgramInfo(
-            program,
-            (vertex_position, vertex_color),
-            (projection_matrix, model_view_matrix),
-        )
-    };
+    // Build the materials the model and the ground are drawn with.
+    let materials = Rc::new(RefCell::new(SceneMaterials::new(
+        &context,
+        &mut program_cache.borrow_mut(),
+        &shadow_map.borrow(),
+    )?));
 
     // Call the routine that builds all the objects that will be drawed.
-    let buffers: Buffers = init_buffers(&context)?;
+    // The cube is shown until a model is loaded.
+    let model = Rc::new(RefCell::new(Model::cube(&resources)?));
+    let ground = ground_buffers(&resources)?;
+
+    // The route overlay, empty until a route is set from JS.
+    let route = Rc::new(RefCell::new(Route::new(
+        &context,
+        &mut program_cache.borrow_mut(),
+        &resources,
+    )?));
+
+    // A scanned point cloud, none until one is loaded from JS.
+    let scan = Rc::new(RefCell::new(Scan::new(
+        &context,
+        &mut program_cache.borrow_mut(),
+        &resources,
+    )?));
+
+    // Color-ID picking, run on demand rather than every frame.
+    let picker = Rc::new(RefCell::new(Picker::new(
+        &resources,
+        &mut program_cache.borrow_mut(),
+    )?));
 
 
     // Draw the scene repeatedly
@@ -105,10 +118,71 @@ pub fn start(canvas_id: &str) -> Result<(), JsValue> {
     let drag = Rc::new(RefCell::new(false));
     let theta = Rc::new(RefCell::new(0.0));
     let phi = Rc::new(RefCell::new(0.0));
-    let dX = Rc::new(RefCell::new(0.0));
-    let dY = Rc::new(RefCell::new(0.0));
+    let dx = Rc::new(RefCell::new(0.0));
+    let dy = Rc::new(RefCell::new(0.0));
     let canvas_width = Rc::new(RefCell::new(canvas.width() as f32));
     let canvas_height = Rc::new(RefCell::new(canvas.height() as f32));
+    let context_lost = Rc::new(Cell::new(false));
+    // A press and release without dragging in between selects the node
+    // under the cursor.
+    let clicked = Rc::new(RefCell::new(false));
+    let selected = Rc::new(RefCell::new(None));
+    // Draws tested and skipped by frustum culling in the last frame.
+    let cull_stats = Rc::new(Cell::new(CullStats::default()));
+    // Draw the model's edges instead of its faces in the main pass.
+    let wireframe = Rc::new(Cell::new(false));
+
+    // Pick at CSS pixel coordinates relative to the canvas.
+    let pick: PickFn = {
+        let canvas = canvas.clone();
+        let picker = picker.clone();
+        let model = model.clone();
+        let theta = theta.clone();
+        let phi = phi.clone();
+        let context_lost = context_lost.clone();
+        let state = resources.state().clone();
+        Rc::new(move |x: f32, y: f32| {
+            if context_lost.get() {
+                return Ok(None);
+            }
+            // The drawing buffer may be larger than the canvas on screen.
+            let scale_x = canvas.width() as f32 / canvas.client_width().max(1) as f32;
+            let scale_y = canvas.height() as f32 / canvas.client_height().max(1) as f32;
+            let picked = pick_scene(
+                &state,
+                &mut picker.borrow_mut(),
+                &model.borrow(),
+                *theta.borrow(),
+                *phi.borrow(),
+                (x * scale_x) as i32,
+                (y * scale_y) as i32,
+            );
+            // The picker sets up its pass behind the cache's back.
+            state.invalidate();
+            picked
+        })
+    };
+
+    // Ray casts against the model on the CPU, for measuring.
+    let raycast: RaycastFn = {
+        let canvas = canvas.clone();
+        let model = model.clone();
+        let theta = theta.clone();
+        let phi = phi.clone();
+        Rc::new(move |x: f32, y: f32| {
+            let scale_x = canvas.width() as f32 / canvas.client_width().max(1) as f32;
+            let scale_y = canvas.height() as f32 / canvas.client_height().max(1) as f32;
+            raycast_scene(
+                &model.borrow(),
+                *theta.borrow(),
+                *phi.borrow(),
+                canvas.width() as i32,
+                canvas.height() as i32,
+                x * scale_x,
+                y * scale_y,
+            )
+        })
+    };
 
     // get canvas as event target
     let event_target: EventTarget = canvas.into();
@@ -117,8 +191,10 @@ pub fn start(canvas_id: &str) -> Result<(), JsValue> {
     // MOUSEDOWN
     {
         let drag = drag.clone();
+        let clicked = clicked.clone();
         let mousedown_cb = Closure::wrap(Box::new(move |_event: MouseEvent| {
             *drag.borrow_mut() = true;
+            *clicked.borrow_mut() = true;
         }) as Box<dyn FnMut(MouseEvent)>);
         event_target
             .add_event_listener_with_callback("mousedown", mousedown_cb.as_ref().unchecked_ref())
@@ -128,8 +204,19 @@ pub fn start(canvas_id: &str) -> Result<(), JsValue> {
     // MOUSEUP and MOUSEOUT
     {
         let drag = drag.clone();
-        let mouseup_cb = Closure::wrap(Box::new(move |_event: MouseEvent| {
+        let clicked = clicked.clone();
+        let selected = selected.clone();
+        let pick = pick.clone();
+        let mouseup_cb = Closure::wrap(Box::new(move |event: MouseEvent| {
             *drag.borrow_mut() = false;
+            if event.type_() == "mouseup" && *clicked.borrow() {
+                *selected.borrow_mut() = pick(event.offset_x() as f32, event.offset_y() as f32)
+                    .unwrap_or_else(|error| {
+                        console::error_1(&error);
+                        None
+                    });
+            }
+            *clicked.borrow_mut() = false;
         }) as Box<dyn FnMut(MouseEvent)>);
         event_target
             .add_event_listener_with_callback("mouseup", mouseup_cb.as_ref().unchecked_ref())
@@ -145,17 +232,21 @@ pub fn start(canvas_id: &str) -> Result<(), JsValue> {
         let phi = phi.clone();
         let canvas_width = canvas_width.clone();
         let canvas_height = canvas_height.clone();
-        let dX = dX.clone();
-        let dY = dY.clone();
+        let dx = dx.clone();
+        let dy = dy.clone();
         let drag = drag.clone();
+        let clicked = clicked.clone();
         let mousemove_cb = Closure::wrap(Box::new(move |event: MouseEvent| {
             if *drag.borrow() {
+                if event.movement_x() != 0 || event.movement_y() != 0 {
+                    *clicked.borrow_mut() = false;
+                }
                 let cw = *canvas_width.borrow();
                 let ch = *canvas_height.borrow();
-                *dX.borrow_mut() = (event.movement_x() as f32) * 2.0 * PI / cw;
-                *dY.borrow_mut() = (event.movement_y() as f32) * 2.0 * PI / ch;
-                *theta.borrow_mut() += *dX.borrow();
-                *phi.borrow_mut() += *dY.borrow();
+                *dx.borrow_mut() = (event.movement_x() as f32) * 2.0 * PI / cw;
+                *dy.borrow_mut() = (event.movement_y() as f32) * 2.0 * PI / ch;
+                *theta.borrow_mut() += *dx.borrow();
+                *phi.borrow_mut() += *dy.borrow();
             }
         }) as Box<dyn FnMut(web_sys::MouseEvent)>);
         event_target
@@ -163,34 +254,170 @@ pub fn start(canvas_id: &str) -> Result<(), JsValue> {
             .unwrap();
         mousemove_cb.forget();
     }
+    // WEBGLCONTEXTLOST and WEBGLCONTEXTRESTORED
+    // Every GL object dies with the context. Stop drawing until it comes
+    // back, then rebuild everything from what was kept on the CPU side.
+    {
+        let context_lost = context_lost.clone();
+        let contextlost_cb = Closure::wrap(Box::new(move |event: Event| {
+            // Without this the browser never restores the context.
+            event.prevent_default();
+            context_lost.set(true);
+        }) as Box<dyn FnMut(Event)>);
+        event_target
+            .add_event_listener_with_callback(
+                "webglcontextlost",
+                contextlost_cb.as_ref().unchecked_ref(),
+            )
+            .unwrap();
+        contextlost_cb.forget();
+    }
+    {
+        let context = context.clone();
+        let context_lost = context_lost.clone();
+        let resources = resources.clone();
+        let program_cache = program_cache.clone();
+        let post_process = post_process.clone();
+        let shadow_map = shadow_map.clone();
+        let materials = materials.clone();
+        let picker = picker.clone();
+        let contextrestored_cb = Closure::wrap(Box::new(move |_event: Event| {
+            let restored = resources.restore().and_then(|_| {
+                let mut program_cache = program_cache.borrow_mut();
+                program_cache.restore(&context)?;
+                post_process.borrow_mut().restore(&context)?;
+                picker.borrow_mut().restore()?;
+                let mut shadow_map = shadow_map.borrow_mut();
+                shadow_map.restore(&mut program_cache)?;
+                // The receivers follow the restored map's depth storage.
+                *materials.borrow_mut() =
+                    SceneMaterials::new(&context, &mut program_cache, &shadow_map)?;
+                Ok(())
+            });
+            match restored {
+                Ok(()) => context_lost.set(false),
+                // Stay paused rather than draw with half the resources.
+                Err(error) => console::error_2(&"failed to restore WebGL context:".into(), &error),
+            }
+        }) as Box<dyn FnMut(Event)>);
+        event_target
+            .add_event_listener_with_callback(
+                "webglcontextrestored",
+                contextrestored_cb.as_ref().unchecked_ref(),
+            )
+            .unwrap();
+        contextrestored_cb.forget();
+    }
     // RequestAnimationFrame
     {
-        let dX = dX.clone();
-        let dY = dY.clone();
+        let context = context.clone();
+        let context_lost = context_lost.clone();
+        let post_process = post_process.clone();
+        let shadow_map = shadow_map.clone();
+        let cull_stats = cull_stats.clone();
+        let wireframe = wireframe.clone();
+        let model = model.clone();
+        let route = route.clone();
+        let scan = scan.clone();
+        let state = resources.state().clone();
+        let dx = dx.clone();
+        let dy = dy.clone();
         let drag = drag.clone();
         // Request animation frame
         *g.borrow_mut() = Some(Closure::wrap(Box::new(move |_d| {
+            // The context can be lost before the event saying so arrives,
+            // so ask it directly as well.
+            if context_lost.get() || context.is_context_lost() {
+                // Keep the loop alive so drawing resumes once restored.
+                request_animation_frame(f.borrow().as_ref().unwrap());
+                return;
+            }
             if !*drag.borrow() {
-                *dX.borrow_mut() *= AMORTIZATION;
-                *dY.borrow_mut() *= AMORTIZATION;
-                *theta.borrow_mut() += *dX.borrow();
-                *phi.borrow_mut() += *dY.borrow();
+                *dx.borrow_mut() *= AMORTIZATION;
+                *dy.borrow_mut() *= AMORTIZATION;
+                *theta.borrow_mut() += *dx.borrow();
+                *phi.borrow_mut() += *dy.borrow();
             }
-            draw_scene(
-                &context.clone(),
-                program_info.clone(),
-                buffers.clone(),
-                *theta.borrow(),
-                *phi.borrow(),
-            )
-            .unwrap();
+            let mut post_process = post_process.borrow_mut();
+            let mut shadow_map = shadow_map.borrow_mut();
+            let shadow = if shadow_map.enabled() {
+                Some(&mut *shadow_map)
+            } else {
+                None
+            };
+            let materials = materials.borrow();
+            let model = model.borrow();
+            let route = route.borrow();
+            let mut scan = scan.borrow_mut();
+            let mut stats = CullStats::default();
+            let drawn = (|| -> Result<(), JsValue> {
+                if post_process.is_active() {
+                    // Draw the scene offscreen, then let the effects present
+                    // it.
+                    let scene_target = post_process.begin()?;
+                    draw_scene(
+                        &state,
+                        Some(scene_target),
+                        shadow,
+                        &materials,
+                        &model,
+                        &ground,
+                        &route,
+                        &mut scan,
+                        *theta.borrow(),
+                        *phi.borrow(),
+                        wireframe.get(),
+                        &mut stats,
+                    )?;
+                    post_process.finish(&context)?;
+                    state.invalidate();
+                } else {
+                    draw_scene(
+                        &state,
+                        None,
+                        shadow,
+                        &materials,
+                        &model,
+                        &ground,
+                        &route,
+                        &mut scan,
+                        *theta.borrow(),
+                        *phi.borrow(),
+                        wireframe.get(),
+                        &mut stats,
+                    )?;
+                }
+                Ok(())
+            })();
+            if let Err(error) = drawn {
+                // Skip the frame rather than panic; a lost context stops
+                // drawing until it is restored, anything else is retried.
+                console::error_1(&error);
+                state.invalidate();
+            }
+            cull_stats.set(stats);
             // Schedule ourself for another requestAnimationFrame callback.
             request_animation_frame(f.borrow().as_ref().unwrap());
-        }) as Box<FnMut(f32)>));
+        }) as Box<dyn FnMut(f32)>));
 
         request_animation_frame(g.borrow().as_ref().unwrap());
     }
-    Ok(())
+    Ok(Viewer::new(
+        context,
+        resources,
+        program_cache,
+        post_process,
+        shadow_map,
+        context_lost,
+        pick,
+        selected,
+        raycast,
+        cull_stats,
+        wireframe,
+        model,
+        route,
+        scan,
+    ))
 
 /*
     // Draw the scene repeatedly
@@ -209,264 +436,811 @@ pub fn start(canvas_id: &str) -> Result<(), JsValue> {
 */
 }
 
+/// The vertex positions, normals and colors of a mesh, or of one part of
+/// a split mesh, its triangle indices and bounds for culling, and the edge
+/// indices it is drawn with as a wireframe. The handles free their GPU
+/// buffers once the last clone is dropped.
 #[derive(Debug, Clone)]
-struct Buffers(WebGlBuffer, WebGlBuffer, WebGlBuffer);
-
-fn init_buffers(context: &WebGlRenderingContext) -> Result<Buffers, JsValue> {
-    // Create a buffer for the cube's vertex positions.
-    let position_buffer = context
-        .create_buffer()
-        .ok_or("failed to create position_buffer")?;
-    // Select the position_buffer as the one to apply buffer operstions to from here out.
-    context.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&position_buffer));
-
-    // Create an array of positions for the cube.
-    let positions: [f32; 72] = [
-        // Front face
-        -1.0, -1.0, 1.0, //
-        1.0, -1.0, 1.0, //
-        1.0, 1.0, 1.0, //
-        -1.0, 1.0, 1.0, //
-        // Back face
-        -1.0, -1.0, -1.0, //
-        -1.0, 1.0, -1.0, //
-        1.0, 1.0, -1.0, //
-        1.0, -1.0, -1.0, //
-        // Top face
-        -1.0, 1.0, -1.0, //
-        -1.0, 1.0, 1.0, //
-        1.0, 1.0, 1.0, //
-        1.0, 1.0, -1.0, //
-        // Bottom face
-        -1.0, -1.0, -1.0, //
-        1.0, -1.0, -1.0, //
-        1.0, -1.0, 1.0, //
-        -1.0, -1.0, 1.0, //
-        // Right face
-        1.0, -1.0, -1.0, //
-        1.0, 1.0, -1.0, //
-        1.0, 1.0, 1.0, //
-        1.0, -1.0, 1.0, //
-        // Left face
-        -1.0, -1.0, -1.0, //
-        -1.0, -1.0, 1.0, //
-        -1.0, 1.0, 1.0, //
-        -1.0, 1.0, -1.0, //
-    ];
-    let position_array = float_32_array!(positions);
-    // Pass the list of positions into WebGL to build the shape.
-    // Do this by creating a Float32Array from the Rust array,
-    // then use it to fill the currect buffer.
-    context.buffer_data_with_array_buffer_view(
-        WebGlRenderingContext::ARRAY_BUFFER,
-        &position_array,
-        WebGlRenderingContext::STATIC_DRAW,
-    );
+struct Buffers {
+    positions: BufferHandle,
+    normals: BufferHandle,
+    colors: BufferHandle,
+    indices: IndexBuffer,
+    bounds: Bounds,
+    edges: IndexBuffer,
+}
 
-    // Set up the color for the faces.
-    // In this case, solid colors for each face is used.
-    let color_buffer = context
-        .create_buffer()
-        .ok_or("failed to create color_buffer")?;
-    context.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&color_buffer));
-
-    let face_colors = [
-        [1.0, 1.0, 1.0, 1.0], // Front face: white
-        [1.0, 0.0, 0.0, 1.0], // Back face: red
-        [0.0, 1.0, 0.0, 1.0], // Top face: green
-        [0.0, 0.0, 1.0, 1.0], // Bottom face: blue
-        [1.0, 1.0, 0.0, 1.0], // Right face: yellow
-        [1.0, 0.0, 1.0, 1.0], // Left face: purple
-    ];
-    let color_array = {
-        let color_vec: Vec<f32> = face_colors
+/// The model shown in the scene: its mesh on the CPU, for exporting and
+/// ray casts, its buffers on the GPU and the transform fitting it to the
+/// camera. The cube until `Viewer::load_stl` replaces it.
+pub(crate) struct Model {
+    mesh: TriangleMesh,
+    /// One part, unless the mesh needed 32-bit indices the context lacks
+    /// and was split into pieces that fit 16-bit ones.
+    parts: Vec<Buffers>,
+    bvh: Bvh,
+    fit: math::Mat4,
+}
+
+/// Color of loaded meshes that have no vertex colors of their own.
+const MODEL_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+impl Model {
+    /// The cube, in a solid color per face.
+    fn cube(resources: &ResourceManager) -> Result<Model, JsValue> {
+        let face_colors = [
+            [1.0, 1.0, 1.0, 1.0], // Front face: white
+            [1.0, 0.0, 0.0, 1.0], // Back face: red
+            [0.0, 1.0, 0.0, 1.0], // Top face: green
+            [0.0, 0.0, 1.0, 1.0], // Bottom face: blue
+            [1.0, 1.0, 0.0, 1.0], // Right face: yellow
+            [1.0, 0.0, 1.0, 1.0], // Left face: purple
+        ];
+        let colors: Vec<f32> = face_colors
             .iter()
-            .map(|row| vec![row, row, row, row])
-            .flatten()
+            .flat_map(|row| vec![row, row, row, row])
             .flatten()
-            .map(|x| *x)
+            .copied()
             .collect();
-        let mut color_arr: [f32; 96] = [0f32; 96];
-        color_arr.copy_from_slice(color_vec.as_slice());
-        float_32_array!(color_arr)
-    };
-   context.buffer_data_with_array_buffer_view(
+        let mut mesh = TriangleMesh {
+            positions: CUBE_POSITIONS.to_vec(),
+            indices: CUBE_INDICES.to_vec(),
+            ..TriangleMesh::default()
+        };
+        // No vertex is shared between faces, so every face stays flat.
+        mesh.recompute_normals();
+        Model::new(resources, mesh, &colors, mat4::new_identity())
+    }
+
+    /// `mesh` centered on the origin and scaled to the cube's size, so any
+    /// model fills the view the same way. Drawn in its vertex colors, or
+    /// gray without any.
+    pub(crate) fn fitted(
+        resources: &ResourceManager,
+        mut mesh: TriangleMesh,
+    ) -> Result<Model, JsValue> {
+        mesh.validate()?;
+        if mesh.triangle_count() == 0 {
+            return Err("the mesh has no triangles to show".into());
+        }
+        if mesh.normals.is_empty() {
+            mesh.recompute_normals();
+        }
+        let colors: Vec<f32> = if mesh.colors.is_empty() {
+            MODEL_COLOR.iter().copied().cycle().take(mesh.vertex_count() * 4).collect()
+        } else {
+            mesh.colors.iter().map(|&channel| channel as f32 / 255.0).collect()
+        };
+
+        // The sphere around the cube's corners has a radius of √3.
+        let sphere = Bounds::from_positions(&mesh.positions).sphere;
+        let scale = 3f32.sqrt() / sphere.radius.max(f32::EPSILON);
+        let mut fit = mat4::new_identity();
+        let unscaled = fit;
+        mat4::scale(&mut fit, &unscaled, &[scale, scale, scale]);
+        let untranslated = fit;
+        let center = sphere.center;
+        mat4::translate(&mut fit, &untranslated, &[-center[0], -center[1], -center[2]]);
+
+        Model::new(resources, mesh, &colors, fit)
+    }
+
+    fn new(
+        resources: &ResourceManager,
+        mesh: TriangleMesh,
+        colors: &[f32],
+        fit: math::Mat4,
+    ) -> Result<Model, JsValue> {
+        let parts = upload_buffers(resources, &mesh, colors)?;
+        let bvh = Bvh::build(&mesh.positions, &mesh.indices)?;
+        Ok(Model {
+            mesh,
+            parts,
+            bvh,
+            fit,
+        })
+    }
+
+    /// The mesh as given, before fitting it to the camera.
+    pub(crate) fn mesh(&self) -> &TriangleMesh {
+        &self.mesh
+    }
+
+    /// Fitted to the camera, then spun around its center.
+    fn model_matrix(&self, theta: f32, phi: f32) -> math::Mat4 {
+        math::mul(&cube_model_matrix(theta, phi), &self.fit)
+    }
+}
+
+const CUBE_POSITIONS: [f32; 72] = [
+    // Front face
+    -1.0, -1.0, 1.0, //
+    1.0, -1.0, 1.0, //
+    1.0, 1.0, 1.0, //
+    -1.0, 1.0, 1.0, //
+    // Back face
+    -1.0, -1.0, -1.0, //
+    -1.0, 1.0, -1.0, //
+    1.0, 1.0, -1.0, //
+    1.0, -1.0, -1.0, //
+    // Top face
+    -1.0, 1.0, -1.0, //
+    -1.0, 1.0, 1.0, //
+    1.0, 1.0, 1.0, //
+    1.0, 1.0, -1.0, //
+    // Bottom face
+    -1.0, -1.0, -1.0, //
+    1.0, -1.0, -1.0, //
+    1.0, -1.0, 1.0, //
+    -1.0, -1.0, 1.0, //
+    // Right face
+    1.0, -1.0, -1.0, //
+    1.0, 1.0, -1.0, //
+    1.0, 1.0, 1.0, //
+    1.0, -1.0, 1.0, //
+    // Left face
+    -1.0, -1.0, -1.0, //
+    -1.0, -1.0, 1.0, //
+    -1.0, 1.0, 1.0, //
+    -1.0, 1.0, -1.0, //
+];
+
+/// Indices into `CUBE_POSITIONS`, each face as two triangles.
+const CUBE_INDICES: [u32; 36] = [
+    0, 1, 2, 0, 2, 3, // front
+    4, 5, 6, 4, 6, 7, // back
+    8, 9, 10, 8, 10, 11, // top
+    12, 13, 14, 12, 14, 15, // bottom
+    16, 17, 18, 16, 18, 19, // right
+    20, 21, 22, 20, 22, 23, // left
+];
+
+/// Upload `mesh` in one part, or, if it needs 32-bit indices and the
+/// context has no `OES_element_index_uint`, in as many parts as it takes
+/// for each to be drawn with 16-bit indices.
+fn upload_buffers(
+    resources: &ResourceManager,
+    mesh: &TriangleMesh,
+    colors: &[f32],
+) -> Result<Vec<Buffers>, JsValue> {
+    let max_index = mesh.indices.iter().copied().max().unwrap_or(0);
+    if IndexType::for_max_index(max_index) == IndexType::U32
+        && !supports_u32_indices(resources.context())
+    {
+        // Vertices shared across the seams are duplicated into each part.
+        return split_mesh(&mesh.indices, MAX_U16_VERTICES)?
+            .iter()
+            .map(|sub_mesh| {
+                let indices: Vec<u32> =
+                    sub_mesh.indices.iter().map(|&index| index.into()).collect();
+                upload_part(
+                    resources,
+                    &sub_mesh.gather(&mesh.positions, 3),
+                    &sub_mesh.gather(&mesh.normals, 3),
+                    &sub_mesh.gather(colors, 4),
+                    IndexBuffer::from_u16(resources, &sub_mesh.indices)?,
+                    &indices,
+                )
+            })
+            .collect();
+    }
+
+    // The index type follows the mesh size: the cube's 24 vertices fit in
+    // a byte.
+    let index_buffer = IndexBuffer::new(resources, &mesh.indices)?;
+    Ok(vec![upload_part(
+        resources,
+        &mesh.positions,
+        &mesh.normals,
+        colors,
+        index_buffer,
+        &mesh.indices,
+    )?])
+}
+
+/// Upload the vertex attributes of one part of a mesh, next to its
+/// already uploaded `index_buffer` holding `indices`.
+fn upload_part(
+    resources: &ResourceManager,
+    positions: &[f32],
+    normals: &[f32],
+    colors: &[f32],
+    index_buffer: IndexBuffer,
+    indices: &[u32],
+) -> Result<Buffers, JsValue> {
+    // Create a buffer for the vertex positions and pass the list of
+    // positions into WebGL to build the shape.
+    let position_buffer = resources.create_buffer(
         WebGlRenderingContext::ARRAY_BUFFER,
-        &color_array,
+        positions,
         WebGlRenderingContext::STATIC_DRAW,
-    );
+    )?;
 
-    // Build the element array buffer; this specifies the indices
-    // into the vertex arrays for each face's vertices.
-    let index_buffer = context
-        .create_buffer()
-        .ok_or("failed to create index_buffer buffer")?;
-    context.bind_buffer(
-        WebGlRenderingContext::ELEMENT_ARRAY_BUFFER,
-        Some(&index_buffer),
-    );
-    // Define each face as two triangles, using the indies into the vertex array
-    // to specify each triangle's position.
-    let indices: [u16; 36] = [
-        0, 1, 2, 0, 2, 3, // front
-        4, 5, 6, 4, 6, 7, // back
-        8, 9, 10, 8, 10, 11, // top
-        12, 13, 14, 12, 14, 15, // bottom
-        16, 17, 18, 16, 18, 19, // right
-        20, 21, 22, 20, 22, 23, // left
-    ];
-    let index_array = uint_16_array!(indices);
-    context.buffer_data_with_array_buffer_view(
-        WebGlRenderingContext::ELEMENT_ARRAY_BUFFER,
-        &index_array,
+    // Normals for lighting, one per vertex.
+    let normal_buffer = resources.create_buffer(
+        WebGlRenderingContext::ARRAY_BUFFER,
+        normals,
         WebGlRenderingContext::STATIC_DRAW,
-    );
+    )?;
+
+    // RGBA colors, one per vertex.
+    let color_buffer = resources.create_buffer(
+        WebGlRenderingContext::ARRAY_BUFFER,
+        colors,
+        WebGlRenderingContext::STATIC_DRAW,
+    )?;
+
+    // Bounds are measured once here rather than every time the mesh is
+    // culled.
+    let bounds = Bounds::from_positions(positions);
+
+    // Every edge of the triangles once, for inspecting the topology.
+    let edges = wireframe_edges(indices)?;
+    let edge_buffer = IndexBuffer::with_primitive(resources, &edges, PrimitiveMode::Lines)?;
+
+    Ok(Buffers {
+        positions: position_buffer,
+        normals: normal_buffer,
+        colors: color_buffer,
+        indices: index_buffer,
+        bounds,
+        edges: edge_buffer,
+    })
+}
+
+/// Half the width of the ground square.
+const GROUND_EXTENT: f32 = 3.0;
+
+/// Height of the ground, just below the spinning cube's corners at √3.
+const GROUND_HEIGHT: f32 = -2.0;
+
+const GROUND_COLOR: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+
+/// A square under the model, facing up, that catches its shadow.
+fn ground_buffers(resources: &ResourceManager) -> Result<Buffers, JsValue> {
+    let (e, y) = (GROUND_EXTENT, GROUND_HEIGHT);
+    let mesh = TriangleMesh {
+        positions: vec![-e, y, -e, -e, y, e, e, y, e, e, y, -e],
+        normals: [0.0, 1.0, 0.0].iter().copied().cycle().take(12).collect(),
+        indices: vec![0, 1, 2, 0, 2, 3],
+        ..TriangleMesh::default()
+    };
+    let colors: Vec<f32> = GROUND_COLOR.iter().copied().cycle().take(16).collect();
+    let index_buffer = IndexBuffer::new(resources, &mesh.indices)?;
+    upload_part(
+        resources,
+        &mesh.positions,
+        &mesh.normals,
+        &colors,
+        index_buffer,
+        &mesh.indices,
+    )
+}
 
-    Ok(Buffers(position_buffer, color_buffer, index_buffer))
+/// What the model and the ground are drawn with: lit, and lit and
+/// shadowed while the shadow map is enabled. Rebuilt along with the
+/// shadow map after a context restore, since the receiver's defines follow
+/// how the map stores depth.
+struct SceneMaterials {
+    lit: Material,
+    shadowed: Material,
 }
 
+impl SceneMaterials {
+    fn new(
+        context: &WebGlRenderingContext,
+        cache: &mut ProgramCache,
+        shadow_map: &ShadowMap,
+    ) -> Result<SceneMaterials, JsValue> {
+        Ok(SceneMaterials {
+            lit: Material::lit(context, cache)?,
+            shadowed: shadow_map.lit_material(context, cache)?,
+        })
+    }
+}
 
-#[allow(dead_code)]
+#[allow(clippy::too_many_arguments)]
 fn draw_scene(
-    gl: &WebGlRenderingContext,
-    programInfo: ProgramInfo,
-    buffers: Buffers,
+    state: &GlState,
+    target: Option<&RenderTarget>,
+    mut shadow: Option<&mut ShadowMap>,
+    materials: &SceneMaterials,
+    model: &Model,
+    ground: &Buffers,
+    route: &Route,
+    scan: &mut Scan,
     theta: f32,
     phi: f32,
+    wireframe: bool,
+    stats: &mut CullStats,
 ) -> Result<(), JsValue> {
-    use std::f32::consts::PI;
-    let Buffers(positionBuffer, colorBuffer, indexBuffer) = buffers;
-    let ProgramInfo(
-        shaderProgram,
-        (vertexPosition, vertexColor),
-        (location_projectionMatrix, location_modelViewMatrix),
-    ) = programInfo;
-    gl.clear_color(0.0, 0.0, 0.0, 1.0); // Clear to black, fully opaque
-    gl.clear_depth(1.0); // Clear everything
-    gl.enable(WebGlRenderingContext::DEPTH_TEST); // Enable depth testing
-                                                  // gl.depth_func(WebGlRenderingContext::LEQUAL); // Near things obscure far things
+    let gl = state.context();
+    let model_matrix = model.model_matrix(theta, phi);
+
+    // Render the depth of the scene from the light first, so the main
+    // pass can look up what is in shadow.
+    if let Some(shadow) = shadow.as_deref_mut() {
+        // Cloned, as the map is needed again while its shader is borrowed.
+        let depth_material = shadow.begin(state).clone();
+        let depth_shader = depth_material.shader();
+        // Casters outside the light's view cannot shadow anything. The
+        // ground only receives. Only the main pass counts towards `stats`,
+        // so each mesh is tested there once per frame.
+        let light_matrix = math::mul(shadow.view_projection(), &model_matrix);
+        for buffers in model.parts.iter() {
+            if is_visible(&light_matrix, &buffers.bounds) {
+                bind_attribute(state, &depth_shader, "position", &buffers.positions, 3)?;
+                shadow.draw_caster(gl, &model_matrix);
+                buffers.indices.draw(state);
+            }
+        }
+        shadow.end(gl);
+    }
+
+    // Draw into the offscreen target if one is given, otherwise onto the
+    // canvas. Either way the viewport covers the whole destination.
+    let (width, height) = match target {
+        Some(target) => {
+            target.bind(gl);
+            // Binding set the viewport; keep the cache in step.
+            state.viewport(0, 0, target.width(), target.height());
+            (target.width(), target.height())
+        }
+        None => {
+            let canvas: web_sys::HtmlCanvasElement = gl
+                .canvas()
+                .unwrap()
+                .dyn_into::<web_sys::HtmlCanvasElement>()?;
+            RenderTarget::unbind(gl);
+            state.viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
+            (canvas.width() as i32, canvas.height() as i32)
+        }
+    };
+
+    state.clear_color(0.0, 0.0, 0.0, 1.0); // Clear to black, fully opaque
+    state.clear_depth(1.0); // Clear everything
+    // Depth testing, blending and culling come with each material's
+    // render state.
 
     // Clear the canvas before we start drawing on it.
 
     gl.clear(WebGlRenderingContext::COLOR_BUFFER_BIT | WebGlRenderingContext::DEPTH_BUFFER_BIT);
-    // Create a perspective matrix, a special matrix that is
-    // used to simulate the distortion of perspective in a camera.
-    // Our field of view is 45 degrees, with a width/height
-    // ratio that matches the display size of the canvas
-    // and we only want to see objects between 0.1 units
-    // and 100 units away from the camera.
-
-    let fieldOfView = 45.0 * PI / 180.0; // in radians
-    let canvas: web_sys::HtmlCanvasElement = gl
-        .canvas()
-        .unwrap()
-        .dyn_into::<web_sys::HtmlCanvasElement>()?;
-    gl.viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
-    let aspect: f32 = canvas.width() as f32 / canvas.height() as f32;
-    let zNear = 1.0;
-    let zFar = 100.0;
-    let mut projectionMatrix = mat4::new_zero();
+    let projection_matrix = scene_projection_matrix(width, height);
+    let view_matrix = scene_view_matrix();
 
-    mat4::perspective(&mut projectionMatrix, &fieldOfView, &aspect, &zNear, &zFar);
+    // Shadows are only looked up while the map was rendered this frame.
+    let material = if shadow.is_some() {
+        &materials.shadowed
+    } else {
+        &materials.lit
+    };
 
-    // Set the drawing position to the "identity" point, which is
-    // the center of the scene.
-    let mut modelViewMatrix = mat4::new_identity();
+    // Collect the visible meshes and let the queue order them so program
+    // and buffer changes are only made when needed.
+    let mut queue = RenderQueue::new();
+    let parts = model.parts.iter().map(|buffers| (buffers, model_matrix));
+    for (buffers, model_matrix) in parts.chain([(ground, mat4::new_identity())]) {
+        let model_view_matrix = math::mul(&view_matrix, &model_matrix);
+        let bounds = &buffers.bounds;
+        if stats.record(is_visible(&math::mul(&projection_matrix, &model_view_matrix), bounds)) {
+            queue.push(DrawItem {
+                key: DrawKey::new(material, &buffers.positions),
+                depth: view_depth(&model_view_matrix, &bounds.sphere.center),
+                transparent: material.render_state().is_transparent(),
+                payload: SceneDraw {
+                    material,
+                    buffers,
+                    model_matrix,
+                    wireframe,
+                },
+            });
+        }
+    }
 
-    // Now move the drawing position a bit to where we want to
-    // start drawing the square.
-    let mat_to_translate = modelViewMatrix.clone();
-    mat4::translate(
-        &mut modelViewMatrix, // destination matrix
-        &mat_to_translate,    // matrix to translate
-        &[-0.0, 0.0, -6.0],
-    ); // amount to translate
+    let shadow = shadow.as_deref();
+    queue.render(state, |item, changes| {
+        let SceneDraw {
+            material,
+            buffers,
+            model_matrix,
+            wireframe,
+        } = item.payload;
+        // Tell WebGL to use the material's program and upload its
+        // parameters and render state.
+        let shader = material.shader();
+        if changes.program || changes.material {
+            state.use_program(Some(shader.program()));
+            material.upload_params(gl);
+            material.render_state().apply(state);
+        }
+        if let Some(shadow) = shadow {
+            shadow.bind_receiver(gl, &shader, &model_matrix);
+        }
 
-    let mat_to_rotate = modelViewMatrix.clone();
-    mat4::rotate_x(
-        &mut modelViewMatrix, // destination matrix
-        &mat_to_rotate,       // matrix to rotate
-        &phi,
-    );
-    let mat_to_rotate = modelViewMatrix.clone();
-    mat4::rotate_y(
-        &mut modelViewMatrix, // destination matrix
-        &mat_to_rotate,       // matrix to rotate
-        &theta,
-    );
+        // Attribute pointers belong to the program's locations, so they
+        // are set again whenever either changes.
+        if changes.program || changes.buffer {
+            bind_attribute(state, &shader, "position", &buffers.positions, 3)?;
+            bind_attribute(state, &shader, "normal", &buffers.normals, 3)?;
+            bind_attribute(state, &shader, "color", &buffers.colors, 4)?;
+        }
 
-    // Tell WebGL how to pull out the positions from the position
-    // buffer into the vertexPosition attribute
-    {
-        let numComponents = 3;
-        let type_ = WebGlRenderingContext::FLOAT;
-        let normalize = false;
-        let stride = 0;
-        let offset = 0;
-        gl.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&positionBuffer));
+        // Set the shader uniforms shared by every material
+        let model_view_matrix = math::mul(&view_matrix, &model_matrix);
+        gl.uniform_matrix4fv_with_f32_array(
+            shader.uniform_location("projection_matrix"),
+            false,
+            &projection_matrix,
+        );
+        gl.uniform_matrix4fv_with_f32_array(
+            shader.uniform_location("model_view_matrix"),
+            false,
+            &model_view_matrix,
+        );
+        // Count, index type and primitive come from the uploaded buffer.
+        if wireframe {
+            buffers.edges.draw(state);
+        } else {
+            buffers.indices.draw(state);
+        }
+        Ok(())
+    })?;
 
-        gl.vertex_attrib_pointer_with_i32(
-            vertexPosition,
-            numComponents,
-            type_,
-            normalize,
-            stride,
-            offset,
+    scan.draw(state, &projection_matrix, &view_matrix, width, height)?;
+
+    // The route goes over the opaque scene, hidden where it passes
+    // behind the cube.
+    route.draw(state, &projection_matrix, &view_matrix, width, height);
+
+    Ok(())
+}
+
+/// Color the route overlay is drawn in.
+const ROUTE_COLOR: [f32; 4] = [1.0, 0.6, 0.1, 1.0];
+
+/// A path through the scene drawn as a thick line, in world space.
+pub(crate) struct Route {
+    material: Material,
+    mesh: LineMesh,
+}
+
+impl Route {
+    fn new(
+        context: &WebGlRenderingContext,
+        cache: &mut ProgramCache,
+        resources: &ResourceManager,
+    ) -> Result<Route, JsValue> {
+        Ok(Route {
+            material: Material::lines(context, cache)?,
+            mesh: LineMesh::new(resources, &Polylines::new())?,
+        })
+    }
+
+    /// Replace the route with one through `points`, given as xyz triples.
+    /// Fewer than two points remove it.
+    pub(crate) fn set_points(
+        &mut self,
+        resources: &ResourceManager,
+        points: &[f32],
+    ) -> Result<(), JsValue> {
+        if !points.len().is_multiple_of(3) {
+            return Err(format!("{} coordinates are not whole xyz points", points.len()).into());
+        }
+        let points: Vec<math::Vec3> = points
+            .chunks_exact(3)
+            .map(|point| [point[0], point[1], point[2]])
+            .collect();
+        let mut lines = Polylines::new();
+        if points.len() >= 2 {
+            let style = LineStyle {
+                join: LineJoin::Round,
+                cap: LineCap::Round,
+            };
+            lines.push(&points, ROUTE_COLOR, style)?;
+        }
+        self.mesh.set_lines(resources, &lines)
+    }
+
+    pub(crate) fn set_width(&mut self, width: f32) {
+        self.material.set_param("line_width", MaterialParam::Float(width));
+    }
+
+    fn draw(
+        &self,
+        state: &GlState,
+        projection_matrix: &math::Mat4,
+        view_matrix: &math::Mat4,
+        width: i32,
+        height: i32,
+    ) {
+        if self.mesh.is_empty() {
+            return;
+        }
+        let gl = state.context();
+        let shader = self.material.shader();
+        state.use_program(Some(shader.program()));
+        self.material.upload_params(gl);
+        self.material.render_state().apply(state);
+        gl.uniform_matrix4fv_with_f32_array(
+            shader.uniform_location("projection_matrix"),
+            false,
+            projection_matrix,
+        );
+        gl.uniform_matrix4fv_with_f32_array(
+            shader.uniform_location("model_view_matrix"),
+            false,
+            view_matrix,
         );
-        gl.enable_vertex_attrib_array(vertexPosition);
-        // gl.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, None);
+        self.mesh.draw(state, &shader, [width as f32, height as f32]);
+        RenderState::reset(state);
     }
-    // Tell WebGL how to pull out the colors from the color buffer
-    // into the vertexColor attribute.
-    {
-        let numComponents = 4;
-        let type_ = WebGlRenderingContext::FLOAT;
-        let normalize = false;
-        let stride = 0;
-        let offset = 0;
-        gl.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&colorBuffer));
-        gl.vertex_attrib_pointer_with_i32(
-            vertexColor,
-            numComponents,
-            type_,
-            normalize,
-            stride,
-            offset,
+}
+
+/// A point cloud shown in the scene, in world space, drawn within a point
+/// budget from an octree.
+pub(crate) struct Scan {
+    material: Material,
+    mesh: Option<PointCloudMesh>,
+    resources: ResourceManager,
+    point_budget: usize,
+}
+
+impl Scan {
+    fn new(
+        context: &WebGlRenderingContext,
+        cache: &mut ProgramCache,
+        resources: &ResourceManager,
+    ) -> Result<Scan, JsValue> {
+        Ok(Scan {
+            material: Material::points(context, cache)?,
+            mesh: None,
+            resources: resources.clone(),
+            point_budget: DEFAULT_POINT_BUDGET,
+        })
+    }
+
+    /// Replace the cloud. Its octree is built here, once.
+    pub(crate) fn set_cloud(&mut self, cloud: &PointCloud) -> Result<(), JsValue> {
+        let octree = PointOctree::build(cloud, OctreeSettings::default())?;
+        let mut mesh = PointCloudMesh::new(octree);
+        mesh.set_point_budget(self.point_budget);
+        self.mesh = Some(mesh);
+        Ok(())
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.mesh = None;
+    }
+
+    pub(crate) fn set_point_budget(&mut self, budget: usize) {
+        self.point_budget = budget;
+        if let Some(mesh) = &mut self.mesh {
+            mesh.set_point_budget(budget);
+        }
+    }
+
+    pub(crate) fn set_point_size(&mut self, size: f32) {
+        self.material.set_param("point_size", MaterialParam::Float(size));
+    }
+
+    pub(crate) fn stats(&self) -> PointCloudStats {
+        self.mesh.as_ref().map(PointCloudMesh::stats).unwrap_or_default()
+    }
+
+    /// Stream in what the camera sees and draw it.
+    fn draw(
+        &mut self,
+        state: &GlState,
+        projection_matrix: &math::Mat4,
+        view_matrix: &math::Mat4,
+        width: i32,
+        height: i32,
+    ) -> Result<(), JsValue> {
+        let mesh = match &mut self.mesh {
+            Some(mesh) => mesh,
+            None => return Ok(()),
+        };
+        let frustum = match Frustum::from_matrix(&math::mul(projection_matrix, view_matrix)) {
+            Some(frustum) => frustum,
+            None => return Ok(()),
+        };
+        let eye = math::invert(view_matrix)
+            .map(|camera| math::transform_point(&camera, &[0.0, 0.0, 0.0]))
+            .unwrap_or_default();
+        mesh.update(&self.resources, &frustum, &eye)?;
+
+        let gl = state.context();
+        let shader = self.material.shader();
+        state.use_program(Some(shader.program()));
+        self.material.upload_params(gl);
+        self.material.render_state().apply(state);
+        gl.uniform_matrix4fv_with_f32_array(
+            shader.uniform_location("projection_matrix"),
+            false,
+            projection_matrix,
+        );
+        gl.uniform_matrix4fv_with_f32_array(
+            shader.uniform_location("model_view_matrix"),
+            false,
+            view_matrix,
         );
-        gl.enable_vertex_attrib_array(vertexColor);
+        mesh.draw(state, &shader, [width as f32, height as f32]);
+        RenderState::reset(state);
+        Ok(())
+    }
+}
+
+/// One mesh queued for the main pass.
+#[derive(Clone, Copy)]
+struct SceneDraw<'a> {
+    material: &'a Material,
+    buffers: &'a Buffers,
+    model_matrix: math::Mat4,
+    /// Draw the edges rather than the faces.
+    wireframe: bool,
+}
 
-        // gl.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, None);
+/// Point the attribute `name` of `shader`, if it has one, at `buffer`,
+/// holding tightly packed floats with `components` per vertex.
+fn bind_attribute(
+    state: &GlState,
+    shader: &shader::Shader,
+    name: &str,
+    buffer: &BufferHandle,
+    components: i32,
+) -> Result<(), JsValue> {
+    if let Some(location) = shader.attrib_location(name) {
+        let buffer = buffer
+            .get()
+            .ok_or_else(|| format!("the buffer for \"{}\" has been deleted", name))?;
+        state.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&buffer));
+        let gl = state.context();
+        gl.vertex_attrib_pointer_with_i32(
+            location,
+            components,
+            WebGlRenderingContext::FLOAT,
+            false,
+            0,
+            0,
+        );
+        gl.enable_vertex_attrib_array(location);
     }
+    Ok(())
+}
+
+/// Whether a mesh with `bounds` may show up under `matrix`, its
+/// projection × model-view. Degenerate matrices cull nothing.
+fn is_visible(matrix: &math::Mat4, bounds: &Bounds) -> bool {
+    Frustum::from_matrix(matrix).is_none_or(|frustum| frustum.intersects(bounds))
+}
 
-    // Tell WebGL which indices to use to index the vertices
-    gl.bind_buffer(
-        WebGlRenderingContext::ELEMENT_ARRAY_BUFFER,
-        Some(&indexBuffer),
+/// Node ID of the model in the picking pass.
+const MODEL_NODE_ID: u32 = 1;
+
+/// The model matrix spins the model around its center.
+fn cube_model_matrix(theta: f32, phi: f32) -> math::Mat4 {
+    let mut model_matrix = mat4::new_identity();
+    let mat_to_rotate = model_matrix;
+    mat4::rotate_x(
+        &mut model_matrix, // destination matrix
+        &mat_to_rotate,    // matrix to rotate
+        &phi,
     );
+    let mat_to_rotate = model_matrix;
+    mat4::rotate_y(
+        &mut model_matrix, // destination matrix
+        &mat_to_rotate,    // matrix to rotate
+        &theta,
+    );
+    model_matrix
+}
 
-    // Tell WebGL to use our program when drawing
+/// Create a perspective matrix, a special matrix that is
+/// used to simulate the distortion of perspective in a camera.
+/// Our field of view is 45 degrees, with a width/height
+/// ratio that matches the display size of the canvas
+/// and we only want to see objects between 1 unit
+/// and 100 units away from the camera.
+fn scene_projection_matrix(width: i32, height: i32) -> math::Mat4 {
+    let field_of_view = 45.0 * PI / 180.0; // in radians
+    let aspect: f32 = width as f32 / height as f32;
+    let z_near = 1.0;
+    let z_far = 100.0;
+    let mut projection_matrix = mat4::new_zero();
 
-    gl.use_program(Some(&shaderProgram));
+    mat4::perspective(&mut projection_matrix, &field_of_view, &aspect, &z_near, &z_far);
+    projection_matrix
+}
+
+/// The view matrix moves the scene a bit away from the camera.
+fn scene_view_matrix() -> math::Mat4 {
+    // Set the drawing position to the "identity" point, which is
+    // the center of the scene.
+    let mut view_matrix = mat4::new_identity();
 
-    // Set the shader uniforms
+    // Now move the drawing position a bit to where we want to
+    // start drawing the square.
+    let mat_to_translate = view_matrix;
+    mat4::translate(
+        &mut view_matrix,  // destination matrix
+        &mat_to_translate, // matrix to translate
+        &[-0.0, 0.0, -6.0],
+    ); // amount to translate
+    view_matrix
+}
 
+/// Draw the scene into the picker's ID target and return the node under
+/// (`x`, `y`), given in drawing-buffer pixels from the top left.
+fn pick_scene(
+    state: &GlState,
+    picker: &mut Picker,
+    model: &Model,
+    theta: f32,
+    phi: f32,
+    x: i32,
+    y: i32,
+) -> Result<Option<u32>, JsValue> {
+    let gl = state.context();
+    // Cloned, as the picker is needed again while its shader is borrowed.
+    let material = picker.begin(gl)?.clone();
+    let shader = material.shader();
+    let (width, height) = (gl.drawing_buffer_width(), gl.drawing_buffer_height());
+    let projection_matrix = scene_projection_matrix(width, height);
+    let model_view_matrix = math::mul(&scene_view_matrix(), &model.model_matrix(theta, phi));
     gl.uniform_matrix4fv_with_f32_array(
-        Some(&location_projectionMatrix?),
+        shader.uniform_location("projection_matrix"),
         false,
-        &projectionMatrix,
+        &projection_matrix,
     );
-    gl.uniform_matrix4fv_with_f32_array(Some(&location_modelViewMatrix?), false, &modelViewMatrix);
-    {
-        let vertexCount = 36;
-        let type_ = WebGlRenderingContext::UNSIGNED_SHORT;
-        let offset = 0;
-        gl.draw_elements_with_i32(WebGlRenderingContext::TRIANGLES, vertexCount, type_, offset);
+    gl.uniform_matrix4fv_with_f32_array(
+        shader.uniform_location("model_view_matrix"),
+        false,
+        &model_view_matrix,
+    );
+    picker.draw_node(gl, MODEL_NODE_ID)?;
+
+    let clip_matrix = math::mul(&projection_matrix, &model_view_matrix);
+    for buffers in model.parts.iter() {
+        if is_visible(&clip_matrix, &buffers.bounds) {
+            bind_attribute(state, &shader, "position", &buffers.positions, 3)?;
+            buffers.indices.draw(state);
+        }
     }
+    picker.read(gl, x, y)
+}
 
-    Ok(())
+/// Cast a ray through (`x`, `y`), in drawing-buffer pixels from the top
+/// left, and return the world-space hit point and normal on the model.
+fn raycast_scene(
+    model: &Model,
+    theta: f32,
+    phi: f32,
+    width: i32,
+    height: i32,
+    x: f32,
+    y: f32,
+) -> Option<(math::Vec3, math::Vec3, f32)> {
+    let ray = Ray::from_screen(
+        x,
+        y,
+        width as f32,
+        height as f32,
+        &scene_projection_matrix(width, height),
+        &scene_view_matrix(),
+    )?;
+    // Fitting scales the model, so the distance is measured again in world
+    // space rather than carried over from the model's own.
+    let model_matrix = model.model_matrix(theta, phi);
+    let hit = model.bvh.raycast(&ray.transform(&math::invert(&model_matrix)?))?;
+    let point = math::transform_point(&model_matrix, &hit.point);
+    Some((
+        point,
+        math::normalize(&math::transform_direction(&model_matrix, &hit.normal)),
+        math::length(&math::sub(&point, &ray.origin)),
+    ))
 }
 
-pub fn request_animation_frame(f: &Closure<FnMut(f32)>) {
+pub fn request_animation_frame(f: &Closure<dyn FnMut(f32)>) {
     window()
         .request_animation_frame(f.as_ref().unchecked_ref())
         .expect("should register `requestAnimationFrame` OK");
@@ -0,0 +1,206 @@
+mod params;
+mod program_cache;
+mod render_state;
+
+use std::{
+    cell::{Ref, RefCell},
+    rc::Rc,
+};
+
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::renderer::TextureHandle;
+use crate::shader::{builtin, Preprocessor, Shader};
+
+pub use self::params::MaterialParam;
+pub use self::program_cache::ProgramCache;
+pub use self::render_state::{BlendMode, CullFace, RenderState};
+
+/// A shader program plus the parameter values and render state to draw
+/// with it.
+///
+/// Several materials may point at the same program (see `ProgramCache`)
+/// while carrying different parameters.
+#[derive(Debug, Clone)]
+pub struct Material {
+    shader: Rc<RefCell<Shader>>,
+    params: Vec<(String, MaterialParam)>,
+    render_state: RenderState,
+}
+
+impl Material {
+    pub fn new(
+        context: &GL,
+        cache: &mut ProgramCache,
+        vert_shader: &str,
+        frag_shader: &str,
+    ) -> Result<Material, JsValue> {
+        Ok(Material {
+            shader: cache.get_or_build(context, vert_shader, frag_shader)?,
+            params: Vec::new(),
+            render_state: RenderState::default(),
+        })
+    }
+
+    /// Like `new`, and register the program as `name` so it can be
+    /// replaced later with `ProgramCache::reload`.
+    pub fn named(
+        context: &GL,
+        cache: &mut ProgramCache,
+        name: &str,
+        vert_shader: &str,
+        frag_shader: &str,
+    ) -> Result<Material, JsValue> {
+        Ok(Material {
+            shader: cache.get_or_build_named(context, name, vert_shader, frag_shader)?,
+            params: Vec::new(),
+            render_state: RenderState::default(),
+        })
+    }
+
+    /// Like `new`, but with the sources expanded by `preprocessor` for the
+    /// feature permutation given in `defines`.
+    pub fn variant(
+        context: &GL,
+        cache: &mut ProgramCache,
+        preprocessor: &Preprocessor,
+        vert_shader: &str,
+        frag_shader: &str,
+        defines: &[(&str, &str)],
+    ) -> Result<Material, JsValue> {
+        Ok(Material {
+            shader: cache.get_or_build_variant(
+                context,
+                preprocessor,
+                vert_shader,
+                frag_shader,
+                defines,
+            )?,
+            params: Vec::new(),
+            render_state: RenderState::default(),
+        })
+    }
+
+    /// Vertex colors only. Registered as "unlit".
+    pub fn unlit(context: &GL, cache: &mut ProgramCache) -> Result<Material, JsValue> {
+        Material::named(context, cache, "unlit", builtin::UNLIT_VERT, builtin::UNLIT_FRAG)
+    }
+
+    /// Per-instance transforms and colors, for `InstancedMesh`. Shares
+    /// the unlit fragment shader. Registered as "instanced".
+    pub fn instanced(context: &GL, cache: &mut ProgramCache) -> Result<Material, JsValue> {
+        Material::named(
+            context,
+            cache,
+            "instanced",
+            builtin::INSTANCED_VERT,
+            builtin::UNLIT_FRAG,
+        )
+    }
+
+    /// Vertex colors lit by one directional light; needs a `normal` attribute.
+    /// Registered as "lit".
+    pub fn lit(context: &GL, cache: &mut ProgramCache) -> Result<Material, JsValue> {
+        let mut material =
+            Material::named(context, cache, "lit", builtin::LIT_VERT, builtin::LIT_FRAG)?;
+        material.set_param("light_direction", MaterialParam::Vec3([-0.5, -1.0, -0.75]));
+        material.set_param("ambient_color", MaterialParam::Vec3([0.2, 0.2, 0.2]));
+        Ok(material)
+    }
+
+    /// Screen-space thick lines for a `LineMesh`, `line_width` pixels wide
+    /// with miters up to `miter_limit` times that. Blended, for the
+    /// anti-aliased edges, and drawn from both sides. Registered as "lines".
+    pub fn lines(context: &GL, cache: &mut ProgramCache) -> Result<Material, JsValue> {
+        let mut material =
+            Material::named(context, cache, "lines", builtin::LINE_VERT, builtin::LINE_FRAG)?;
+        material.set_param("line_width", MaterialParam::Float(2.0));
+        material.set_param("miter_limit", MaterialParam::Float(4.0));
+        material.set_render_state(RenderState::transparent().double_sided());
+        Ok(material)
+    }
+
+    /// Round, per-point colored points for a `PointCloudMesh`, sized
+    /// `point_size` world units. Registered as "points".
+    pub fn points(context: &GL, cache: &mut ProgramCache) -> Result<Material, JsValue> {
+        let mut material =
+            Material::named(context, cache, "points", builtin::POINTS_VERT, builtin::POINTS_FRAG)?;
+        material.set_param("point_size", MaterialParam::Float(0.05));
+        material.set_param("size_attenuation", MaterialParam::Float(1.0));
+        Ok(material)
+    }
+
+    /// A single texture; needs a `texcoord` attribute. Registered as "textured".
+    pub fn textured(
+        context: &GL,
+        cache: &mut ProgramCache,
+        texture: TextureHandle,
+    ) -> Result<Material, JsValue> {
+        let mut material = Material::named(
+            context,
+            cache,
+            "textured",
+            builtin::TEXTURED_VERT,
+            builtin::TEXTURED_FRAG,
+        )?;
+        material.set_param("diffuse_map", MaterialParam::Texture(texture));
+        material.set_param("tint", MaterialParam::Vec4([1.0, 1.0, 1.0, 1.0]));
+        Ok(material)
+    }
+
+    /// The program currently backing this material. Borrowed rather than
+    /// returned by reference because a reload may swap it out.
+    pub fn shader(&self) -> Ref<'_, Shader> {
+        self.shader.borrow()
+    }
+
+    /// Identifies the program behind this material. Materials sharing a
+    /// cached program share the ID, and it stays the same across reloads.
+    pub fn program_id(&self) -> usize {
+        Rc::as_ptr(&self.shader) as usize
+    }
+
+    pub fn param(&self, name: &str) -> Option<&MaterialParam> {
+        self.params
+            .iter()
+            .find(|(param_name, _)| param_name == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn set_param(&mut self, name: &str, value: MaterialParam) {
+        match self.params.iter_mut().find(|(param_name, _)| param_name == name) {
+            Some((_, param)) => *param = value,
+            None => self.params.push((name.to_string(), value)),
+        }
+    }
+
+    pub fn render_state(&self) -> &RenderState {
+        &self.render_state
+    }
+
+    /// Blending, culling and depth settings, applied by the renderer
+    /// before drawing with this material.
+    pub fn set_render_state(&mut self, render_state: RenderState) {
+        self.render_state = render_state;
+    }
+
+    /// Make this material's program current and upload its parameters.
+    /// Parameters the program does not use are skipped.
+    pub fn apply(&self, context: &GL) {
+        context.use_program(Some(self.shader().program()));
+        self.upload_params(context);
+    }
+
+    /// Upload the parameters to the program, which must be current. For
+    /// switching between materials that share a program.
+    pub fn upload_params(&self, context: &GL) {
+        let shader = self.shader();
+        let mut texture_unit = 0;
+        for (name, value) in self.params.iter() {
+            if let Some(location) = shader.uniform_location(name) {
+                value.upload(context, location, &mut texture_unit);
+            }
+        }
+    }
+}
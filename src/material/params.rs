@@ -0,0 +1,42 @@
+use web_sys::{WebGlRenderingContext as GL, WebGlUniformLocation};
+
+use crate::renderer::TextureHandle;
+
+/// A value a material feeds into one of its shader's uniforms.
+#[derive(Debug, Clone)]
+pub enum MaterialParam {
+    Int(i32),
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    Mat4([f32; 16]),
+    /// Bound to the next free texture unit when the material is applied.
+    /// The handle is resolved then, so a texture the `ResourceManager`
+    /// recreated after a context loss is picked up; a deleted one leaves
+    /// the unit empty.
+    Texture(TextureHandle),
+}
+
+impl MaterialParam {
+    /// Upload the value to `location`. Textures take the unit in
+    /// `texture_unit` and advance it for the next sampler.
+    pub fn upload(&self, context: &GL, location: &WebGlUniformLocation, texture_unit: &mut u32) {
+        match self {
+            MaterialParam::Int(v) => context.uniform1i(Some(location), *v),
+            MaterialParam::Float(v) => context.uniform1f(Some(location), *v),
+            MaterialParam::Vec2(v) => context.uniform2fv_with_f32_array(Some(location), v),
+            MaterialParam::Vec3(v) => context.uniform3fv_with_f32_array(Some(location), v),
+            MaterialParam::Vec4(v) => context.uniform4fv_with_f32_array(Some(location), v),
+            MaterialParam::Mat4(v) => {
+                context.uniform_matrix4fv_with_f32_array(Some(location), false, v)
+            }
+            MaterialParam::Texture(texture) => {
+                context.active_texture(GL::TEXTURE0 + *texture_unit);
+                context.bind_texture(GL::TEXTURE_2D, texture.get().as_ref());
+                context.uniform1i(Some(location), *texture_unit as i32);
+                *texture_unit += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+};
+
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::renderer::{ProgramHandle, ResourceManager};
+use crate::shader::{Preprocessor, Shader};
+
+type SourceKey = (String, String);
+
+/// Linked programs keyed by their vertex and fragment source, so materials
+/// built from the same shader pair share a single `WebGlProgram`.
+///
+/// Programs may also be registered under a name, which is what
+/// `reload` uses to find the program to replace.
+///
+/// Each program is adopted by the `ResourceManager`, so it shows up in
+/// its stats and is deleted once the cache lets go of it.
+#[derive(Debug)]
+pub struct ProgramCache {
+    resources: ResourceManager,
+    programs: SourceIndex<CachedProgram>,
+}
+
+#[derive(Debug)]
+struct CachedProgram {
+    shader: Rc<RefCell<Shader>>,
+    handle: ProgramHandle,
+}
+
+impl ProgramCache {
+    pub fn new(resources: &ResourceManager) -> ProgramCache {
+        ProgramCache {
+            resources: resources.clone(),
+            programs: SourceIndex::default(),
+        }
+    }
+
+    /// Return the cached program for this source pair, compiling and
+    /// linking it on first use.
+    pub fn get_or_build(
+        &mut self,
+        context: &GL,
+        vert_shader: &str,
+        frag_shader: &str,
+    ) -> Result<Rc<RefCell<Shader>>, JsValue> {
+        let key = (vert_shader.to_string(), frag_shader.to_string());
+        if let Some(cached) = self.programs.by_source.get(&key) {
+            return Ok(cached.shader.clone());
+        }
+        let shader = Shader::new(context, vert_shader, frag_shader)?;
+        let cached = CachedProgram {
+            handle: self.resources.adopt_program(shader.program().clone()),
+            shader: Rc::new(RefCell::new(shader)),
+        };
+        let shader = cached.shader.clone();
+        self.programs.by_source.insert(key, cached);
+        Ok(shader)
+    }
+
+    /// Like `get_or_build`, and also register the program as `name`.
+    pub fn get_or_build_named(
+        &mut self,
+        context: &GL,
+        name: &str,
+        vert_shader: &str,
+        frag_shader: &str,
+    ) -> Result<Rc<RefCell<Shader>>, JsValue> {
+        let shader = self.get_or_build(context, vert_shader, frag_shader)?;
+        self.programs.name(name, (vert_shader.to_string(), frag_shader.to_string()));
+        Ok(shader)
+    }
+
+    /// Run both sources through `preprocessor` with `defines` before
+    /// looking them up, so each distinct variant is linked once.
+    pub fn get_or_build_variant(
+        &mut self,
+        context: &GL,
+        preprocessor: &Preprocessor,
+        vert_shader: &str,
+        frag_shader: &str,
+        defines: &[(&str, &str)],
+    ) -> Result<Rc<RefCell<Shader>>, JsValue> {
+        let (vert_shader, frag_shader) =
+            preprocessor.process_pair(vert_shader, frag_shader, defines)?;
+        self.get_or_build(context, &vert_shader, &frag_shader)
+    }
+
+    /// Replace the program registered as `name` with one built from new
+    /// sources.
+    ///
+    /// The replacement is compiled and linked before anything is touched;
+    /// if that fails the error is returned and the old program stays in
+    /// use. On success every material sharing the program sees the new one,
+    /// with its attribute and uniform locations resolved again.
+    ///
+    /// Fails, changing nothing, if another cached program is already built
+    /// from the new sources, since the two cannot share one entry.
+    pub fn reload(
+        &mut self,
+        context: &GL,
+        name: &str,
+        vert_shader: &str,
+        frag_shader: &str,
+    ) -> Result<(), JsValue> {
+        let new_key = (vert_shader.to_string(), frag_shader.to_string());
+        let old_key = self.programs.reload_target(name, &new_key)?;
+        let replacement = Shader::new(context, vert_shader, frag_shader)?;
+
+        let cached = self.programs.rekey(&old_key, new_key);
+        // Dropping the old handle deletes the old program.
+        cached.handle = self.resources.adopt_program(replacement.program().clone());
+        cached.shader.replace(replacement);
+        Ok(())
+    }
+
+    /// Relink every cached program from its sources after the context
+    /// was lost and restored. Materials keep working since they share the
+    /// `Shader`s being replaced.
+    pub fn restore(&mut self, context: &GL) -> Result<(), JsValue> {
+        for ((vert_shader, frag_shader), cached) in self.programs.by_source.iter_mut() {
+            // The old program died with the context, and the manager has
+            // already forgotten it.
+            let shader = Shader::new(context, vert_shader, frag_shader)?;
+            cached.handle = self.resources.adopt_program(shader.program().clone());
+            cached.shader.replace(shader);
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.programs.by_source.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.programs.by_source.is_empty()
+    }
+}
+
+/// Programs by their source pair, and the names registered for them.
+/// Generic over the program so the bookkeeping can be tested without a
+/// context.
+#[derive(Debug)]
+struct SourceIndex<P> {
+    by_source: HashMap<SourceKey, P>,
+    names: HashMap<String, SourceKey>,
+}
+
+impl<P> Default for SourceIndex<P> {
+    fn default() -> SourceIndex<P> {
+        SourceIndex {
+            by_source: HashMap::new(),
+            names: HashMap::new(),
+        }
+    }
+}
+
+impl<P> SourceIndex<P> {
+    fn name(&mut self, name: &str, key: SourceKey) {
+        self.names.insert(name.to_string(), key);
+    }
+
+    /// The sources of the program registered as `name`, if it can be moved
+    /// to `new_key`: no other program may already be cached under it.
+    fn reload_target(&self, name: &str, new_key: &SourceKey) -> Result<SourceKey, String> {
+        let old_key = self
+            .names
+            .get(name)
+            .ok_or_else(|| format!("no shader named \"{}\"", name))?;
+        if !self.by_source.contains_key(old_key) {
+            return Err(format!("shader \"{}\" is not cached", name));
+        }
+        if old_key != new_key && self.by_source.contains_key(new_key) {
+            return Err(format!(
+                "cannot reload shader \"{}\": another cached program has the same sources",
+                name
+            ));
+        }
+        Ok(old_key.clone())
+    }
+
+    /// Move the program cached under `old_key`, and every name pointing
+    /// at it, to `new_key`. `old_key` comes from `reload_target`.
+    fn rekey(&mut self, old_key: &SourceKey, new_key: SourceKey) -> &mut P {
+        let program = self
+            .by_source
+            .remove(old_key)
+            .expect("reload_target checked the program is cached");
+        for key in self.names.values_mut() {
+            if key == old_key {
+                *key = new_key.clone();
+            }
+        }
+        self.by_source.entry(new_key).or_insert(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(vert_shader: &str, frag_shader: &str) -> SourceKey {
+        (vert_shader.to_string(), frag_shader.to_string())
+    }
+
+    fn index() -> SourceIndex<u32> {
+        let mut index = SourceIndex::default();
+        index.by_source.insert(key("a.vert", "a.frag"), 1);
+        index.by_source.insert(key("b.vert", "b.frag"), 2);
+        index.name("a", key("a.vert", "a.frag"));
+        index.name("also_a", key("a.vert", "a.frag"));
+        index.name("b", key("b.vert", "b.frag"));
+        index
+    }
+
+    #[test]
+    fn reload_moves_the_program_and_every_name_sharing_it() {
+        let mut index = index();
+        let new_key = key("c.vert", "c.frag");
+        let old_key = index.reload_target("a", &new_key).unwrap();
+        assert_eq!(*index.rekey(&old_key, new_key.clone()), 1);
+
+        assert_eq!(index.by_source.get(&new_key), Some(&1));
+        assert!(!index.by_source.contains_key(&key("a.vert", "a.frag")));
+        assert_eq!(index.names["a"], new_key);
+        assert_eq!(index.names["also_a"], new_key);
+        assert_eq!(index.names["b"], key("b.vert", "b.frag"));
+    }
+
+    #[test]
+    fn reload_onto_another_programs_sources_is_rejected() {
+        let index = index();
+        assert!(index.reload_target("a", &key("b.vert", "b.frag")).is_err());
+        // Nothing was touched.
+        assert_eq!(index.by_source.len(), 2);
+        assert_eq!(index.names["a"], key("a.vert", "a.frag"));
+    }
+
+    #[test]
+    fn reload_with_unchanged_sources_is_allowed() {
+        let mut index = index();
+        let same = key("a.vert", "a.frag");
+        let old_key = index.reload_target("a", &same).unwrap();
+        assert_eq!(*index.rekey(&old_key, same.clone()), 1);
+        assert_eq!(index.by_source.get(&same), Some(&1));
+        assert_eq!(index.by_source.len(), 2);
+    }
+
+    #[test]
+    fn reload_of_an_unknown_name_fails() {
+        assert!(index().reload_target("c", &key("c.vert", "c.frag")).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::renderer::GlState;
+
+/// How a material's fragments combine with what is already drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Overwrite the destination.
+    Opaque,
+    /// Straight alpha: `src * a + dst * (1 - a)`.
+    Alpha,
+    /// Add light: `src * a + dst`.
+    Additive,
+    /// Darken: `src * dst`.
+    Multiply,
+}
+
+impl BlendMode {
+    /// Source and destination factors for `blend_func`, or `None` when
+    /// blending is off.
+    pub fn factors(self) -> Option<(u32, u32)> {
+        match self {
+            BlendMode::Opaque => None,
+            BlendMode::Alpha => Some((GL::SRC_ALPHA, GL::ONE_MINUS_SRC_ALPHA)),
+            BlendMode::Additive => Some((GL::SRC_ALPHA, GL::ONE)),
+            BlendMode::Multiply => Some((GL::DST_COLOR, GL::ZERO)),
+        }
+    }
+
+    /// Whether drawing depends on what is behind, so the draw has to wait
+    /// for the opaque ones and be sorted back to front.
+    pub fn is_transparent(self) -> bool {
+        self != BlendMode::Opaque
+    }
+}
+
+/// Which faces are discarded before rasterizing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CullFace {
+    /// Draw both sides.
+    None,
+    Front,
+    Back,
+}
+
+impl CullFace {
+    fn gl_mode(self) -> Option<u32> {
+        match self {
+            CullFace::None => None,
+            CullFace::Front => Some(GL::FRONT),
+            CullFace::Back => Some(GL::BACK),
+        }
+    }
+}
+
+/// Fixed-function state a material draws with. The renderer applies it
+/// through the `GlState` cache, so materials sharing settings cost no
+/// extra calls.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderState {
+    pub blend: BlendMode,
+    pub cull_face: CullFace,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    /// Comparison against the depth buffer, e.g. `LEQUAL` so a second
+    /// pass over the same geometry still passes.
+    pub depth_func: u32,
+    /// Red, green, blue and alpha writes.
+    pub color_mask: [bool; 4],
+    /// `(factor, units)` for `polygon_offset`, pushing the surface back
+    /// in depth to draw decals or outlines over coplanar geometry.
+    pub polygon_offset: Option<(f32, f32)>,
+}
+
+impl Default for RenderState {
+    /// Opaque, one-sided and depth tested.
+    fn default() -> RenderState {
+        RenderState {
+            blend: BlendMode::Opaque,
+            cull_face: CullFace::Back,
+            depth_test: true,
+            depth_write: true,
+            depth_func: GL::LESS,
+            color_mask: [true; 4],
+            polygon_offset: None,
+        }
+    }
+}
+
+impl RenderState {
+    pub fn opaque() -> RenderState {
+        RenderState::default()
+    }
+
+    /// Alpha blended and depth tested, but not writing depth so surfaces
+    /// behind still show through.
+    pub fn transparent() -> RenderState {
+        RenderState {
+            blend: BlendMode::Alpha,
+            depth_write: false,
+            ..RenderState::default()
+        }
+    }
+
+    pub fn additive() -> RenderState {
+        RenderState {
+            blend: BlendMode::Additive,
+            depth_write: false,
+            ..RenderState::default()
+        }
+    }
+
+    pub fn multiply() -> RenderState {
+        RenderState {
+            blend: BlendMode::Multiply,
+            depth_write: false,
+            ..RenderState::default()
+        }
+    }
+
+    /// This state with both faces drawn.
+    pub fn double_sided(self) -> RenderState {
+        RenderState {
+            cull_face: CullFace::None,
+            ..self
+        }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.blend.is_transparent()
+    }
+
+    /// Set the context up to draw with this state. Calls that would not
+    /// change anything are skipped by `state`.
+    pub fn apply(&self, state: &GlState) {
+        match self.blend.factors() {
+            Some((source, destination)) => {
+                state.enable(GL::BLEND);
+                state.blend_equation(GL::FUNC_ADD);
+                state.blend_func(source, destination);
+            }
+            None => state.disable(GL::BLEND),
+        }
+        match self.cull_face.gl_mode() {
+            Some(mode) => {
+                state.enable(GL::CULL_FACE);
+                state.cull_face(mode);
+            }
+            None => state.disable(GL::CULL_FACE),
+        }
+        if self.depth_test {
+            state.enable(GL::DEPTH_TEST);
+            state.depth_func(self.depth_func);
+        } else {
+            state.disable(GL::DEPTH_TEST);
+        }
+        state.depth_mask(self.depth_write);
+        let [red, green, blue, alpha] = self.color_mask;
+        state.color_mask(red, green, blue, alpha);
+        match self.polygon_offset {
+            Some((factor, units)) => {
+                state.enable(GL::POLYGON_OFFSET_FILL);
+                state.polygon_offset(factor, units);
+            }
+            None => state.disable(GL::POLYGON_OFFSET_FILL),
+        }
+    }
+
+    /// Put the context back to GL's defaults for the state a material may
+    /// change, leaving the depth test alone, for passes that set up only
+    /// what they need.
+    pub fn reset(state: &GlState) {
+        state.disable(GL::BLEND);
+        state.disable(GL::CULL_FACE);
+        state.depth_func(GL::LESS);
+        state.depth_mask(true);
+        state.color_mask(true, true, true, true);
+        state.disable(GL::POLYGON_OFFSET_FILL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_blend_and_write_depth_as_named() {
+        assert_eq!(RenderState::opaque().blend.factors(), None);
+        assert!(RenderState::opaque().depth_write);
+        for state in [
+            RenderState::transparent(),
+            RenderState::additive(),
+            RenderState::multiply(),
+        ] {
+            assert!(state.is_transparent());
+            assert!(state.blend.factors().is_some());
+            assert!(!state.depth_write);
+            assert!(state.depth_test);
+        }
+        assert_eq!(
+            RenderState::additive().blend.factors(),
+            Some((GL::SRC_ALPHA, GL::ONE))
+        );
+    }
+
+    #[test]
+    fn double_sided_only_changes_culling() {
+        assert_eq!(
+            RenderState::transparent().double_sided(),
+            RenderState {
+                cull_face: CullFace::None,
+                ..RenderState::transparent()
+            }
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Small vector helpers on plain arrays, next to the `mat4` crate.
+
+pub type Vec3 = [f32; 3];
+pub type Mat4 = [f32; 16];
+
+pub fn add(a: &Vec3, b: &Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn sub(a: &Vec3, b: &Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn scale(a: &Vec3, s: f32) -> Vec3 {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+pub fn dot(a: &Vec3, b: &Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: &Vec3, b: &Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn length(a: &Vec3) -> f32 {
+    dot(a, a).sqrt()
+}
+
+/// `a` scaled to unit length, or `a` unchanged if it is zero.
+pub fn normalize(a: &Vec3) -> Vec3 {
+    let len = length(a);
+    if len > 0.0 {
+        scale(a, 1.0 / len)
+    } else {
+        *a
+    }
+}
+
+/// `a * b`, column-major like everything handed to WebGL.
+pub fn mul(a: &Mat4, b: &Mat4) -> Mat4 {
+    let mut out = mat4::new_zero();
+    mat4::mul(&mut out, a, b);
+    out
+}
+
+/// View matrix looking from `eye` at `target`.
+///
+/// `mat4::look_at` stores the camera basis transposed, so it is not used.
+pub fn look_at(eye: &Vec3, target: &Vec3, up: &Vec3) -> Mat4 {
+    let z = normalize(&sub(eye, target));
+    let x = normalize(&cross(up, &z));
+    let y = cross(&z, &x);
+    [
+        x[0], y[0], z[0], 0.0, //
+        x[1], y[1], z[1], 0.0, //
+        x[2], y[2], z[2], 0.0, //
+        -dot(&x, eye), -dot(&y, eye), -dot(&z, eye), 1.0, //
+    ]
+}
+
+/// An up vector that is not parallel to `direction`.
+pub fn up_for(direction: &Vec3) -> Vec3 {
+    if normalize(direction)[1].abs() > 0.99 {
+        [0.0, 0.0, 1.0]
+    } else {
+        [0.0, 1.0, 0.0]
+    }
+}
+
+/// The inverse of `m`, or `None` if it is singular.
+pub fn invert(m: &Mat4) -> Option<Mat4> {
+    let det = mat4::det(m);
+    if det == 0.0 || !det.is_finite() {
+        return None;
+    }
+    let mut out = mat4::new_zero();
+    mat4::inv(&mut out, m);
+    Some(out)
+}
+
+/// `m * (p, 1)` with the perspective divide applied.
+pub fn transform_point(m: &Mat4, p: &Vec3) -> Vec3 {
+    let x = m[0] * p[0] + m[4] * p[1] + m[8] * p[2] + m[12];
+    let y = m[1] * p[0] + m[5] * p[1] + m[9] * p[2] + m[13];
+    let z = m[2] * p[0] + m[6] * p[1] + m[10] * p[2] + m[14];
+    let w = m[3] * p[0] + m[7] * p[1] + m[11] * p[2] + m[15];
+    if w != 0.0 && w != 1.0 {
+        [x / w, y / w, z / w]
+    } else {
+        [x, y, z]
+    }
+}
+
+/// `m * (v, 0)`: `v` rotated and scaled, but not translated.
+pub fn transform_direction(m: &Mat4, v: &Vec3) -> Vec3 {
+    [
+        m[0] * v[0] + m[4] * v[1] + m[8] * v[2],
+        m[1] * v[0] + m[5] * v[1] + m[9] * v[2],
+        m[2] * v[0] + m[6] * v[1] + m[10] * v[2],
+    ]
+}
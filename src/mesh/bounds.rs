@@ -0,0 +1,151 @@
+use crate::math::{self, Vec3};
+
+use super::Ray;
+
+/// An axis-aligned bounding box. An empty box has `min` above `max` and
+/// grows to fit whatever is added to it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Default for Aabb {
+    fn default() -> Aabb {
+        Aabb::empty()
+    }
+}
+
+impl Aabb {
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+        }
+    }
+
+    /// The box around a flat array of xyz positions.
+    pub fn from_positions(positions: &[f32]) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for point in positions.chunks_exact(3) {
+            aabb.expand(&[point[0], point[1], point[2]]);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|axis| self.min[axis] > self.max[axis])
+    }
+
+    pub fn expand(&mut self, point: &Vec3) {
+        for (axis, &value) in point.iter().enumerate() {
+            self.min[axis] = self.min[axis].min(value);
+            self.max[axis] = self.max[axis].max(value);
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut union = *self;
+        union.expand(&other.min);
+        union.expand(&other.max);
+        union
+    }
+
+    pub fn center(&self) -> Vec3 {
+        [
+            (self.min[0] + self.max[0]) * 0.5,
+            (self.min[1] + self.max[1]) * 0.5,
+            (self.min[2] + self.max[2]) * 0.5,
+        ]
+    }
+
+    pub fn size(&self) -> Vec3 {
+        [
+            self.max[0] - self.min[0],
+            self.max[1] - self.min[1],
+            self.max[2] - self.min[2],
+        ]
+    }
+
+    /// The axis along which the box is longest: 0, 1 or 2.
+    pub fn longest_axis(&self) -> usize {
+        let size = self.size();
+        if size[0] >= size[1] && size[0] >= size[2] {
+            0
+        } else if size[1] >= size[2] {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Where `ray` enters the box, clamped to 0 if it starts inside, or
+    /// `None` if it misses or only gets there beyond `max_distance`.
+    pub fn intersect_ray(&self, ray: &Ray, max_distance: f32) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            let inverse = 1.0 / ray.direction[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inverse;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inverse;
+            if inverse < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN (a zero direction on a slab boundary) leaves the
+            // interval alone.
+            if t0 > near {
+                near = t0;
+            }
+            if t1 < far {
+                far = t1;
+            }
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+}
+
+/// A sphere around a mesh: cheaper to test than a box, but looser.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// The sphere centered on the positions' box, reaching the farthest
+    /// position. Empty input gives a zero sphere at the origin.
+    pub fn from_positions(positions: &[f32]) -> BoundingSphere {
+        let aabb = Aabb::from_positions(positions);
+        if aabb.is_empty() {
+            return BoundingSphere {
+                center: [0.0; 3],
+                radius: 0.0,
+            };
+        }
+        let center = aabb.center();
+        let radius = positions
+            .chunks_exact(3)
+            .map(|point| math::length(&math::sub(&[point[0], point[1], point[2]], &center)))
+            .fold(0.0, f32::max);
+        BoundingSphere { center, radius }
+    }
+}
+
+/// Both bounding volumes of a mesh, computed once when it is uploaded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Bounds {
+    pub fn from_positions(positions: &[f32]) -> Bounds {
+        Bounds {
+            aabb: Aabb::from_positions(positions),
+            sphere: BoundingSphere::from_positions(positions),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use crate::math::{self, Vec3};
+
+use super::{Aabb, Ray};
+
+/// Triangles per leaf before a node is split further.
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+/// The closest triangle a ray runs into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    /// Distance along the ray.
+    pub distance: f32,
+    pub point: Vec3,
+    /// Unit face normal, turned to face the ray.
+    pub normal: Vec3,
+    /// Index of the triangle, i.e. of its first index divided by 3.
+    pub triangle: usize,
+    /// Barycentric weights of the triangle's second and third corners.
+    pub barycentric: [f32; 2],
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    /// `count` triangles from `start` in `Bvh::order`.
+    Leaf { bounds: Aabb, start: usize, count: usize },
+    Branch { bounds: Aabb, left: usize, right: usize },
+}
+
+impl Node {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Branch { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// A bounding volume hierarchy over a triangle mesh for ray casts.
+///
+/// Triangles are split at the median centroid along the longest axis of
+/// each node until at most a few remain per leaf, so a cast visits only
+/// the boxes its ray passes through.
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    positions: Vec<Vec3>,
+    triangles: Vec<[u32; 3]>,
+    /// Triangle indices, reordered so every leaf covers a contiguous run.
+    order: Vec<usize>,
+    /// The root is the first node.
+    nodes: Vec<Node>,
+}
+
+impl Bvh {
+    /// Build over xyz `positions` and triangle `indices`.
+    pub fn build(positions: &[f32], indices: &[u32]) -> Result<Bvh, String> {
+        if !positions.len().is_multiple_of(3) {
+            return Err(format!(
+                "position count {} is not a multiple of 3",
+                positions.len()
+            ));
+        }
+        if !indices.len().is_multiple_of(3) {
+            return Err(format!("index count {} is not a multiple of 3", indices.len()));
+        }
+        let positions: Vec<Vec3> = positions
+            .chunks_exact(3)
+            .map(|point| [point[0], point[1], point[2]])
+            .collect();
+        if let Some(&index) = indices.iter().find(|&&index| index as usize >= positions.len()) {
+            return Err(format!(
+                "index {} is out of range for {} vertices",
+                index,
+                positions.len()
+            ));
+        }
+        let triangles: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect();
+
+        let mut bvh = Bvh {
+            order: (0..triangles.len()).collect(),
+            positions,
+            triangles,
+            nodes: Vec::new(),
+        };
+        let centroids: Vec<Vec3> = (0..bvh.triangles.len())
+            .map(|triangle| bvh.triangle_bounds(triangle).center())
+            .collect();
+        let count = bvh.triangles.len();
+        bvh.build_node(&centroids, 0, count);
+        Ok(bvh)
+    }
+
+    /// Box around the whole mesh.
+    pub fn bounds(&self) -> Aabb {
+        self.nodes
+            .first()
+            .map(|node| *node.bounds())
+            .unwrap_or_else(Aabb::empty)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// The nearest triangle along `ray`, if any.
+    pub fn raycast(&self, ray: &Ray) -> Option<RayHit> {
+        let mut best: Option<RayHit> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(node) = stack.pop() {
+            let max_distance = best.map_or(f32::INFINITY, |hit| hit.distance);
+            let node = &self.nodes[node];
+            if node.bounds().intersect_ray(ray, max_distance).is_none() {
+                continue;
+            }
+            match *node {
+                Node::Leaf { start, count, .. } => {
+                    for &triangle in &self.order[start..start + count] {
+                        if let Some(hit) = self.intersect(ray, triangle) {
+                            if best.is_none_or(|best| hit.distance < best.distance) {
+                                best = Some(hit);
+                            }
+                        }
+                    }
+                }
+                Node::Branch { left, right, .. } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        best
+    }
+
+    fn corners(&self, triangle: usize) -> [Vec3; 3] {
+        let [a, b, c] = self.triangles[triangle];
+        [
+            self.positions[a as usize],
+            self.positions[b as usize],
+            self.positions[c as usize],
+        ]
+    }
+
+    fn triangle_bounds(&self, triangle: usize) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for corner in self.corners(triangle).iter() {
+            bounds.expand(corner);
+        }
+        bounds
+    }
+
+    fn intersect(&self, ray: &Ray, triangle: usize) -> Option<RayHit> {
+        let [a, b, c] = self.corners(triangle);
+        let hit = ray.intersect_triangle(&a, &b, &c)?;
+        let mut normal = math::normalize(&math::cross(&math::sub(&b, &a), &math::sub(&c, &a)));
+        if math::dot(&normal, &ray.direction) > 0.0 {
+            normal = math::scale(&normal, -1.0);
+        }
+        Some(RayHit {
+            distance: hit.distance,
+            point: ray.at(hit.distance),
+            normal,
+            triangle,
+            barycentric: [hit.u, hit.v],
+        })
+    }
+
+    /// Build the node over `order[start..end]` and return its index.
+    fn build_node(&mut self, centroids: &[Vec3], start: usize, end: usize) -> usize {
+        let mut bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &triangle in &self.order[start..end] {
+            bounds = bounds.union(&self.triangle_bounds(triangle));
+            centroid_bounds.expand(&centroids[triangle]);
+        }
+
+        let index = self.nodes.len();
+        let count = end - start;
+        self.nodes.push(Node::Leaf { bounds, start, count });
+        if count <= MAX_LEAF_TRIANGLES {
+            return index;
+        }
+        let axis = centroid_bounds.longest_axis();
+        if centroid_bounds.size()[axis] <= 0.0 {
+            // Every centroid coincides; splitting cannot separate them.
+            return index;
+        }
+
+        let middle = start + count / 2;
+        self.order[start..end].select_nth_unstable_by(count / 2, |&a, &b| {
+            centroids[a][axis].total_cmp(&centroids[b][axis])
+        });
+        let left = self.build_node(centroids, start, middle);
+        let right = self.build_node(centroids, middle, end);
+        self.nodes[index] = Node::Branch { bounds, left, right };
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `size`×`size` grid of quads in the z = 0 plane, from 0 to `size`.
+    fn grid(size: u32) -> (Vec<f32>, Vec<u32>) {
+        let mut positions = Vec::new();
+        for y in 0..=size {
+            for x in 0..=size {
+                positions.extend_from_slice(&[x as f32, y as f32, 0.0]);
+            }
+        }
+        let mut indices = Vec::new();
+        let row = size + 1;
+        for y in 0..size {
+            for x in 0..size {
+                let a = y * row + x;
+                indices.extend_from_slice(&[a, a + 1, a + row, a + 1, a + row + 1, a + row]);
+            }
+        }
+        (positions, indices)
+    }
+
+    /// A few hundred triangles scattered by a fixed LCG.
+    fn scattered() -> (Vec<f32>, Vec<u32>) {
+        let mut seed = 12345u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 10.0 - 5.0
+        };
+        let mut positions = Vec::new();
+        for _ in 0..300 {
+            let center = [next(), next(), next()];
+            for _ in 0..3 {
+                for coordinate in center.iter() {
+                    positions.push(coordinate + next() * 0.1);
+                }
+            }
+        }
+        let indices = (0..positions.len() as u32 / 3).collect();
+        (positions, indices)
+    }
+
+    fn brute_force(positions: &[f32], indices: &[u32], ray: &Ray) -> Option<f32> {
+        let corner = |index: u32| {
+            let i = index as usize * 3;
+            [positions[i], positions[i + 1], positions[i + 2]]
+        };
+        indices
+            .chunks_exact(3)
+            .filter_map(|t| ray.intersect_triangle(&corner(t[0]), &corner(t[1]), &corner(t[2])))
+            .map(|hit| hit.distance)
+            .fold(None, |best: Option<f32>, d| Some(best.map_or(d, |b| b.min(d))))
+    }
+
+    #[test]
+    fn hits_grid_where_the_ray_points() {
+        let (positions, indices) = grid(16);
+        let bvh = Bvh::build(&positions, &indices).unwrap();
+        let ray = Ray::new([3.25, 7.75, 10.0], [0.0, 0.0, -1.0]);
+        let hit = bvh.raycast(&ray).unwrap();
+        assert!((hit.distance - 10.0).abs() < 1e-4);
+        assert!((hit.point[0] - 3.25).abs() < 1e-4);
+        assert!((hit.point[1] - 7.75).abs() < 1e-4);
+        assert_eq!(hit.normal, [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn normal_faces_the_ray() {
+        let (positions, indices) = grid(4);
+        let bvh = Bvh::build(&positions, &indices).unwrap();
+        let hit = bvh.raycast(&Ray::new([1.5, 1.5, -3.0], [0.0, 0.0, 1.0])).unwrap();
+        assert_eq!(hit.normal, [0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn misses_outside_the_mesh() {
+        let (positions, indices) = grid(4);
+        let bvh = Bvh::build(&positions, &indices).unwrap();
+        assert!(bvh.raycast(&Ray::new([10.0, 10.0, 1.0], [0.0, 0.0, -1.0])).is_none());
+        assert!(bvh.raycast(&Ray::new([1.0, 1.0, 1.0], [0.0, 0.0, 1.0])).is_none());
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let (positions, indices) = scattered();
+        let bvh = Bvh::build(&positions, &indices).unwrap();
+        assert!(bvh.nodes.len() > 1);
+        let mut hits = 0;
+        for i in 0..200 {
+            let angle = i as f32 * 0.37;
+            let origin = [angle.cos() * 8.0, (i as f32 * 0.05) - 5.0, angle.sin() * 8.0];
+            let target = [angle.sin() * 2.0, angle.cos() * 2.0, 0.0];
+            let ray = Ray::new(origin, math::sub(&target, &origin));
+            let expected = brute_force(&positions, &indices, &ray);
+            let actual = bvh.raycast(&ray).map(|hit| hit.distance);
+            assert_eq!(expected.is_some(), actual.is_some(), "ray {}", i);
+            if let (Some(expected), Some(actual)) = (expected, actual) {
+                assert!((expected - actual).abs() < 1e-4, "ray {}", i);
+                hits += 1;
+            }
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn nearest_of_stacked_triangles_wins() {
+        let positions = [
+            -1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 0.0, 1.0, 0.0, //
+            -1.0, -1.0, 2.0, 1.0, -1.0, 2.0, 0.0, 1.0, 2.0, //
+        ];
+        let bvh = Bvh::build(&positions, &[0, 1, 2, 3, 4, 5]).unwrap();
+        let hit = bvh.raycast(&Ray::new([0.0, 0.0, 5.0], [0.0, 0.0, -1.0])).unwrap();
+        assert_eq!(hit.triangle, 1);
+        assert!((hit.distance - 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn bounds_cover_the_mesh() {
+        let (positions, indices) = grid(8);
+        let bvh = Bvh::build(&positions, &indices).unwrap();
+        assert_eq!(bvh.bounds(), Aabb::from_positions(&positions));
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(Bvh::build(&[0.0, 0.0], &[]).is_err());
+        assert!(Bvh::build(&[0.0; 9], &[0, 1]).is_err());
+        assert!(Bvh::build(&[0.0; 9], &[0, 1, 3]).is_err());
+    }
+
+    #[test]
+    fn empty_mesh_never_hits() {
+        let bvh = Bvh::build(&[], &[]).unwrap();
+        assert!(bvh.raycast(&Ray::new([0.0; 3], [0.0, 0.0, -1.0])).is_none());
+        assert!(bvh.bounds().is_empty());
+    }
+}
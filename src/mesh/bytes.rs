@@ -0,0 +1,118 @@
+//! Reading numbers and header lines out of a byte slice, shared by the
+//! file format parsers.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Endian {
+    Little,
+    Big,
+}
+
+/// A cursor over `data` that fails with a message, rather than panicking,
+/// when the data runs out.
+#[derive(Debug, Clone)]
+pub(crate) struct ByteReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+macro_rules! read_number {
+    ($name:ident, $type:ty) => {
+        pub(crate) fn $name(&mut self, endian: Endian) -> Result<$type, String> {
+            let mut bytes = [0; std::mem::size_of::<$type>()];
+            bytes.copy_from_slice(self.take(std::mem::size_of::<$type>())?);
+            Ok(match endian {
+                Endian::Little => <$type>::from_le_bytes(bytes),
+                Endian::Big => <$type>::from_be_bytes(bytes),
+            })
+        }
+    };
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { data, offset: 0 }
+    }
+
+    pub(crate) fn remaining(&self) -> &'a [u8] {
+        &self.data[self.offset..]
+    }
+
+    pub(crate) fn is_at_end(&self) -> bool {
+        self.offset == self.data.len()
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() - self.offset < len {
+            return Err(format!(
+                "unexpected end of data at byte {}, {} more needed",
+                self.offset,
+                len - (self.data.len() - self.offset)
+            ));
+        }
+        let bytes = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn skip(&mut self, len: usize) -> Result<(), String> {
+        self.take(len).map(|_| ())
+    }
+
+    /// The next line as text, without its `\n` or `\r\n`, or `None` at
+    /// the end of the data.
+    pub(crate) fn line(&mut self) -> Result<Option<&'a str>, String> {
+        if self.is_at_end() {
+            return Ok(None);
+        }
+        let rest = self.remaining();
+        let len = rest
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .unwrap_or(rest.len());
+        let line = &rest[..len];
+        self.offset += (len + 1).min(rest.len());
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        std::str::from_utf8(line)
+            .map(Some)
+            .map_err(|_| format!("line ending at byte {} is not text", self.offset))
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn i8(&mut self) -> Result<i8, String> {
+        Ok(self.take(1)?[0] as i8)
+    }
+
+    read_number!(u16, u16);
+    read_number!(i16, i16);
+    read_number!(u32, u32);
+    read_number!(i32, i32);
+    read_number!(f32, f32);
+    read_number!(f64, f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_in_either_byte_order() {
+        let mut reader = ByteReader::new(&[1, 2, 1, 2, 0, 0, 128, 63]);
+        assert_eq!(reader.u16(Endian::Little), Ok(0x0201));
+        assert_eq!(reader.u16(Endian::Big), Ok(0x0102));
+        assert_eq!(reader.f32(Endian::Little), Ok(1.0));
+        assert!(reader.is_at_end());
+        assert!(reader.u8().is_err());
+    }
+
+    #[test]
+    fn lines_drop_their_endings() {
+        let mut reader = ByteReader::new(b"ply\r\nformat ascii 1.0\nlast");
+        assert_eq!(reader.line(), Ok(Some("ply")));
+        assert_eq!(reader.line(), Ok(Some("format ascii 1.0")));
+        assert_eq!(reader.line(), Ok(Some("last")));
+        assert_eq!(reader.line(), Ok(None));
+    }
+}
@@ -0,0 +1,252 @@
+use crate::math::{self, Mat4, Vec3};
+
+use super::{Aabb, BoundingSphere, Bounds};
+
+/// The points `p` with `dot(normal, p) + distance >= 0` lie on the inner
+/// side. `normal` is unit length, so the left side is a signed distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// The plane `a·x + b·y + c·z + d = 0`, normalized. `None` if
+    /// (`a`, `b`, `c`) is zero.
+    fn from_coefficients(coefficients: [f32; 4]) -> Option<Plane> {
+        let [a, b, c, d] = coefficients;
+        let length = math::length(&[a, b, c]);
+        if length == 0.0 || !length.is_finite() {
+            return None;
+        }
+        Some(Plane {
+            normal: [a / length, b / length, c / length],
+            distance: d / length,
+        })
+    }
+
+    pub fn signed_distance(&self, point: &Vec3) -> f32 {
+        math::dot(&self.normal, point) + self.distance
+    }
+}
+
+/// The volume a camera sees, as six inward-facing planes: left, right,
+/// bottom, top, near and far.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extract the planes from a projection × model-view matrix. The
+    /// planes are in the space the matrix maps from, so bounds in a
+    /// mesh's own space can be tested without transforming them. `None`
+    /// if the matrix is degenerate.
+    pub fn from_matrix(m: &Mat4) -> Option<Frustum> {
+        // Rows of the column-major matrix. A point is inside when its
+        // clip coordinates satisfy -w <= x, y, z <= w.
+        let row = |i: usize| [m[i], m[4 + i], m[8 + i], m[12 + i]];
+        let combine = |a: [f32; 4], b: [f32; 4], sign: f32| {
+            Plane::from_coefficients([
+                a[0] + sign * b[0],
+                a[1] + sign * b[1],
+                a[2] + sign * b[2],
+                a[3] + sign * b[3],
+            ])
+        };
+        let w = row(3);
+        Some(Frustum {
+            planes: [
+                combine(w, row(0), 1.0)?,
+                combine(w, row(0), -1.0)?,
+                combine(w, row(1), 1.0)?,
+                combine(w, row(1), -1.0)?,
+                combine(w, row(2), 1.0)?,
+                combine(w, row(2), -1.0)?,
+            ],
+        })
+    }
+
+    pub fn contains_point(&self, point: &Vec3) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(point) >= 0.0)
+    }
+
+    /// False only if the sphere is entirely outside one of the planes.
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(&sphere.center) >= -sphere.radius)
+    }
+
+    /// False only if the box is entirely outside one of the planes, judged
+    /// by the corner farthest along each plane's normal. Boxes near a
+    /// frustum corner may pass without being visible, which only costs a
+    /// draw.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+        self.planes.iter().all(|plane| {
+            let mut corner = aabb.min;
+            for (axis, value) in corner.iter_mut().enumerate() {
+                if plane.normal[axis] >= 0.0 {
+                    *value = aabb.max[axis];
+                }
+            }
+            plane.signed_distance(&corner) >= 0.0
+        })
+    }
+
+    /// Whether a mesh with these bounds may be visible: the sphere is
+    /// tested first as the cheaper check, then the tighter box.
+    pub fn intersects(&self, bounds: &Bounds) -> bool {
+        self.intersects_sphere(&bounds.sphere) && self.intersects_aabb(&bounds.aabb)
+    }
+}
+
+/// How many draws were tested against a frustum and how many of them were
+/// skipped, usually counted over one frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub tested: u32,
+    pub culled: u32,
+}
+
+impl CullStats {
+    pub fn visible(&self) -> u32 {
+        self.tested - self.culled
+    }
+
+    /// Count one test and return `visible` for use in a condition.
+    pub fn record(&mut self, visible: bool) -> bool {
+        self.tested += 1;
+        if !visible {
+            self.culled += 1;
+        }
+        visible
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perspective() -> Mat4 {
+        let mut projection = mat4::new_zero();
+        let fov = std::f32::consts::FRAC_PI_2;
+        mat4::perspective(&mut projection, &fov, &1.0, &1.0, &100.0);
+        projection
+    }
+
+    fn sphere(center: Vec3, radius: f32) -> BoundingSphere {
+        BoundingSphere { center, radius }
+    }
+
+    fn assert_plane(plane: &Plane, normal: Vec3, distance: f32) {
+        for (actual, expected) in plane.normal.iter().zip(normal.iter()) {
+            assert!((actual - expected).abs() < 1e-5, "{:?}", plane);
+        }
+        let tolerance = 1e-5 * distance.abs().max(1.0);
+        assert!((plane.distance - distance).abs() < tolerance, "{:?}", plane);
+    }
+
+    #[test]
+    fn identity_gives_the_clip_cube() {
+        let frustum = Frustum::from_matrix(&mat4::new_identity()).unwrap();
+        assert_plane(&frustum.planes[0], [1.0, 0.0, 0.0], 1.0);
+        assert_plane(&frustum.planes[1], [-1.0, 0.0, 0.0], 1.0);
+        assert_plane(&frustum.planes[2], [0.0, 1.0, 0.0], 1.0);
+        assert_plane(&frustum.planes[3], [0.0, -1.0, 0.0], 1.0);
+        assert_plane(&frustum.planes[4], [0.0, 0.0, 1.0], 1.0);
+        assert_plane(&frustum.planes[5], [0.0, 0.0, -1.0], 1.0);
+    }
+
+    #[test]
+    fn perspective_planes_match_near_far_and_field_of_view() {
+        let frustum = Frustum::from_matrix(&perspective()).unwrap();
+        // The camera looks down -z; near is at z = -1 and far at z = -100.
+        assert_plane(&frustum.planes[4], [0.0, 0.0, -1.0], -1.0);
+        assert_plane(&frustum.planes[5], [0.0, 0.0, 1.0], 100.0);
+        // A 90 degree field of view puts the side planes at 45 degrees.
+        let side = std::f32::consts::FRAC_1_SQRT_2;
+        assert_plane(&frustum.planes[0], [side, 0.0, -side], 0.0);
+        assert_plane(&frustum.planes[3], [0.0, -side, -side], 0.0);
+    }
+
+    #[test]
+    fn points_inside_and_outside() {
+        let frustum = Frustum::from_matrix(&perspective()).unwrap();
+        assert!(frustum.contains_point(&[0.0, 0.0, -10.0]));
+        assert!(frustum.contains_point(&[9.0, -9.0, -10.0]));
+        assert!(!frustum.contains_point(&[11.0, 0.0, -10.0]));
+        assert!(!frustum.contains_point(&[0.0, 0.0, 5.0]));
+        assert!(!frustum.contains_point(&[0.0, 0.0, -0.5]));
+        assert!(!frustum.contains_point(&[0.0, 0.0, -150.0]));
+    }
+
+    #[test]
+    fn model_view_moves_the_planes_into_model_space() {
+        // A sphere is in view or not depending on where the model-view
+        // matrix places the model in front of the camera.
+        let mut view = mat4::new_identity();
+        let identity = view;
+        mat4::translate(&mut view, &identity, &[20.0, 0.0, -6.0]);
+        let centered = Frustum::from_matrix(&perspective()).unwrap();
+        let shifted = Frustum::from_matrix(&math::mul(&perspective(), &view)).unwrap();
+        let origin_sphere = sphere([0.0; 3], 0.5);
+        assert!(!centered.intersects_sphere(&origin_sphere));
+        assert!(!shifted.intersects_sphere(&origin_sphere));
+        assert!(shifted.intersects_sphere(&sphere([-20.0, 0.0, 0.0], 1.0)));
+    }
+
+    #[test]
+    fn spheres_straddling_a_plane_are_kept() {
+        let frustum = Frustum::from_matrix(&perspective()).unwrap();
+        assert!(frustum.intersects_sphere(&sphere([0.0, 0.0, -10.0], 1.0)));
+        assert!(frustum.intersects_sphere(&sphere([0.0, 0.0, -0.5], 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere([0.0, 0.0, 2.5], 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere([0.0, 30.0, -10.0], 1.0)));
+    }
+
+    #[test]
+    fn boxes_inside_outside_and_straddling() {
+        let frustum = Frustum::from_matrix(&perspective()).unwrap();
+        let inside = Aabb::from_positions(&[-1.0, -1.0, -11.0, 1.0, 1.0, -9.0]);
+        let straddling = Aabb::from_positions(&[5.0, -1.0, -11.0, 15.0, 1.0, -9.0]);
+        let outside = Aabb::from_positions(&[15.0, -1.0, -11.0, 25.0, 1.0, -9.0]);
+        assert!(frustum.intersects_aabb(&inside));
+        assert!(frustum.intersects_aabb(&straddling));
+        assert!(!frustum.intersects_aabb(&outside));
+        assert!(!frustum.intersects_aabb(&Aabb::empty()));
+    }
+
+    #[test]
+    fn bounds_need_both_volumes_in_view() {
+        let frustum = Frustum::from_matrix(&perspective()).unwrap();
+        let in_view = Bounds::from_positions(&[-1.0, -1.0, -11.0, 1.0, 1.0, -9.0]);
+        assert!((in_view.sphere.radius - 3f32.sqrt()).abs() < 1e-5);
+        assert!(frustum.intersects(&in_view));
+        // A flat slab just past the right plane: its loose sphere reaches
+        // into view, but the box does not.
+        let beside = Bounds::from_positions(&[11.5, -5.0, -11.0, 13.5, 5.0, -9.0]);
+        assert!(frustum.intersects_sphere(&beside.sphere));
+        assert!(!frustum.intersects(&beside));
+    }
+
+    #[test]
+    fn degenerate_matrix_has_no_frustum() {
+        assert!(Frustum::from_matrix(&mat4::new_zero()).is_none());
+    }
+
+    #[test]
+    fn stats_count_culled_draws() {
+        let mut stats = CullStats::default();
+        assert!(stats.record(true));
+        assert!(!stats.record(false));
+        assert!(stats.record(true));
+        assert_eq!(stats, CullStats { tested: 3, culled: 1 });
+        assert_eq!(stats.visible(), 2);
+    }
+}
@@ -0,0 +1,40 @@
+mod bounds;
+mod bvh;
+mod bytes;
+mod frustum;
+mod normals;
+mod octree;
+mod pcd;
+mod ply;
+mod point_cloud;
+mod polyline;
+mod ray;
+mod split;
+mod stl;
+mod triangle_mesh;
+mod wireframe;
+
+pub use self::bounds::{Aabb, BoundingSphere, Bounds};
+pub use self::bvh::{Bvh, RayHit};
+pub use self::frustum::{CullStats, Frustum, Plane};
+pub use self::normals::{compute_tangents, flat_normals, smooth_normals};
+pub use self::octree::{LodSelection, OctreeNode, OctreeSettings, PointOctree};
+pub use self::pcd::read_pcd;
+pub use self::ply::{read_ply, PlyGeometry};
+pub use self::point_cloud::PointCloud;
+pub use self::polyline::{LineCap, LineJoin, LineStyle, Polylines, LINE_VERTEX_FLOATS};
+pub use self::ray::{Ray, TriangleHit};
+pub use self::split::{split_mesh, SubMesh, MAX_U16_VERTICES};
+pub use self::stl::{read_stl, weld_vertices, write_stl, write_stl_ascii, DEFAULT_WELD_TOLERANCE};
+pub use self::triangle_mesh::TriangleMesh;
+pub use self::wireframe::{triangle_list, wireframe_edges};
+
+#[cfg(test)]
+mod tests {
+    /// The contents of `tests/fixtures/<name>`, shared by the file format
+    /// readers' tests.
+    pub(crate) fn fixture(name: &str) -> Vec<u8> {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read(&path).unwrap_or_else(|error| panic!("{}: {}", path, error))
+    }
+}
@@ -0,0 +1,544 @@
+use std::f32::consts::PI;
+
+use crate::math::{self, Vec3};
+
+use super::TriangleMesh;
+
+/// Cosine above which two corner normals are taken for the same one, so
+/// rounding does not split a vertex.
+const SAME_NORMAL: f32 = 0.999_999;
+
+/// `mesh` with flat normals: every triangle gets its own three vertices,
+/// all with the triangle's normal, so each face is shaded evenly. Fails if
+/// `mesh` does not validate.
+pub fn flat_normals(mesh: &TriangleMesh) -> Result<TriangleMesh, String> {
+    mesh.validate()?;
+    let mut flat = TriangleMesh::default();
+    for triangle in 0..mesh.triangle_count() {
+        let normal = mesh.face_normal(triangle);
+        for vertex in mesh.triangle(triangle) {
+            flat.indices.push(flat.vertex_count() as u32);
+            push_vertex(&mut flat, mesh, vertex, normal);
+        }
+    }
+    Ok(flat)
+}
+
+/// `mesh` with smooth normals: each corner gets the average normal of the
+/// triangles around its vertex, weighted by their angle at the vertex so
+/// the way a face is split into triangles does not tilt it.
+///
+/// Triangles whose normals are more than `crease_angle` radians from the
+/// corner's own triangle are left out, so edges sharper than that stay
+/// hard. Vertices on such edges are split, the extra copies going after
+/// the existing vertices; with a crease angle of pi or more nothing is
+/// split and only the normals change. Fails if `mesh` does not validate.
+pub fn smooth_normals(mesh: &TriangleMesh, crease_angle: f32) -> Result<TriangleMesh, String> {
+    mesh.validate()?;
+    let faces = FaceNormals::new(mesh);
+    let around = faces_around_vertices(mesh);
+    let crease_all = crease_angle >= PI;
+    let cos_crease = crease_angle.cos();
+
+    let mut smooth = TriangleMesh {
+        positions: mesh.positions.clone(),
+        normals: vec![0.0; mesh.positions.len()],
+        texcoords: mesh.texcoords.clone(),
+        colors: mesh.colors.clone(),
+        indices: Vec::with_capacity(mesh.indices.len()),
+    };
+    // The normals given to each original vertex so far and the vertex
+    // holding each: the first is the vertex itself, others are copies.
+    let mut variants: Vec<Vec<(Vec3, u32)>> = vec![Vec::new(); mesh.vertex_count()];
+    for triangle in 0..mesh.triangle_count() {
+        let own = faces.normals[triangle];
+        for vertex in mesh.triangle(triangle) {
+            let mut normal = faces.average(mesh, vertex, &around[vertex], |other| {
+                crease_all || math::dot(&own, &faces.normals[other]) >= cos_crease
+            });
+            if normal == [0.0; 3] {
+                normal = faces.average(mesh, vertex, &around[vertex], |_| true);
+            }
+            let same = variants[vertex]
+                .iter()
+                .find(|(existing, _)| math::dot(existing, &normal) >= SAME_NORMAL)
+                .map(|&(_, index)| index);
+            let index = match same {
+                Some(index) => index,
+                None if variants[vertex].is_empty() => {
+                    smooth.normals[vertex * 3..vertex * 3 + 3].copy_from_slice(&normal);
+                    vertex as u32
+                }
+                None => {
+                    push_vertex(&mut smooth, mesh, vertex, normal);
+                    (smooth.vertex_count() - 1) as u32
+                }
+            };
+            if same.is_none() {
+                variants[vertex].push((normal, index));
+            }
+            smooth.indices.push(index);
+        }
+    }
+    Ok(smooth)
+}
+
+/// Smooth normals for the vertices of `mesh` as they are, xyz each,
+/// weighted by angle as in `smooth_normals` but with no crease.
+pub(crate) fn vertex_normals(mesh: &TriangleMesh) -> Vec<f32> {
+    let faces = FaceNormals::new(mesh);
+    let around = faces_around_vertices(mesh);
+    let mut normals = Vec::with_capacity(mesh.positions.len());
+    for (vertex, triangles) in around.iter().enumerate() {
+        normals.extend_from_slice(&faces.average(mesh, vertex, triangles, |_| true));
+    }
+    normals
+}
+
+/// MikkTSpace-style tangents for normal mapping: `mesh` with vertices
+/// split where needed, and xyzw per vertex of the result. xyz points
+/// along increasing u in the plane of the vertex normal, and w is 1 or -1
+/// so that `cross(normal, xyz) * w` points along increasing v, the
+/// convention glTF and most bakers use.
+///
+/// As in MikkTSpace each triangle's tangent is projected onto the vertex
+/// normal and weighted by the triangle's angle at the vertex before they
+/// are averaged, and a vertex shared by triangles whose UVs are mirrored
+/// relative to each other is split so each side keeps its own frame. The
+/// copies go after the existing vertices. Unlike MikkTSpace, vertices are
+/// only split by handedness, not by diverging normals or by degenerate
+/// UVs, so the result is close to what bakers expect but not bit for bit
+/// the same. Needs normals and texture coordinates.
+pub fn compute_tangents(mesh: &TriangleMesh) -> Result<(TriangleMesh, Vec<f32>), String> {
+    mesh.validate()?;
+    if mesh.normals.is_empty() || mesh.texcoords.is_empty() {
+        return Err("tangents need normals and texture coordinates".to_string());
+    }
+    let mut split = TriangleMesh {
+        positions: mesh.positions.clone(),
+        normals: mesh.normals.clone(),
+        texcoords: mesh.texcoords.clone(),
+        colors: mesh.colors.clone(),
+        indices: Vec::with_capacity(mesh.indices.len()),
+    };
+    let mut along_u = vec![[0.0; 3]; mesh.vertex_count()];
+    let mut along_v = vec![[0.0; 3]; mesh.vertex_count()];
+    // The handedness each original vertex has been used with so far and
+    // the vertex holding it: the first is the vertex itself, the other a
+    // copy.
+    let mut variants: Vec<Vec<(bool, u32)>> = vec![Vec::new(); mesh.vertex_count()];
+    for triangle in 0..mesh.triangle_count() {
+        let corners = mesh.triangle(triangle);
+        let [p0, p1, p2] = corners.map(|vertex| mesh.position(vertex));
+        let [t0, t1, t2] = corners.map(|vertex| texcoord(mesh, vertex));
+        let (edge1, edge2) = (math::sub(&p1, &p0), math::sub(&p2, &p0));
+        let (du1, dv1) = (t1[0] - t0[0], t1[1] - t0[1]);
+        let (du2, dv2) = (t2[0] - t0[0], t2[1] - t0[1]);
+        // Unscaled by the UV area, whose sign is all that matters: it
+        // says whether the UVs are mirrored.
+        let mirrored = du1 * dv2 - du2 * dv1 < 0.0;
+        let sign = if mirrored { -1.0 } else { 1.0 };
+        let u = math::scale(
+            &math::sub(&math::scale(&edge1, dv2), &math::scale(&edge2, dv1)),
+            sign,
+        );
+        let v = math::scale(
+            &math::sub(&math::scale(&edge2, du1), &math::scale(&edge1, du2)),
+            sign,
+        );
+        for (corner, &vertex) in corners.iter().enumerate() {
+            let same = variants[vertex]
+                .iter()
+                .find(|(existing, _)| *existing == mirrored)
+                .map(|&(_, index)| index);
+            let index = match same {
+                Some(index) => index as usize,
+                None => {
+                    let index = if variants[vertex].is_empty() {
+                        vertex
+                    } else {
+                        let normal = [0, 1, 2].map(|axis| mesh.normals[vertex * 3 + axis]);
+                        push_vertex(&mut split, mesh, vertex, normal);
+                        along_u.push([0.0; 3]);
+                        along_v.push([0.0; 3]);
+                        split.vertex_count() - 1
+                    };
+                    variants[vertex].push((mirrored, index as u32));
+                    index
+                }
+            };
+            split.indices.push(index as u32);
+
+            let [a, b, c] = [0, 1, 2].map(|offset| mesh.position(corners[(corner + offset) % 3]));
+            let weight = corner_angle(&a, &b, &c);
+            let normal = normal(mesh, vertex);
+            let u = math::normalize(&project(&u, &normal));
+            let v = math::normalize(&project(&v, &normal));
+            along_u[index] = math::add(&along_u[index], &math::scale(&u, weight));
+            along_v[index] = math::add(&along_v[index], &math::scale(&v, weight));
+        }
+    }
+
+    let mut tangents = Vec::with_capacity(split.vertex_count() * 4);
+    for (vertex, (u, v)) in along_u.iter().zip(&along_v).enumerate() {
+        let normal = normal(&split, vertex);
+        let mut tangent = math::normalize(&project(u, &normal));
+        if tangent == [0.0; 3] {
+            // No usable UVs around the vertex: any direction in the plane.
+            tangent = math::normalize(&math::cross(&math::up_for(&normal), &normal));
+        }
+        let w = if math::dot(&math::cross(&normal, &tangent), v) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        tangents.extend_from_slice(&[tangent[0], tangent[1], tangent[2], w]);
+    }
+    Ok((split, tangents))
+}
+
+/// The unit normal of every triangle, zero for degenerate ones, and the
+/// angle of each of its corners.
+struct FaceNormals {
+    normals: Vec<Vec3>,
+    angles: Vec<[f32; 3]>,
+}
+
+impl FaceNormals {
+    fn new(mesh: &TriangleMesh) -> FaceNormals {
+        let count = mesh.triangle_count();
+        let mut faces = FaceNormals {
+            normals: Vec::with_capacity(count),
+            angles: Vec::with_capacity(count),
+        };
+        for triangle in 0..count {
+            let [a, b, c] = mesh.triangle(triangle).map(|vertex| mesh.position(vertex));
+            faces.normals.push(mesh.face_normal(triangle));
+            faces.angles.push([
+                corner_angle(&a, &b, &c),
+                corner_angle(&b, &c, &a),
+                corner_angle(&c, &a, &b),
+            ]);
+        }
+        faces
+    }
+
+    /// The angle-weighted average normal at `vertex` of those of its
+    /// `triangles` that `include` accepts, or zero if none counts.
+    fn average(
+        &self,
+        mesh: &TriangleMesh,
+        vertex: usize,
+        triangles: &[usize],
+        include: impl Fn(usize) -> bool,
+    ) -> Vec3 {
+        let mut sum = [0.0; 3];
+        for &triangle in triangles {
+            if !include(triangle) {
+                continue;
+            }
+            let corners = mesh.triangle(triangle);
+            for (corner, &angle) in corners.iter().zip(&self.angles[triangle]) {
+                // A vertex may appear twice in a degenerate triangle.
+                if *corner == vertex {
+                    sum = math::add(&sum, &math::scale(&self.normals[triangle], angle));
+                }
+            }
+        }
+        math::normalize(&sum)
+    }
+}
+
+/// The triangles each vertex belongs to, each listed once.
+fn faces_around_vertices(mesh: &TriangleMesh) -> Vec<Vec<usize>> {
+    let mut around = vec![Vec::new(); mesh.vertex_count()];
+    for triangle in 0..mesh.triangle_count() {
+        for vertex in mesh.triangle(triangle) {
+            let faces = &mut around[vertex];
+            if faces.last() != Some(&triangle) {
+                faces.push(triangle);
+            }
+        }
+    }
+    around
+}
+
+/// The angle at `corner` between the edges to `next` and `previous`, or
+/// zero if either edge has no length.
+fn corner_angle(corner: &Vec3, next: &Vec3, previous: &Vec3) -> f32 {
+    let a = math::sub(next, corner);
+    let b = math::sub(previous, corner);
+    let sine = math::length(&math::cross(&a, &b));
+    let cosine = math::dot(&a, &b);
+    if sine == 0.0 && cosine == 0.0 {
+        0.0
+    } else {
+        sine.atan2(cosine)
+    }
+}
+
+/// `vector` without its component along the unit `normal`.
+fn project(vector: &Vec3, normal: &Vec3) -> Vec3 {
+    math::sub(vector, &math::scale(normal, math::dot(vector, normal)))
+}
+
+fn normal(mesh: &TriangleMesh, vertex: usize) -> Vec3 {
+    let normal = &mesh.normals[vertex * 3..vertex * 3 + 3];
+    math::normalize(&[normal[0], normal[1], normal[2]])
+}
+
+fn texcoord(mesh: &TriangleMesh, vertex: usize) -> [f32; 2] {
+    [mesh.texcoords[vertex * 2], mesh.texcoords[vertex * 2 + 1]]
+}
+
+/// Append a copy of `vertex` of `from` to `to` with `normal`.
+fn push_vertex(to: &mut TriangleMesh, from: &TriangleMesh, vertex: usize, normal: Vec3) {
+    to.positions
+        .extend_from_slice(&from.positions[vertex * 3..vertex * 3 + 3]);
+    to.normals.extend_from_slice(&normal);
+    if !from.texcoords.is_empty() {
+        to.texcoords
+            .extend_from_slice(&from.texcoords[vertex * 2..vertex * 2 + 2]);
+    }
+    if !from.colors.is_empty() {
+        to.colors
+            .extend_from_slice(&from.colors[vertex * 4..vertex * 4 + 4]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cube from -1 to 1 sharing its eight corners, each face split
+    /// into two triangles along one diagonal.
+    fn shared_cube() -> TriangleMesh {
+        let mut positions = Vec::new();
+        for corner in 0..8 {
+            for axis in 0..3 {
+                positions.push(if corner & (1 << axis) == 0 { -1.0 } else { 1.0 });
+            }
+        }
+        let faces = [
+            [0, 2, 3, 1], // -z
+            [4, 5, 7, 6], // +z
+            [0, 1, 5, 4], // -y
+            [2, 6, 7, 3], // +y
+            [0, 4, 6, 2], // -x
+            [1, 3, 7, 5], // +x
+        ];
+        let indices = faces
+            .iter()
+            .flat_map(|&[a, b, c, d]| [a, b, c, a, c, d])
+            .collect();
+        TriangleMesh {
+            positions,
+            indices,
+            ..TriangleMesh::default()
+        }
+    }
+
+    fn normal_at(mesh: &TriangleMesh, vertex: usize) -> Vec3 {
+        [0, 1, 2].map(|axis| mesh.normals[vertex * 3 + axis])
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(
+            math::length(&math::sub(&a, &b)) < 1e-5,
+            "{:?} is not {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn flat_normals_give_every_triangle_its_own_corners() {
+        let cube = shared_cube();
+        let flat = flat_normals(&cube).unwrap();
+        assert_eq!(flat.vertex_count(), 36);
+        assert_eq!(flat.validate(), Ok(()));
+        for triangle in 0..flat.triangle_count() {
+            let normal = cube.face_normal(triangle);
+            // Each face normal points straight out along one axis.
+            assert_eq!(normal.iter().filter(|&&value| value != 0.0).count(), 1);
+            for vertex in flat.triangle(triangle) {
+                assert_eq!(normal_at(&flat, vertex), normal);
+                assert_eq!(
+                    flat.position(vertex),
+                    cube.position(cube.indices[vertex] as usize)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn angle_weighting_ignores_how_faces_are_split() {
+        // Every corner has one triangle on some faces and two on others,
+        // yet each face counts as a right angle and the normals come out
+        // along the diagonals.
+        let smooth = smooth_normals(&shared_cube(), PI).unwrap();
+        assert_eq!(smooth.vertex_count(), 8);
+        for vertex in 0..8 {
+            let diagonal = math::normalize(&smooth.position(vertex));
+            assert_close(normal_at(&smooth, vertex), diagonal);
+        }
+        let mut recomputed = shared_cube();
+        recomputed.recompute_normals();
+        assert_eq!(recomputed.normals, smooth.normals);
+    }
+
+    #[test]
+    fn creases_split_hard_edges() {
+        let cube = shared_cube();
+        let creased = smooth_normals(&cube, PI / 4.0).unwrap();
+        // Three normals at each corner, the first in place of the vertex.
+        assert_eq!(creased.vertex_count(), 24);
+        assert_eq!(&creased.positions[..24], &cube.positions[..]);
+        assert_eq!(creased.validate(), Ok(()));
+        for triangle in 0..creased.triangle_count() {
+            for vertex in creased.triangle(triangle) {
+                assert_close(normal_at(&creased, vertex), cube.face_normal(triangle));
+            }
+        }
+    }
+
+    #[test]
+    fn creases_leave_gentle_bends_smooth() {
+        // Two triangles meeting at 30 degrees along the y axis.
+        let (sin, cos) = (PI / 6.0).sin_cos();
+        let mesh = TriangleMesh {
+            positions: vec![0.0, 0.0, 0.0, 0.0, 1.0, 0.0, -1.0, 0.0, 0.0, cos, 0.0, sin],
+            indices: vec![0, 1, 2, 1, 0, 3],
+            texcoords: vec![0.0; 8],
+            ..TriangleMesh::default()
+        };
+        let smooth = smooth_normals(&mesh, PI / 4.0).unwrap();
+        assert_eq!(smooth.vertex_count(), 4);
+        assert_eq!(smooth.texcoords.len(), 8);
+        assert_close(normal_at(&smooth, 0), normal_at(&smooth, 1));
+        let hard = smooth_normals(&mesh, PI / 12.0).unwrap();
+        assert_eq!(hard.vertex_count(), 6);
+        assert_eq!(hard.texcoords.len(), 12);
+    }
+
+    /// A unit quad facing +z with the given texture coordinates.
+    fn quad(texcoords: Vec<f32>) -> TriangleMesh {
+        TriangleMesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+            normals: [0.0, 0.0, 1.0].repeat(4),
+            texcoords,
+            indices: vec![0, 1, 2, 0, 2, 3],
+            ..TriangleMesh::default()
+        }
+    }
+
+    fn assert_tangents(tangents: &[f32], expected: [f32; 4]) {
+        assert_eq!(tangents.len(), 16);
+        for tangent in tangents.chunks_exact(4) {
+            assert_close(
+                [tangent[0], tangent[1], tangent[2]],
+                [expected[0], expected[1], expected[2]],
+            );
+            assert_eq!(tangent[3], expected[3]);
+        }
+    }
+
+    #[test]
+    fn tangents_follow_u_with_handedness_in_w() {
+        let (_, tangents) =
+            compute_tangents(&quad(vec![0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0])).unwrap();
+        assert_tangents(&tangents, [1.0, 0.0, 0.0, 1.0]);
+        // Mirrored in u: the tangent turns round and so does w, keeping
+        // cross(normal, tangent) * w along +v.
+        let mirrored = quad(vec![1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+        let (_, tangents) = compute_tangents(&mirrored).unwrap();
+        assert_tangents(&tangents, [-1.0, 0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn tangents_lie_in_the_plane_of_tilted_normals() {
+        let mut mesh = quad(vec![0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0]);
+        let tilted = math::normalize(&[0.5, 0.0, 1.0]);
+        mesh.normals = tilted.repeat(4);
+        let (_, tangents) = compute_tangents(&mesh).unwrap();
+        for tangent in tangents.chunks_exact(4) {
+            let xyz = [tangent[0], tangent[1], tangent[2]];
+            assert!(math::dot(&xyz, &tilted).abs() < 1e-6);
+            assert!((math::length(&xyz) - 1.0).abs() < 1e-6);
+            assert!(xyz[0] > 0.0);
+        }
+    }
+
+    #[test]
+    fn tangents_need_normals_and_texcoords() {
+        let mut mesh = quad(Vec::new());
+        assert!(compute_tangents(&mesh).is_err());
+        mesh.texcoords = vec![0.0; 8];
+        mesh.normals.clear();
+        assert!(compute_tangents(&mesh).is_err());
+        // Texture coordinates that do not vary still give a frame.
+        mesh.normals = [0.0, 0.0, 1.0].repeat(4);
+        let (_, tangents) = compute_tangents(&mesh).unwrap();
+        assert_eq!(tangents.len(), 16);
+        assert!((math::length(&[tangents[0], tangents[1], tangents[2]]) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn normals_reject_invalid_meshes() {
+        let mut mesh = shared_cube();
+        mesh.indices.push(8);
+        assert!(flat_normals(&mesh).is_err());
+        assert!(smooth_normals(&mesh, PI).is_err());
+        mesh.indices.truncate(mesh.indices.len() - 1);
+        mesh.normals = vec![0.0; 5];
+        assert!(flat_normals(&mesh).is_err());
+        assert!(smooth_normals(&mesh, PI).is_err());
+    }
+
+    #[test]
+    fn mirrored_uvs_split_shared_vertices() {
+        // Two quads side by side sharing the edge from vertex 1 to 2, the
+        // right one mirrored in u like the two halves of a symmetric face.
+        let mesh = TriangleMesh {
+            positions: vec![
+                0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, //
+                2.0, 0.0, 0.0, 2.0, 1.0, 0.0,
+            ],
+            normals: [0.0, 0.0, 1.0].repeat(6),
+            texcoords: vec![0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            colors: vec![128; 24],
+            indices: vec![0, 1, 2, 0, 2, 3, 1, 4, 5, 1, 5, 2],
+        };
+        let (split, tangents) = compute_tangents(&mesh).unwrap();
+        assert_eq!(split.validate(), Ok(()));
+        // The shared edge's two vertices get a copy each, after the rest.
+        assert_eq!(split.vertex_count(), 8);
+        assert_eq!(&split.positions[..18], &mesh.positions[..]);
+        assert_eq!(split.colors.len(), 32);
+        assert_eq!(&split.indices[..6], &mesh.indices[..6]);
+        assert_eq!(split.indices[6..], [6, 4, 5, 6, 5, 7]);
+        assert_eq!(split.position(6), mesh.position(1));
+        assert_eq!(split.position(7), mesh.position(2));
+
+        for vertex in 0..split.vertex_count() {
+            let tangent = &tangents[vertex * 4..vertex * 4 + 4];
+            let expected = if vertex < 4 {
+                [1.0, 0.0, 0.0, 1.0]
+            } else {
+                [-1.0, 0.0, 0.0, -1.0]
+            };
+            assert_close(
+                [tangent[0], tangent[1], tangent[2]],
+                [expected[0], expected[1], expected[2]],
+            );
+            assert_eq!(tangent[3], expected[3], "vertex {}", vertex);
+        }
+    }
+
+    #[test]
+    fn consistent_uvs_split_nothing() {
+        let mesh = quad(vec![0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0]);
+        let (split, _) = compute_tangents(&mesh).unwrap();
+        assert_eq!(split, mesh);
+    }
+}
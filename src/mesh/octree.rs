@@ -0,0 +1,456 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+use std::ops::Range;
+
+use crate::math::{self, Vec3};
+
+use super::{Aabb, Frustum, PointCloud};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OctreeSettings {
+    /// Points a node keeps for itself. Nodes with more are thinned out to
+    /// this many, evenly spread, and the rest go to their children.
+    pub node_capacity: usize,
+    /// Nodes this deep keep every point they get.
+    pub max_depth: u32,
+}
+
+impl Default for OctreeSettings {
+    fn default() -> OctreeSettings {
+        OctreeSettings {
+            node_capacity: 16_384,
+            max_depth: 16,
+        }
+    }
+}
+
+/// A cube of an octree and the points it contributes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OctreeNode {
+    pub bounds: Aabb,
+    /// The node's own points, indices into `PointOctree::cloud`.
+    pub points: Range<usize>,
+    pub depth: u32,
+    pub children: Vec<usize>,
+}
+
+impl OctreeNode {
+    pub fn point_count(&self) -> usize {
+        self.points.len()
+    }
+}
+
+/// Nodes chosen for drawing and how many points they hold together.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LodSelection {
+    /// Coarsest and nearest first.
+    pub nodes: Vec<usize>,
+    pub points: usize,
+}
+
+/// A point cloud split into levels of detail.
+///
+/// Each node keeps an even sample of the points in its cube and hands the
+/// rest down to its eight children, so the root alone is a coarse preview
+/// and every level below fills in detail. The points are reordered so
+/// each node's are contiguous and can be uploaded as one slice.
+#[derive(Debug, Clone)]
+pub struct PointOctree {
+    cloud: PointCloud,
+    nodes: Vec<OctreeNode>,
+}
+
+impl PointOctree {
+    pub fn build(cloud: &PointCloud, settings: OctreeSettings) -> Result<PointOctree, String> {
+        if settings.node_capacity == 0 {
+            return Err("octree nodes must hold at least one point".to_string());
+        }
+        let mut builder = Builder {
+            cloud,
+            settings,
+            nodes: Vec::new(),
+            order: Vec::with_capacity(cloud.len()),
+        };
+        if !cloud.is_empty() {
+            let indices = (0..cloud.len() as u32).collect();
+            builder.build_node(indices, cube_around(&cloud.bounds()), 0);
+        }
+        Ok(PointOctree {
+            cloud: cloud.reordered(&builder.order),
+            nodes: builder.nodes,
+        })
+    }
+
+    /// The points in node order.
+    pub fn cloud(&self) -> &PointCloud {
+        &self.cloud
+    }
+
+    /// Every node, the root first. Empty for an empty cloud.
+    pub fn nodes(&self) -> &[OctreeNode] {
+        &self.nodes
+    }
+
+    pub fn node(&self, id: usize) -> &OctreeNode {
+        &self.nodes[id]
+    }
+
+    /// The nodes to draw from `eye`, both in the octree's space, within
+    /// `budget` points. Nodes inside `frustum` are taken in order of how
+    /// large they appear, each only after its parent, until the next one
+    /// would go over the budget. Nodes that do not fit are passed over
+    /// along with their children, letting smaller ones fill the rest.
+    pub fn select(&self, frustum: &Frustum, eye: &Vec3, budget: usize) -> LodSelection {
+        let mut selection = LodSelection::default();
+        let mut candidates = BinaryHeap::new();
+        let consider = |id: usize, candidates: &mut BinaryHeap<Candidate>| {
+            let node = &self.nodes[id];
+            if frustum.intersects_aabb(&node.bounds) {
+                candidates.push(Candidate {
+                    priority: apparent_size(&node.bounds, eye),
+                    node: id,
+                });
+            }
+        };
+        if !self.nodes.is_empty() {
+            consider(0, &mut candidates);
+        }
+        while let Some(Candidate { node: id, .. }) = candidates.pop() {
+            let node = &self.nodes[id];
+            if selection.points + node.point_count() > budget {
+                continue;
+            }
+            selection.nodes.push(id);
+            selection.points += node.point_count();
+            for &child in &node.children {
+                consider(child, &mut candidates);
+            }
+        }
+        selection
+    }
+}
+
+struct Builder<'a> {
+    cloud: &'a PointCloud,
+    settings: OctreeSettings,
+    nodes: Vec<OctreeNode>,
+    /// Original indices of the points in node order.
+    order: Vec<u32>,
+}
+
+impl Builder<'_> {
+    fn build_node(&mut self, indices: Vec<u32>, bounds: Aabb, depth: u32) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(OctreeNode {
+            bounds,
+            points: 0..0,
+            depth,
+            children: Vec::new(),
+        });
+
+        let (kept, rest) = if indices.len() <= self.settings.node_capacity
+            || depth >= self.settings.max_depth
+        {
+            (indices, Vec::new())
+        } else {
+            self.sample(indices, &bounds)
+        };
+        let start = self.order.len();
+        self.order.extend_from_slice(&kept);
+        self.nodes[id].points = start..self.order.len();
+
+        let center = bounds.center();
+        let mut octants = vec![Vec::new(); 8];
+        for index in rest {
+            let point = self.cloud.position(index as usize);
+            octants[octant(&center, &point)].push(index);
+        }
+        for (octant, indices) in octants.into_iter().enumerate() {
+            if !indices.is_empty() {
+                let child = self.build_node(indices, octant_bounds(&bounds, octant), depth + 1);
+                self.nodes[id].children.push(child);
+            }
+        }
+        id
+    }
+
+    /// Split `indices` into at most `node_capacity` points, one per cell
+    /// of a grid over `bounds`, and the rest.
+    fn sample(&self, indices: Vec<u32>, bounds: &Aabb) -> (Vec<u32>, Vec<u32>) {
+        let capacity = self.settings.node_capacity;
+        let cells = (capacity as f32).cbrt().ceil().max(1.0);
+        let cell_size = bounds.size()[0] / cells;
+        let last = cells as u32 - 1;
+        let mut occupied = HashSet::new();
+        let mut kept = Vec::with_capacity(capacity);
+        let mut rest = Vec::with_capacity(indices.len() - capacity);
+        for index in indices {
+            let point = self.cloud.position(index as usize);
+            let mut cell = [0u32; 3];
+            for (axis, cell) in cell.iter_mut().enumerate() {
+                let offset = (point[axis] - bounds.min[axis]) / cell_size;
+                *cell = (offset.max(0.0) as u32).min(last);
+            }
+            if kept.len() < capacity && occupied.insert(cell) {
+                kept.push(index);
+            } else {
+                rest.push(index);
+            }
+        }
+        (kept, rest)
+    }
+}
+
+/// The smallest cube around `aabb` sharing its center, so that every
+/// level of the octree has cubic cells.
+fn cube_around(aabb: &Aabb) -> Aabb {
+    let center = aabb.center();
+    let size = aabb.size();
+    let half = size[0].max(size[1]).max(size[2]) * 0.5;
+    Aabb {
+        min: [center[0] - half, center[1] - half, center[2] - half],
+        max: [center[0] + half, center[1] + half, center[2] + half],
+    }
+}
+
+/// Which eighth of a cube centered on `center` holds `point`: bit 0 set
+/// for the upper half in x, bit 1 in y, bit 2 in z.
+fn octant(center: &Vec3, point: &Vec3) -> usize {
+    (0..3)
+        .filter(|&axis| point[axis] >= center[axis])
+        .map(|axis| 1 << axis)
+        .sum()
+}
+
+fn octant_bounds(bounds: &Aabb, octant: usize) -> Aabb {
+    let center = bounds.center();
+    let mut child = Aabb {
+        min: bounds.min,
+        max: center,
+    };
+    for (axis, &middle) in center.iter().enumerate() {
+        if octant & (1 << axis) != 0 {
+            child.min[axis] = middle;
+            child.max[axis] = bounds.max[axis];
+        }
+    }
+    child
+}
+
+/// The node's radius over its distance from `eye`, proportional to its
+/// size on screen. Nodes around the eye come first of all.
+fn apparent_size(bounds: &Aabb, eye: &Vec3) -> f32 {
+    let radius = math::length(&bounds.size()) * 0.5;
+    let distance = math::length(&math::sub(&bounds.center(), eye)) - radius;
+    if distance <= 0.0 {
+        f32::INFINITY
+    } else {
+        radius / distance
+    }
+}
+
+/// A node waiting to be selected, ordered by priority for the max-heap.
+struct Candidate {
+    priority: f32,
+    node: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Candidate) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    /// Ties go to the earlier node, which is the coarser one.
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        self.priority
+            .total_cmp(&other.priority)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `side`³ grid of points one unit apart, colored by index.
+    fn grid(side: usize) -> PointCloud {
+        let mut positions = Vec::new();
+        let mut colors = Vec::new();
+        for z in 0..side {
+            for y in 0..side {
+                for x in 0..side {
+                    positions.extend_from_slice(&[x as f32, y as f32, z as f32]);
+                    let index = (positions.len() / 3 - 1) as u32;
+                    colors.extend_from_slice(&index.to_le_bytes());
+                }
+            }
+        }
+        PointCloud::new(positions, colors).unwrap()
+    }
+
+    fn settings(node_capacity: usize) -> OctreeSettings {
+        OctreeSettings {
+            node_capacity,
+            max_depth: 16,
+        }
+    }
+
+    fn contains(bounds: &Aabb, point: &Vec3) -> bool {
+        (0..3).all(|axis| bounds.min[axis] <= point[axis] && point[axis] <= bounds.max[axis])
+    }
+
+    fn perspective_from(eye: Vec3, target: Vec3) -> Frustum {
+        let mut projection = mat4::new_zero();
+        let fov = std::f32::consts::FRAC_PI_2;
+        mat4::perspective(&mut projection, &fov, &1.0, &0.1, &1000.0);
+        let view = math::look_at(&eye, &target, &[0.0, 1.0, 0.0]);
+        Frustum::from_matrix(&math::mul(&projection, &view)).unwrap()
+    }
+
+    #[test]
+    fn every_point_lands_in_exactly_one_node() {
+        let cloud = grid(10);
+        let octree = PointOctree::build(&cloud, settings(50)).unwrap();
+        let mut ranges: Vec<_> = octree.nodes().iter().map(|node| node.points.clone()).collect();
+        ranges.sort_by_key(|range| range.start);
+        let mut next = 0;
+        for range in ranges {
+            assert_eq!(range.start, next);
+            next = range.end;
+        }
+        assert_eq!(next, cloud.len());
+        // The colors encode the original index, so they show the points
+        // were permuted rather than lost or duplicated.
+        let mut seen: Vec<u32> = octree
+            .cloud()
+            .colors()
+            .chunks_exact(4)
+            .map(|color| u32::from_le_bytes([color[0], color[1], color[2], color[3]]))
+            .collect();
+        seen.sort_unstable();
+        assert_eq!(seen, (0..1000).collect::<Vec<u32>>());
+        for (index, original) in (0..cloud.len()).map(|i| (i, octree.cloud().color(i).unwrap())) {
+            let original = u32::from_le_bytes(original) as usize;
+            assert_eq!(octree.cloud().position(index), cloud.position(original));
+        }
+    }
+
+    #[test]
+    fn nodes_hold_their_points_and_respect_capacity() {
+        let octree = PointOctree::build(&grid(12), settings(64)).unwrap();
+        assert!(octree.nodes().len() > 1);
+        for node in octree.nodes() {
+            assert!(node.point_count() <= 64);
+            for index in node.points.clone() {
+                assert!(contains(&node.bounds, &octree.cloud().position(index)));
+            }
+            for &child in &node.children {
+                let child = octree.node(child);
+                assert_eq!(child.depth, node.depth + 1);
+                assert!(contains(&node.bounds, &child.bounds.min));
+                assert!(contains(&node.bounds, &child.bounds.max));
+            }
+        }
+    }
+
+    #[test]
+    fn the_root_is_an_even_preview() {
+        let octree = PointOctree::build(&grid(16), settings(8)).unwrap();
+        let root = octree.node(0);
+        assert_eq!(root.point_count(), 8);
+        // One point from each octant of the cloud.
+        let center = root.bounds.center();
+        let octants: HashSet<_> = root
+            .points
+            .clone()
+            .map(|index| octant(&center, &octree.cloud().position(index)))
+            .collect();
+        assert_eq!(octants.len(), 8);
+    }
+
+    #[test]
+    fn coincident_points_stop_at_the_depth_limit() {
+        let cloud = PointCloud::new(vec![1.0; 3 * 100], vec![]).unwrap();
+        let limited = OctreeSettings {
+            node_capacity: 10,
+            max_depth: 3,
+        };
+        let octree = PointOctree::build(&cloud, limited).unwrap();
+        let deepest = octree.nodes().iter().map(|node| node.depth).max();
+        assert_eq!(deepest, Some(3));
+        let total: usize = octree.nodes().iter().map(OctreeNode::point_count).sum();
+        assert_eq!(total, 100);
+    }
+
+    #[test]
+    fn empty_clouds_have_no_nodes() {
+        let octree = PointOctree::build(&PointCloud::default(), settings(8)).unwrap();
+        assert!(octree.nodes().is_empty());
+        let frustum = perspective_from([0.0, 0.0, 10.0], [0.0; 3]);
+        assert_eq!(octree.select(&frustum, &[0.0, 0.0, 10.0], 100), LodSelection::default());
+        assert!(PointOctree::build(&grid(2), settings(0)).is_err());
+    }
+
+    #[test]
+    fn selection_stays_within_budget_and_below_parents() {
+        let octree = PointOctree::build(&grid(16), settings(64)).unwrap();
+        let eye = [7.5, 7.5, 40.0];
+        let frustum = perspective_from(eye, [7.5, 7.5, 7.5]);
+        for budget in [0, 64, 500, 2000, 100_000] {
+            let selection = octree.select(&frustum, &eye, budget);
+            assert!(selection.points <= budget);
+            let total: usize = selection
+                .nodes
+                .iter()
+                .map(|&id| octree.node(id).point_count())
+                .sum();
+            assert_eq!(total, selection.points);
+            let selected: HashSet<_> = selection.nodes.iter().copied().collect();
+            for (id, node) in octree.nodes().iter().enumerate() {
+                for child in &node.children {
+                    assert!(!selected.contains(child) || selected.contains(&id));
+                }
+            }
+        }
+        // With room for everything, everything in view is drawn.
+        assert_eq!(octree.select(&frustum, &eye, 100_000).points, 16 * 16 * 16);
+    }
+
+    #[test]
+    fn nearer_detail_comes_first() {
+        // A long row of points seen from one end.
+        let positions = (0..4096).flat_map(|i| [i as f32 * 0.1, 0.0, 0.0]).collect();
+        let octree = PointOctree::build(&PointCloud::new(positions, vec![]).unwrap(), settings(32))
+            .unwrap();
+        let eye = [-5.0, 0.0, 0.0];
+        let frustum = perspective_from(eye, [1.0, 0.0, 0.0]);
+        let selection = octree.select(&frustum, &eye, 1024);
+        let near = selection
+            .nodes
+            .iter()
+            .flat_map(|&id| octree.node(id).points.clone())
+            .filter(|&index| octree.cloud().position(index)[0] < 409.6 / 2.0)
+            .count();
+        let far = selection.points - near;
+        assert!(near > 2 * far, "{} near against {} far", near, far);
+    }
+
+    #[test]
+    fn nodes_out_of_view_are_skipped() {
+        let octree = PointOctree::build(&grid(16), settings(64)).unwrap();
+        // Looking away from the cloud.
+        let eye = [7.5, 7.5, 40.0];
+        let frustum = perspective_from(eye, [7.5, 7.5, 80.0]);
+        assert!(octree.select(&frustum, &eye, 100_000).nodes.is_empty());
+    }
+}
@@ -0,0 +1,508 @@
+use super::bytes::{ByteReader, Endian};
+use super::PointCloud;
+
+/// Read a Point Cloud Library PCD file with `ascii`, `binary` or
+/// `binary_compressed` data.
+///
+/// `x`, `y` and `z` give the positions, `normal_x`, `normal_y` and
+/// `normal_z` the normals and a packed `rgb` or `rgba` field the colors.
+/// Other fields are skipped. Points whose position is not finite, as an
+/// organized cloud uses for missing returns, are dropped.
+pub fn read_pcd(data: &[u8]) -> Result<PointCloud, String> {
+    let mut reader = ByteReader::new(data);
+    let header = read_header(&mut reader)?;
+    let layout = Layout::new(&header.fields);
+    let count = header.points;
+    let data_len = layout
+        .stride
+        .checked_mul(count)
+        .ok_or_else(|| format!("PCD data for {} points is too large", count))?;
+
+    let table = match header.data {
+        DataFormat::Ascii => Table::Ascii(ascii_rows(&mut reader, &header, count)?),
+        DataFormat::Binary => {
+            let bytes = reader.take(data_len)?;
+            Table::Binary {
+                bytes: bytes.to_vec(),
+                columns: false,
+            }
+        }
+        DataFormat::BinaryCompressed => {
+            let compressed_len = reader.u32(Endian::Little)? as usize;
+            let raw_len = reader.u32(Endian::Little)? as usize;
+            if raw_len != data_len {
+                return Err(format!(
+                    "PCD data unpacks to {} bytes, {} expected for {} points",
+                    raw_len, data_len, count
+                ));
+            }
+            let bytes = lzf_decompress(reader.take(compressed_len)?, raw_len)?;
+            Table::Binary {
+                bytes,
+                columns: true,
+            }
+        }
+    };
+
+    let find = |name: &str| header.fields.iter().position(|field| field.name == name);
+    let position = [find("x"), find("y"), find("z")];
+    let position = match position {
+        [Some(x), Some(y), Some(z)] => [x, y, z],
+        _ => return Err("PCD points need x, y and z fields".to_string()),
+    };
+    let normal = match [find("normal_x"), find("normal_y"), find("normal_z")] {
+        [Some(x), Some(y), Some(z)] => Some([x, y, z]),
+        _ => None,
+    };
+    let color = find("rgba")
+        .map(|field| (field, true))
+        .or_else(|| find("rgb").map(|field| (field, false)));
+    if let Some((field, _)) = color {
+        if header.fields[field].size != 4 {
+            return Err("PCD colors must be packed into 4 bytes".to_string());
+        }
+    }
+
+    let mut positions = Vec::with_capacity(count * 3);
+    let mut normals = Vec::new();
+    let mut colors = Vec::new();
+    for point in 0..count {
+        let xyz = position.map(|field| table.value(&header, &layout, point, field) as f32);
+        if !xyz.iter().all(|value| value.is_finite()) {
+            continue;
+        }
+        positions.extend_from_slice(&xyz);
+        if let Some(fields) = normal {
+            normals.extend(
+                fields
+                    .iter()
+                    .map(|&field| table.value(&header, &layout, point, field) as f32),
+            );
+        }
+        if let Some((field, has_alpha)) = color {
+            let packed = table.packed(&header, &layout, point, field)?;
+            let [blue, green, red, alpha] = packed.to_le_bytes();
+            colors.extend_from_slice(&[red, green, blue, if has_alpha { alpha } else { 255 }]);
+        }
+    }
+
+    let cloud = PointCloud::new(positions, colors)?;
+    if normal.is_some() {
+        cloud.with_normals(normals)
+    } else {
+        Ok(cloud)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DataFormat {
+    Ascii,
+    Binary,
+    BinaryCompressed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Signed,
+    Unsigned,
+    Float,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Field {
+    name: String,
+    size: usize,
+    kind: Kind,
+    count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Header {
+    fields: Vec<Field>,
+    points: usize,
+    data: DataFormat,
+}
+
+fn read_header(reader: &mut ByteReader) -> Result<Header, String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut sizes = Vec::new();
+    let mut kinds = Vec::new();
+    let mut counts = Vec::new();
+    let mut width = None;
+    let mut height = 1;
+    let mut points = None;
+    let data = loop {
+        let line = reader.line()?.ok_or("PCD header has no DATA line")?;
+        let mut words = line.split_whitespace();
+        let key = match words.next() {
+            Some(key) if !key.starts_with('#') => key,
+            _ => continue,
+        };
+        let values: Vec<_> = words.collect();
+        let number = |value: &str| {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("invalid number \"{}\" in PCD line \"{}\"", value, line))
+        };
+        let single = || match values.as_slice() {
+            [value] => number(value),
+            _ => Err(format!("PCD line \"{}\" needs one value", line)),
+        };
+        match key {
+            "VERSION" | "VIEWPOINT" => {}
+            "FIELDS" => names = values.iter().map(|name| name.to_string()).collect(),
+            "SIZE" => {
+                sizes = values
+                    .iter()
+                    .map(|&value| number(value))
+                    .collect::<Result<_, _>>()?
+            }
+            "COUNT" => {
+                counts = values
+                    .iter()
+                    .map(|&value| number(value))
+                    .collect::<Result<_, _>>()?
+            }
+            "TYPE" => {
+                kinds = values
+                    .iter()
+                    .map(|&kind| match kind {
+                        "I" => Ok(Kind::Signed),
+                        "U" => Ok(Kind::Unsigned),
+                        "F" => Ok(Kind::Float),
+                        _ => Err(format!("unknown PCD field type \"{}\"", kind)),
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            "WIDTH" => width = Some(single()?),
+            "HEIGHT" => height = single()?,
+            "POINTS" => points = Some(single()?),
+            "DATA" => {
+                break match values.as_slice() {
+                    ["ascii"] => DataFormat::Ascii,
+                    ["binary"] => DataFormat::Binary,
+                    ["binary_compressed"] => DataFormat::BinaryCompressed,
+                    _ => return Err(format!("unsupported PCD data \"{}\"", line)),
+                };
+            }
+            _ => return Err(format!("unknown PCD header line \"{}\"", line)),
+        }
+    };
+
+    if counts.is_empty() {
+        counts = vec![1; names.len()];
+    }
+    if sizes.len() != names.len() || kinds.len() != names.len() || counts.len() != names.len() {
+        return Err(format!(
+            "PCD header has {} fields but {} sizes, {} types and {} counts",
+            names.len(),
+            sizes.len(),
+            kinds.len(),
+            counts.len()
+        ));
+    }
+    let mut fields = Vec::with_capacity(names.len());
+    for (((name, size), kind), count) in names.into_iter().zip(sizes).zip(kinds).zip(counts) {
+        let valid = match kind {
+            Kind::Float => size == 4 || size == 8,
+            Kind::Signed | Kind::Unsigned => matches!(size, 1 | 2 | 4 | 8),
+        };
+        if !valid || count == 0 {
+            return Err(format!(
+                "PCD field \"{}\" has an unsupported size {} or count {}",
+                name, size, count
+            ));
+        }
+        fields.push(Field {
+            name,
+            size,
+            kind,
+            count,
+        });
+    }
+    let points = match (points, width) {
+        (Some(points), _) => points,
+        (None, Some(width)) => width
+            .checked_mul(height)
+            .ok_or_else(|| format!("PCD cloud of {} by {} points is too large", width, height))?,
+        (None, None) => return Err("PCD header gives no point count".to_string()),
+    };
+    Ok(Header {
+        fields,
+        points,
+        data,
+    })
+}
+
+/// Where each field starts within a point's bytes.
+#[derive(Debug)]
+struct Layout {
+    offsets: Vec<usize>,
+    /// Bytes per point.
+    stride: usize,
+}
+
+impl Layout {
+    fn new(fields: &[Field]) -> Layout {
+        let mut offsets = Vec::with_capacity(fields.len());
+        let mut stride = 0;
+        for field in fields {
+            offsets.push(stride);
+            stride += field.size * field.count;
+        }
+        Layout { offsets, stride }
+    }
+}
+
+/// The points' values, read as text or still packed as bytes.
+enum Table<'a> {
+    /// Each point's tokens, one per field element.
+    Ascii(Vec<Vec<&'a str>>),
+    /// Little-endian values laid out point by point, or field by field
+    /// when `columns` is set as in compressed data.
+    Binary { bytes: Vec<u8>, columns: bool },
+}
+
+impl Table<'_> {
+    /// The first element of `field` of `point`. Values that do not parse
+    /// come out as NaN.
+    fn value(&self, header: &Header, layout: &Layout, point: usize, field: usize) -> f64 {
+        match self {
+            Table::Ascii(rows) => rows[point][token_index(header, field)]
+                .parse()
+                .unwrap_or(f64::NAN),
+            Table::Binary { .. } => {
+                let bytes = self.bytes(header, layout, point, field);
+                let mut raw = [0; 8];
+                raw[..bytes.len()].copy_from_slice(bytes);
+                let field = &header.fields[field];
+                match (field.kind, field.size) {
+                    (Kind::Float, 4) => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    (Kind::Float, _) => f64::from_le_bytes(raw),
+                    (Kind::Unsigned, _) => u64::from_le_bytes(raw) as f64,
+                    (Kind::Signed, size) => {
+                        let shift = 64 - size as u32 * 8;
+                        ((i64::from_le_bytes(raw) << shift) >> shift) as f64
+                    }
+                }
+            }
+        }
+    }
+
+    /// The four bytes of a packed color field as an integer, whatever type
+    /// the file gives it.
+    fn packed(
+        &self,
+        header: &Header,
+        layout: &Layout,
+        point: usize,
+        field: usize,
+    ) -> Result<u32, String> {
+        match self {
+            Table::Ascii(rows) => {
+                let token = rows[point][token_index(header, field)];
+                let packed = match header.fields[field].kind {
+                    Kind::Float => token.parse::<f32>().map(f32::to_bits).ok(),
+                    Kind::Unsigned => token.parse::<u32>().ok(),
+                    Kind::Signed => token.parse::<i32>().map(|value| value as u32).ok(),
+                };
+                packed.ok_or_else(|| format!("invalid PCD color \"{}\"", token))
+            }
+            Table::Binary { .. } => {
+                let bytes = self.bytes(header, layout, point, field);
+                Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+        }
+    }
+
+    fn bytes(&self, header: &Header, layout: &Layout, point: usize, field: usize) -> &[u8] {
+        let (bytes, columns) = match self {
+            Table::Binary { bytes, columns } => (bytes, *columns),
+            Table::Ascii(_) => unreachable!("ASCII values have no bytes"),
+        };
+        let size = header.fields[field].size;
+        let start = if columns {
+            layout.offsets[field] * header.points + point * size * header.fields[field].count
+        } else {
+            point * layout.stride + layout.offsets[field]
+        };
+        &bytes[start..start + size]
+    }
+}
+
+/// Which token of an ASCII row holds the first element of `field`.
+fn token_index(header: &Header, field: usize) -> usize {
+    header.fields[..field].iter().map(|field| field.count).sum()
+}
+
+fn ascii_rows<'a>(
+    reader: &mut ByteReader<'a>,
+    header: &Header,
+    count: usize,
+) -> Result<Vec<Vec<&'a str>>, String> {
+    let tokens = token_index(header, header.fields.len());
+    // A row takes at least a byte, whatever the header claims.
+    let mut rows = Vec::with_capacity(count.min(reader.remaining().len()));
+    while rows.len() < count {
+        let line = reader
+            .line()?
+            .ok_or_else(|| format!("PCD data ends after {} of {} points", rows.len(), count))?;
+        let row: Vec<_> = line.split_whitespace().collect();
+        if row.is_empty() {
+            continue;
+        }
+        if row.len() != tokens {
+            return Err(format!(
+                "PCD point {} has {} values, {} expected",
+                rows.len(),
+                row.len(),
+                tokens
+            ));
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Undo the LZF compression of `binary_compressed` PCD data.
+fn lzf_decompress(input: &[u8], output_len: usize) -> Result<Vec<u8>, String> {
+    let truncated = || "truncated LZF data".to_string();
+    // A three-byte back reference copies at most 264 bytes, so no more
+    // than that can come out of the input, whatever its header claims.
+    let mut output = Vec::with_capacity(output_len.min(input.len().saturating_mul(88)));
+    let mut at = 0;
+    while at < input.len() {
+        let control = input[at] as usize;
+        at += 1;
+        if control < 32 {
+            // A run of control + 1 literal bytes.
+            let literal = input.get(at..at + control + 1).ok_or_else(truncated)?;
+            output.extend_from_slice(literal);
+            at += literal.len();
+        } else {
+            // A copy of earlier output: a 3-bit length, extended by a
+            // byte when full, and a 13-bit distance back.
+            let mut len = control >> 5;
+            if len == 7 {
+                len += *input.get(at).ok_or_else(truncated)? as usize;
+                at += 1;
+            }
+            len += 2;
+            let low = *input.get(at).ok_or_else(truncated)? as usize;
+            at += 1;
+            let distance = ((control & 0x1f) << 8) + low + 1;
+            if distance > output.len() {
+                return Err("LZF data refers back before its start".to_string());
+            }
+            let start = output.len() - distance;
+            // Copies may overlap what they produce, so go byte by byte.
+            for index in start..start + len {
+                output.push(output[index]);
+            }
+        }
+        if output.len() > output_len {
+            break;
+        }
+    }
+    if output.len() != output_len {
+        return Err(format!(
+            "LZF data unpacks to {} bytes, {} expected",
+            output.len(),
+            output_len
+        ));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::tests::fixture;
+
+    #[test]
+    fn ascii_points_with_packed_colors() {
+        let cloud = read_pcd(&fixture("points_ascii.pcd")).unwrap();
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.position(1), [1.0, 2.0, 3.0]);
+        assert_eq!(cloud.color(0), Some([255, 0, 0, 255]));
+        assert_eq!(cloud.color(2), Some([0, 0, 255, 255]));
+        assert!(!cloud.has_normals());
+    }
+
+    #[test]
+    fn binary_matches_ascii_and_keeps_normals() {
+        let cloud = read_pcd(&fixture("points_binary.pcd")).unwrap();
+        let ascii = read_pcd(&fixture("points_ascii.pcd")).unwrap();
+        assert_eq!(cloud.positions(), ascii.positions());
+        assert_eq!(cloud.colors(), ascii.colors());
+        assert_eq!(&cloud.normals()[..3], [0.0, 0.0, 1.0]);
+        assert_eq!(cloud.normals().len(), 9);
+    }
+
+    #[test]
+    fn compressed_organized_cloud_drops_missing_points() {
+        // A 2 by 2 organized cloud with one NaN point.
+        let cloud = read_pcd(&fixture("points_compressed.pcd")).unwrap();
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.position(2), [1.0, 1.0, 0.0]);
+        // rgba keeps the alpha of the high byte.
+        assert_eq!(cloud.color(0), Some([10, 20, 30, 128]));
+    }
+
+    #[test]
+    fn lzf_back_references_may_overlap() {
+        // "ab", then 6 bytes copied from 2 back.
+        let data = [1, b'a', b'b', 4 << 5, 1];
+        assert_eq!(lzf_decompress(&data, 8).unwrap(), b"abababab");
+        assert!(lzf_decompress(&data, 9).is_err());
+        assert!(lzf_decompress(&[4 << 5, 1], 6).is_err());
+    }
+
+    #[test]
+    fn header_errors_are_reported() {
+        assert!(read_pcd(b"FIELDS x y z\n").is_err());
+        let mismatched = b"FIELDS x y z\nSIZE 4 4\nTYPE F F F\nPOINTS 0\nDATA ascii\n";
+        assert!(read_pcd(mismatched).is_err());
+        let no_xyz = b"FIELDS x y\nSIZE 4 4\nTYPE F F\nPOINTS 0\nDATA ascii\n";
+        assert!(read_pcd(no_xyz).is_err());
+        let short = b"FIELDS x y z\nSIZE 4 4 4\nTYPE F F F\nPOINTS 2\nDATA ascii\n0 0 0\n";
+        assert!(read_pcd(short).is_err());
+    }
+
+    #[test]
+    fn truncated_headers_are_reported() {
+        assert!(read_pcd(b"").is_err());
+        assert!(
+            read_pcd(b"VERSION 0.7\nFIELDS x y z\nSIZE 4 4 4\nTYPE F F F\nPOINTS 1\n").is_err()
+        );
+        assert!(read_pcd(b"VERSION 0.7\nFIELDS x y z\nSIZE 4 4 4\nTYPE F F F\nPOINTS").is_err());
+    }
+
+    #[test]
+    fn huge_counts_fail_instead_of_allocating() {
+        let fields = "FIELDS x y z\nSIZE 4 4 4\nTYPE F F F\n";
+        for points in [
+            "100000000000",
+            "6148914691236517206",
+            "18446744073709551615",
+        ] {
+            for data in ["ascii", "binary", "binary_compressed"] {
+                let file = format!("{}POINTS {}\nDATA {}\n0 0 0\n", fields, points, data);
+                assert!(read_pcd(file.as_bytes()).is_err());
+            }
+        }
+        let organized = format!(
+            "{}WIDTH 4294967296\nHEIGHT 4294967296\nDATA binary\n",
+            fields
+        );
+        assert!(read_pcd(organized.as_bytes())
+            .unwrap_err()
+            .contains("too large"));
+        // A compressed block claiming the data of 2^28 points.
+        let mut file = format!("{}POINTS 268435456\nDATA binary_compressed\n", fields).into_bytes();
+        file.extend_from_slice(&2u32.to_le_bytes());
+        file.extend_from_slice(&(12u32 << 28).to_le_bytes());
+        file.extend_from_slice(&[0, 0]);
+        assert!(read_pcd(&file).is_err());
+    }
+}
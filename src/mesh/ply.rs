@@ -0,0 +1,530 @@
+use std::str::SplitAsciiWhitespace;
+
+use super::bytes::{ByteReader, Endian};
+use super::{PointCloud, TriangleMesh};
+
+/// What a PLY file holds: a mesh if it has faces, otherwise points.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlyGeometry {
+    Mesh(TriangleMesh),
+    Points(PointCloud),
+}
+
+/// Read a PLY file in any of its three formats: ASCII, binary little
+/// endian or binary big endian.
+///
+/// The `vertex` element gives the positions (`x`, `y`, `z`) and, when
+/// present, normals (`nx`, `ny`, `nz`), texture coordinates (`u`, `v` or
+/// `s`, `t`) and colors (`red`, `green`, `blue`, `alpha`, as bytes or as
+/// floats from 0 to 1). Polygons of the `face` element's `vertex_indices`
+/// are split into triangle fans. Other properties and elements are
+/// skipped.
+pub fn read_ply(data: &[u8]) -> Result<PlyGeometry, String> {
+    let mut reader = ByteReader::new(data);
+    let header = read_header(&mut reader)?;
+    let mut body = match header.format {
+        Format::Ascii => {
+            let text = std::str::from_utf8(reader.remaining())
+                .map_err(|_| "ASCII PLY body is not text".to_string())?;
+            Body::Ascii {
+                words: text.split_ascii_whitespace(),
+                len: text.len(),
+            }
+        }
+        Format::Binary(endian) => Body::Binary(reader, endian),
+    };
+
+    let mut vertices = None;
+    let mut faces = None;
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => vertices = Some(read_vertices(&mut body, element)?),
+            "face" => faces = Some(read_faces(&mut body, element)?),
+            _ => skip_element(&mut body, element)?,
+        }
+    }
+    let vertices = vertices.ok_or("PLY file has no vertex element")?;
+
+    match faces {
+        Some(indices) if !indices.is_empty() => {
+            let mesh = TriangleMesh {
+                positions: vertices.positions,
+                normals: vertices.normals,
+                texcoords: vertices.texcoords,
+                colors: vertices.colors,
+                indices,
+            };
+            mesh.validate()?;
+            Ok(PlyGeometry::Mesh(mesh))
+        }
+        _ => {
+            let cloud = PointCloud::new(vertices.positions, vertices.colors)?;
+            if vertices.normals.is_empty() {
+                Ok(PlyGeometry::Points(cloud))
+            } else {
+                Ok(PlyGeometry::Points(cloud.with_normals(vertices.normals)?))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    Binary(Endian),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<ScalarType, String> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return Err(format!("unknown PLY property type \"{}\"", name)),
+        })
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, ScalarType::F32 | ScalarType::F64)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PropertyKind {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+fn read_header(reader: &mut ByteReader) -> Result<Header, String> {
+    if reader.line()? != Some("ply") {
+        return Err("not a PLY file".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        let line = reader.line()?.ok_or("PLY header has no end_header")?;
+        let words: Vec<_> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", "1.0"] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", "1.0"] => {
+                format = Some(Format::Binary(Endian::Little))
+            }
+            ["format", "binary_big_endian", "1.0"] => format = Some(Format::Binary(Endian::Big)),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("invalid PLY element count in \"{}\"", line))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let count = ScalarType::parse(count)?;
+                if count.is_float() {
+                    return Err(format!("PLY list counts must be integers in \"{}\"", line));
+                }
+                let kind = PropertyKind::List {
+                    count,
+                    item: ScalarType::parse(item)?,
+                };
+                push_property(&mut elements, name, kind)?;
+            }
+            ["property", kind, name] => {
+                let kind = PropertyKind::Scalar(ScalarType::parse(kind)?);
+                push_property(&mut elements, name, kind)?;
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            ["end_header"] => break,
+            _ => return Err(format!("unsupported PLY header line \"{}\"", line)),
+        }
+    }
+    Ok(Header {
+        format: format.ok_or("PLY header has no format line")?,
+        elements,
+    })
+}
+
+fn push_property(elements: &mut [Element], name: &str, kind: PropertyKind) -> Result<(), String> {
+    let element = elements
+        .last_mut()
+        .ok_or_else(|| format!("PLY property \"{}\" comes before any element", name))?;
+    element.properties.push(Property {
+        name: name.to_string(),
+        kind,
+    });
+    Ok(())
+}
+
+/// Where the values of the elements come from.
+enum Body<'a> {
+    /// The words of the text after the header, `len` bytes of it.
+    Ascii {
+        words: SplitAsciiWhitespace<'a>,
+        len: usize,
+    },
+    Binary(ByteReader<'a>, Endian),
+}
+
+impl Body<'_> {
+    /// `rows` capped by how many the body could still hold, each taking
+    /// at least a byte, so a corrupt count cannot reserve more memory
+    /// than the file is worth.
+    fn rows_that_fit(&self, rows: usize) -> usize {
+        match self {
+            Body::Ascii { len, .. } => rows.min(*len),
+            Body::Binary(reader, _) => rows.min(reader.remaining().len()),
+        }
+    }
+
+    fn scalar(&mut self, ty: ScalarType) -> Result<f64, String> {
+        match self {
+            Body::Ascii { words, .. } => {
+                let word = words.next().ok_or("ASCII PLY body ends early")?;
+                word.parse()
+                    .map_err(|_| format!("invalid number \"{}\" in PLY body", word))
+            }
+            Body::Binary(reader, endian) => Ok(match ty {
+                ScalarType::I8 => reader.i8()? as f64,
+                ScalarType::U8 => reader.u8()? as f64,
+                ScalarType::I16 => reader.i16(*endian)? as f64,
+                ScalarType::U16 => reader.u16(*endian)? as f64,
+                ScalarType::I32 => reader.i32(*endian)? as f64,
+                ScalarType::U32 => reader.u32(*endian)? as f64,
+                ScalarType::F32 => reader.f32(*endian)? as f64,
+                ScalarType::F64 => reader.f64(*endian)?,
+            }),
+        }
+    }
+
+    fn list_len(&mut self, count: ScalarType) -> Result<usize, String> {
+        let len = self.scalar(count)?;
+        if len < 0.0 || len.fract() != 0.0 {
+            return Err(format!("invalid PLY list length {}", len));
+        }
+        Ok(len as usize)
+    }
+
+    /// Read a property and drop it.
+    fn skip(&mut self, kind: &PropertyKind) -> Result<(), String> {
+        match *kind {
+            PropertyKind::Scalar(ty) => self.scalar(ty).map(|_| ()),
+            PropertyKind::List { count, item } => {
+                for _ in 0..self.list_len(count)? {
+                    self.scalar(item)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn skip_element(body: &mut Body, element: &Element) -> Result<(), String> {
+    if element.properties.is_empty() {
+        // Rows of nothing: there is nothing to read, however many.
+        return Ok(());
+    }
+    for _ in 0..element.count {
+        for property in &element.properties {
+            body.skip(&property.kind)?;
+        }
+    }
+    Ok(())
+}
+
+/// What a vertex property holds, with the component it fills.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Position(usize),
+    Normal(usize),
+    Texcoord(usize),
+    Color(usize),
+    Ignored,
+}
+
+impl Role {
+    fn of(name: &str) -> Role {
+        match name {
+            "x" => Role::Position(0),
+            "y" => Role::Position(1),
+            "z" => Role::Position(2),
+            "nx" | "normal_x" => Role::Normal(0),
+            "ny" | "normal_y" => Role::Normal(1),
+            "nz" | "normal_z" => Role::Normal(2),
+            "u" | "s" | "texture_u" | "texture_s" => Role::Texcoord(0),
+            "v" | "t" | "texture_v" | "texture_t" => Role::Texcoord(1),
+            "red" | "r" | "diffuse_red" => Role::Color(0),
+            "green" | "g" | "diffuse_green" => Role::Color(1),
+            "blue" | "b" | "diffuse_blue" => Role::Color(2),
+            "alpha" | "a" => Role::Color(3),
+            _ => Role::Ignored,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Vertices {
+    positions: Vec<f32>,
+    normals: Vec<f32>,
+    texcoords: Vec<f32>,
+    colors: Vec<u8>,
+}
+
+fn read_vertices(body: &mut Body, element: &Element) -> Result<Vertices, String> {
+    let roles: Vec<_> = element
+        .properties
+        .iter()
+        .map(|property| match property.kind {
+            PropertyKind::Scalar(_) => Role::of(&property.name),
+            PropertyKind::List { .. } => Role::Ignored,
+        })
+        .collect();
+    let has = |wanted: &[Role]| wanted.iter().all(|role| roles.contains(role));
+    if !has(&[Role::Position(0), Role::Position(1), Role::Position(2)]) {
+        return Err("PLY vertices need x, y and z".to_string());
+    }
+    let has_normals = has(&[Role::Normal(0), Role::Normal(1), Role::Normal(2)]);
+    let has_texcoords = has(&[Role::Texcoord(0), Role::Texcoord(1)]);
+    let has_colors = has(&[Role::Color(0), Role::Color(1), Role::Color(2)]);
+
+    let count = element.count;
+    let mut vertices = Vertices {
+        positions: Vec::with_capacity(body.rows_that_fit(count) * 3),
+        ..Vertices::default()
+    };
+    for _ in 0..count {
+        let mut position = [0.0; 3];
+        let mut normal = [0.0; 3];
+        let mut texcoord = [0.0; 2];
+        let mut color = [255; 4];
+        for (property, role) in element.properties.iter().zip(&roles) {
+            let ty = match (&property.kind, role) {
+                (PropertyKind::Scalar(ty), role) if *role != Role::Ignored => *ty,
+                (kind, _) => {
+                    body.skip(kind)?;
+                    continue;
+                }
+            };
+            let value = body.scalar(ty)?;
+            match *role {
+                Role::Position(axis) => position[axis] = value as f32,
+                Role::Normal(axis) => normal[axis] = value as f32,
+                Role::Texcoord(axis) => texcoord[axis] = value as f32,
+                Role::Color(channel) => color[channel] = color_byte(value, ty),
+                Role::Ignored => {}
+            }
+        }
+        vertices.positions.extend_from_slice(&position);
+        if has_normals {
+            vertices.normals.extend_from_slice(&normal);
+        }
+        if has_texcoords {
+            vertices.texcoords.extend_from_slice(&texcoord);
+        }
+        if has_colors {
+            vertices.colors.extend_from_slice(&color);
+        }
+    }
+    Ok(vertices)
+}
+
+/// A color channel as a byte: floats run from 0 to 1, 16-bit channels are
+/// scaled down and anything else is taken as a byte already.
+fn color_byte(value: f64, ty: ScalarType) -> u8 {
+    let value = match ty {
+        ScalarType::F32 | ScalarType::F64 => value * 255.0,
+        ScalarType::U16 => value / 257.0,
+        _ => value,
+    };
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+/// The faces' polygons as triangle fans. Polygons of fewer than three
+/// vertices are dropped.
+fn read_faces(body: &mut Body, element: &Element) -> Result<Vec<u32>, String> {
+    let mut indices = Vec::with_capacity(body.rows_that_fit(element.count) * 3);
+    let mut polygon = Vec::new();
+    if element.properties.is_empty() {
+        return Ok(indices);
+    }
+    for _ in 0..element.count {
+        for property in &element.properties {
+            match property.kind {
+                PropertyKind::List { count, item }
+                    if property.name == "vertex_indices" || property.name == "vertex_index" =>
+                {
+                    polygon.clear();
+                    for _ in 0..body.list_len(count)? {
+                        let index = body.scalar(item)?;
+                        if index < 0.0 || index.fract() != 0.0 || index > u32::MAX as f64 {
+                            return Err(format!("invalid PLY vertex index {}", index));
+                        }
+                        polygon.push(index as u32);
+                    }
+                    for corner in 1..polygon.len().saturating_sub(1) {
+                        indices.extend_from_slice(&[
+                            polygon[0],
+                            polygon[corner],
+                            polygon[corner + 1],
+                        ]);
+                    }
+                }
+                ref kind => body.skip(kind)?,
+            }
+        }
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::tests::fixture;
+
+    fn mesh(name: &str) -> TriangleMesh {
+        match read_ply(&fixture(name)).unwrap() {
+            PlyGeometry::Mesh(mesh) => mesh,
+            PlyGeometry::Points(_) => panic!("{} read as points", name),
+        }
+    }
+
+    /// A unit square pyramid: its base as one quad, its sides as
+    /// triangles.
+    fn pyramid() -> TriangleMesh {
+        mesh("pyramid_ascii.ply")
+    }
+
+    #[test]
+    fn ascii_mesh_with_normals_and_colors() {
+        let mesh = pyramid();
+        assert_eq!(mesh.vertex_count(), 5);
+        assert_eq!(mesh.position(4), [0.5, 1.0, 0.5]);
+        assert_eq!(&mesh.normals[12..15], [0.0, 1.0, 0.0]);
+        assert_eq!(&mesh.colors[16..20], [255, 255, 255, 255]);
+        assert_eq!(&mesh.colors[0..4], [255, 0, 0, 255]);
+        assert!(mesh.texcoords.is_empty());
+        // The quad base becomes a fan of two triangles.
+        assert_eq!(mesh.triangle_count(), 6);
+        assert_eq!(&mesh.indices[..6], [0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn binary_meshes_match_ascii() {
+        assert_eq!(mesh("pyramid_binary_le.ply"), pyramid());
+        assert_eq!(mesh("pyramid_binary_be.ply"), pyramid());
+    }
+
+    #[test]
+    fn vertices_without_faces_are_points() {
+        let cloud = match read_ply(&fixture("points_ascii.ply")).unwrap() {
+            PlyGeometry::Points(cloud) => cloud,
+            PlyGeometry::Mesh(_) => panic!("points read as a mesh"),
+        };
+        assert_eq!(cloud.len(), 3);
+        assert_eq!(cloud.position(2), [0.0, 0.0, 3.0]);
+        // Float colors are scaled to bytes; a missing alpha is opaque.
+        assert_eq!(cloud.color(0), Some([255, 128, 0, 255]));
+        assert!(!cloud.has_normals());
+    }
+
+    #[test]
+    fn header_errors_are_reported() {
+        assert!(read_ply(b"obj\n").is_err());
+        assert!(read_ply(b"ply\nformat ascii 1.0\nelement vertex 1\n").is_err());
+        let no_format = b"ply\nelement vertex 0\nproperty float x\nend_header\n";
+        assert!(read_ply(no_format).is_err());
+        let bad_type = b"ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\nend_header\n";
+        assert!(read_ply(bad_type).is_err());
+    }
+
+    #[test]
+    fn truncated_bodies_are_reported() {
+        let mut data = fixture("pyramid_binary_le.ply");
+        data.truncate(data.len() - 3);
+        assert!(read_ply(&data).unwrap_err().contains("end of data"));
+        let ascii = b"ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\n\
+            property float y\nproperty float z\nend_header\n0 0 0\n1 1\n";
+        assert!(read_ply(ascii).is_err());
+    }
+
+    #[test]
+    fn truncated_headers_are_reported() {
+        assert!(read_ply(b"").is_err());
+        assert!(read_ply(b"ply\nformat ascii 1.0\nelement vertex").is_err());
+        assert!(read_ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float").is_err());
+    }
+
+    #[test]
+    fn huge_counts_fail_instead_of_allocating() {
+        for count in [
+            "100000000000",
+            "6148914691236517206",
+            "18446744073709551615",
+        ] {
+            for format in ["ascii", "binary_little_endian"] {
+                let header = format!(
+                    "ply\nformat {} 1.0\nelement vertex {}\nproperty float x\n\
+                     property float y\nproperty float z\nend_header\n0 0 0\n",
+                    format, count
+                );
+                assert!(read_ply(header.as_bytes()).is_err());
+            }
+            let faces = format!(
+                "ply\nformat ascii 1.0\nelement vertex 0\nproperty float x\n\
+                 property float y\nproperty float z\nelement face {}\n\
+                 property list uchar int vertex_indices\nend_header\n3 0 1 2\n",
+                count
+            );
+            assert!(read_ply(faces.as_bytes()).is_err());
+            let empty_rows = format!(
+                "ply\nformat ascii 1.0\nelement nothing {}\nelement vertex 0\n\
+                 property float x\nproperty float y\nproperty float z\nend_header\n",
+                count
+            );
+            assert!(read_ply(empty_rows.as_bytes()).is_ok());
+        }
+    }
+
+    #[test]
+    fn face_indices_must_exist() {
+        let ascii = b"ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\n\
+            property float y\nproperty float z\nelement face 1\n\
+            property list uchar int vertex_indices\nend_header\n\
+            0 0 0\n1 0 0\n0 1 0\n3 0 1 3\n";
+        assert!(read_ply(ascii).unwrap_err().contains("past the 3 vertices"));
+    }
+}
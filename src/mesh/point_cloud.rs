@@ -0,0 +1,150 @@
+use crate::math::Vec3;
+
+use super::Aabb;
+
+/// Points without connectivity, such as a LiDAR scan, optionally colored
+/// per point and with normals.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PointCloud {
+    positions: Vec<f32>,
+    colors: Vec<u8>,
+    normals: Vec<f32>,
+}
+
+impl PointCloud {
+    /// Points from flat xyz `positions` and RGBA `colors`, four bytes per
+    /// point, or no colors at all. Fails if the lengths do not match up.
+    pub fn new(positions: Vec<f32>, colors: Vec<u8>) -> Result<PointCloud, String> {
+        if !positions.len().is_multiple_of(3) {
+            return Err(format!(
+                "{} coordinates are not whole xyz points",
+                positions.len()
+            ));
+        }
+        let count = positions.len() / 3;
+        if !colors.is_empty() && colors.len() != count * 4 {
+            return Err(format!(
+                "{} color bytes do not match {} points",
+                colors.len(),
+                count
+            ));
+        }
+        Ok(PointCloud {
+            positions,
+            colors,
+            normals: Vec::new(),
+        })
+    }
+
+    /// These points with xyz `normals`, one per point. Fails if there are
+    /// more or fewer.
+    pub fn with_normals(self, normals: Vec<f32>) -> Result<PointCloud, String> {
+        if normals.len() != self.positions.len() {
+            return Err(format!(
+                "{} normal coordinates do not match {} points",
+                normals.len(),
+                self.len()
+            ));
+        }
+        Ok(PointCloud { normals, ..self })
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len() / 3
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn has_colors(&self) -> bool {
+        !self.colors.is_empty()
+    }
+
+    pub fn has_normals(&self) -> bool {
+        !self.normals.is_empty()
+    }
+
+    pub fn positions(&self) -> &[f32] {
+        &self.positions
+    }
+
+    /// RGBA per point, or empty.
+    pub fn colors(&self) -> &[u8] {
+        &self.colors
+    }
+
+    /// xyz per point, or empty.
+    pub fn normals(&self) -> &[f32] {
+        &self.normals
+    }
+
+    pub fn position(&self, index: usize) -> Vec3 {
+        let point = &self.positions[index * 3..index * 3 + 3];
+        [point[0], point[1], point[2]]
+    }
+
+    pub fn color(&self, index: usize) -> Option<[u8; 4]> {
+        self.colors
+            .get(index * 4..index * 4 + 4)
+            .map(|color| [color[0], color[1], color[2], color[3]])
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_positions(&self.positions)
+    }
+
+    /// The same points in another order: the `i`th point of the result is
+    /// point `order[i]` of this one.
+    pub fn reordered(&self, order: &[u32]) -> PointCloud {
+        let mut positions = Vec::with_capacity(order.len() * 3);
+        let mut colors = Vec::with_capacity(if self.has_colors() { order.len() * 4 } else { 0 });
+        let mut normals = Vec::with_capacity(if self.has_normals() { order.len() * 3 } else { 0 });
+        for &index in order {
+            let index = index as usize;
+            positions.extend_from_slice(&self.positions[index * 3..index * 3 + 3]);
+            if self.has_colors() {
+                colors.extend_from_slice(&self.colors[index * 4..index * 4 + 4]);
+            }
+            if self.has_normals() {
+                normals.extend_from_slice(&self.normals[index * 3..index * 3 + 3]);
+            }
+        }
+        PointCloud {
+            positions,
+            colors,
+            normals,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lengths_must_match() {
+        assert!(PointCloud::new(vec![0.0; 6], vec![]).is_ok());
+        assert!(PointCloud::new(vec![0.0; 6], vec![255; 8]).is_ok());
+        assert!(PointCloud::new(vec![0.0; 5], vec![]).is_err());
+        assert!(PointCloud::new(vec![0.0; 6], vec![255; 4]).is_err());
+        let cloud = PointCloud::new(vec![0.0; 6], vec![]).unwrap();
+        assert!(cloud.clone().with_normals(vec![0.0; 3]).is_err());
+        assert!(cloud.with_normals(vec![0.0; 6]).unwrap().has_normals());
+    }
+
+    #[test]
+    fn reordering_keeps_colors_with_their_points() {
+        let cloud = PointCloud::new(
+            vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0],
+            vec![0, 0, 0, 255, 1, 1, 1, 255, 2, 2, 2, 255],
+        )
+        .unwrap();
+        let reordered = cloud.reordered(&[2, 0]);
+        assert_eq!(reordered.len(), 2);
+        assert_eq!(reordered.position(0), [2.0; 3]);
+        assert_eq!(reordered.color(0), Some([2, 2, 2, 255]));
+        assert_eq!(reordered.position(1), [0.0; 3]);
+        assert_eq!(reordered.color(1), Some([0, 0, 0, 255]));
+    }
+}
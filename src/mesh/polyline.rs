@@ -0,0 +1,275 @@
+use crate::math::Vec3;
+
+/// Floats per vertex of `Polylines`: the points before, at the start of,
+/// at the end of and after the segment (xyz each), the corner (along the
+/// segment 0 or 1, across it -1 or 1), the start and end styles, and the
+/// RGBA color.
+pub const LINE_VERTEX_FLOATS: usize = 20;
+
+/// How two segments of a polyline meet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineJoin {
+    /// Extend the outer edges until they meet. Sharp turns, whose miter
+    /// would be longer than the miter limit, are rounded instead.
+    Miter,
+    Round,
+}
+
+/// How the ends of an open polyline are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineCap {
+    /// Stop square at the end point.
+    Butt,
+    /// Stop square half the line width past the end point.
+    Square,
+    Round,
+}
+
+impl LineJoin {
+    fn code(self) -> f32 {
+        match self {
+            LineJoin::Miter => 0.0,
+            LineJoin::Round => 3.0,
+        }
+    }
+}
+
+impl LineCap {
+    fn code(self) -> f32 {
+        match self {
+            LineCap::Butt => 1.0,
+            LineCap::Square => 2.0,
+            LineCap::Round => 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineStyle {
+    pub join: LineJoin,
+    pub cap: LineCap,
+}
+
+impl Default for LineStyle {
+    fn default() -> LineStyle {
+        LineStyle {
+            join: LineJoin::Miter,
+            cap: LineCap::Butt,
+        }
+    }
+}
+
+/// Geometry for drawing polylines as thick lines, one quad per segment.
+///
+/// The quads are not expanded here: every corner of a segment carries the
+/// segment's end points and their neighbours, and `builtin::LINE_VERT`
+/// pushes it out in screen space, so the width stays the same number of
+/// pixels at any distance. See `LINE_VERTEX_FLOATS` for the layout.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Polylines {
+    vertices: Vec<f32>,
+    indices: Vec<u32>,
+}
+
+impl Polylines {
+    pub fn new() -> Polylines {
+        Polylines::default()
+    }
+
+    /// Add an open polyline through `points`. Repeated points are skipped;
+    /// fails if fewer than two distinct ones remain.
+    pub fn push(
+        &mut self,
+        points: &[Vec3],
+        color: [f32; 4],
+        style: LineStyle,
+    ) -> Result<(), String> {
+        self.push_points(points, false, color, style)
+    }
+
+    /// Add a polyline through `points` that joins the last one back to the
+    /// first. Fails if fewer than three distinct points remain.
+    pub fn push_closed(
+        &mut self,
+        points: &[Vec3],
+        color: [f32; 4],
+        style: LineStyle,
+    ) -> Result<(), String> {
+        self.push_points(points, true, color, style)
+    }
+
+    pub fn vertices(&self) -> &[f32] {
+        &self.vertices
+    }
+
+    /// Two triangles per segment.
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.vertices.len() / LINE_VERTEX_FLOATS / 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+    }
+
+    fn push_points(
+        &mut self,
+        points: &[Vec3],
+        closed: bool,
+        color: [f32; 4],
+        style: LineStyle,
+    ) -> Result<(), String> {
+        // A zero-length segment has no direction to expand across.
+        let mut points = points.to_vec();
+        points.dedup();
+        if closed && points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        let needed = if closed { 3 } else { 2 };
+        if points.len() < needed {
+            return Err(format!(
+                "a polyline needs at least {} distinct points, got {}",
+                needed,
+                points.len()
+            ));
+        }
+
+        let count = points.len();
+        let segments = if closed { count } else { count - 1 };
+        let join = style.join.code();
+        let cap = style.cap.code();
+        for segment in 0..segments {
+            let start = points[segment];
+            let end = points[(segment + 1) % count];
+            let first = segment == 0 && !closed;
+            let last = segment + 1 == segments && !closed;
+            // Capped ends have no neighbour; the shader ignores it there.
+            let previous = if first {
+                start
+            } else {
+                points[(segment + count - 1) % count]
+            };
+            let next = if last {
+                end
+            } else {
+                points[(segment + 2) % count]
+            };
+            let ends = [
+                if first { cap } else { join },
+                if last { cap } else { join },
+            ];
+
+            let base = (self.vertices.len() / LINE_VERTEX_FLOATS) as u32;
+            for corner in [[0.0, -1.0], [0.0, 1.0], [1.0, -1.0], [1.0, 1.0]] {
+                self.vertices.extend_from_slice(&previous);
+                self.vertices.extend_from_slice(&start);
+                self.vertices.extend_from_slice(&end);
+                self.vertices.extend_from_slice(&next);
+                self.vertices.extend_from_slice(&corner);
+                self.vertices.extend_from_slice(&ends);
+                self.vertices.extend_from_slice(&color);
+            }
+            self.indices
+                .extend_from_slice(&[base, base + 1, base + 2, base + 2, base + 1, base + 3]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+    /// The `index`th vertex's floats from `offset`, `len` of them.
+    fn field(lines: &Polylines, index: usize, offset: usize, len: usize) -> &[f32] {
+        let start = index * LINE_VERTEX_FLOATS + offset;
+        &lines.vertices()[start..start + len]
+    }
+
+    #[test]
+    fn each_segment_is_a_quad() {
+        let mut lines = Polylines::new();
+        let points = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]];
+        lines.push(&points, RED, LineStyle::default()).unwrap();
+        assert_eq!(lines.segment_count(), 2);
+        assert_eq!(lines.vertices().len(), 8 * LINE_VERTEX_FLOATS);
+        assert_eq!(lines.indices(), [0, 1, 2, 2, 1, 3, 4, 5, 6, 6, 5, 7]);
+        // Every corner of the second segment knows both neighbours.
+        for vertex in 4..8 {
+            assert_eq!(field(&lines, vertex, 0, 3), [0.0, 0.0, 0.0]);
+            assert_eq!(field(&lines, vertex, 3, 3), [1.0, 0.0, 0.0]);
+            assert_eq!(field(&lines, vertex, 6, 3), [1.0, 1.0, 0.0]);
+            assert_eq!(field(&lines, vertex, 9, 3), [1.0, 1.0, 0.0]);
+            assert_eq!(field(&lines, vertex, 16, 4), RED);
+        }
+        let corners: Vec<_> = (4..8).map(|vertex| field(&lines, vertex, 12, 2).to_vec()).collect();
+        assert_eq!(corners, [[0.0, -1.0], [0.0, 1.0], [1.0, -1.0], [1.0, 1.0]]);
+    }
+
+    #[test]
+    fn open_ends_are_capped_and_inner_ends_joined() {
+        let mut lines = Polylines::new();
+        let style = LineStyle {
+            join: LineJoin::Round,
+            cap: LineCap::Square,
+        };
+        let points = [[0.0; 3], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0], [3.0, 0.0, 0.0]];
+        lines.push(&points, RED, style).unwrap();
+        let ends: Vec<_> = (0..3)
+            .map(|segment| field(&lines, segment * 4, 14, 2).to_vec())
+            .collect();
+        assert_eq!(ends, [[2.0, 3.0], [3.0, 3.0], [3.0, 2.0]]);
+    }
+
+    #[test]
+    fn closed_polylines_wrap_around() {
+        let mut lines = Polylines::new();
+        let points = [[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0; 3]];
+        lines.push_closed(&points, RED, LineStyle::default()).unwrap();
+        // The repeated first point is dropped, leaving a triangle.
+        assert_eq!(lines.segment_count(), 3);
+        for segment in 0..3 {
+            assert_eq!(field(&lines, segment * 4, 14, 2), [0.0, 0.0]);
+        }
+        // The first segment comes from the last point, the last goes to
+        // the first.
+        assert_eq!(field(&lines, 0, 0, 3), [0.0, 1.0, 0.0]);
+        assert_eq!(field(&lines, 8, 6, 3), [0.0, 0.0, 0.0]);
+        assert_eq!(field(&lines, 8, 9, 3), [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn repeated_points_are_skipped() {
+        let mut lines = Polylines::new();
+        let points = [[0.0; 3], [0.0; 3], [1.0, 0.0, 0.0], [1.0, 0.0, 0.0]];
+        lines.push(&points, RED, LineStyle::default()).unwrap();
+        assert_eq!(lines.segment_count(), 1);
+        assert!(lines.push(&[[2.0; 3], [2.0; 3]], RED, LineStyle::default()).is_err());
+        assert!(lines
+            .push_closed(&[[0.0; 3], [1.0, 0.0, 0.0]], RED, LineStyle::default())
+            .is_err());
+        assert_eq!(lines.segment_count(), 1);
+    }
+
+    #[test]
+    fn later_polylines_index_past_earlier_ones() {
+        let mut lines = Polylines::new();
+        let segment = [[0.0; 3], [1.0, 0.0, 0.0]];
+        lines.push(&segment, RED, LineStyle::default()).unwrap();
+        lines.push(&segment, RED, LineStyle::default()).unwrap();
+        assert_eq!(&lines.indices()[6..], [4, 5, 6, 6, 5, 7]);
+        lines.clear();
+        assert!(lines.is_empty());
+        assert!(lines.indices().is_empty());
+    }
+}
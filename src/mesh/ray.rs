@@ -0,0 +1,192 @@
+use crate::math::{self, Mat4, Vec3};
+
+/// A half-line from `origin` along the unit vector `direction`, so that
+/// distances along it are in world units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+/// Where a ray crosses a triangle: the distance along the ray and the
+/// barycentric weights of the triangle's second and third corners.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriangleHit {
+    pub distance: f32,
+    pub u: f32,
+    pub v: f32,
+}
+
+impl Ray {
+    /// `direction` need not be normalized.
+    pub fn new(origin: Vec3, direction: Vec3) -> Ray {
+        Ray {
+            origin,
+            direction: math::normalize(&direction),
+        }
+    }
+
+    /// The ray through pixel (`x`, `y`), counted from the top left of a
+    /// `width`×`height` viewport, for a camera with the given projection
+    /// and view matrices. It starts on the near plane. `None` if the
+    /// matrices cannot be inverted.
+    pub fn from_screen(
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        projection: &Mat4,
+        view: &Mat4,
+    ) -> Option<Ray> {
+        let inverse = math::invert(&math::mul(projection, view))?;
+        let ndc_x = 2.0 * x / width - 1.0;
+        let ndc_y = 1.0 - 2.0 * y / height;
+        let near = math::transform_point(&inverse, &[ndc_x, ndc_y, -1.0]);
+        let far = math::transform_point(&inverse, &[ndc_x, ndc_y, 1.0]);
+        Some(Ray::new(near, math::sub(&far, &near)))
+    }
+
+    pub fn at(&self, distance: f32) -> Vec3 {
+        math::add(&self.origin, &math::scale(&self.direction, distance))
+    }
+
+    /// The same ray expressed through `m`, e.g. the inverse model matrix
+    /// to bring a world ray into a mesh's own space. Distances along the
+    /// result are only comparable with the original if `m` does not
+    /// scale.
+    pub fn transform(&self, m: &Mat4) -> Ray {
+        Ray::new(
+            math::transform_point(m, &self.origin),
+            math::transform_direction(m, &self.direction),
+        )
+    }
+
+    /// Möller–Trumbore intersection with the triangle `a`, `b`, `c`, hit
+    /// from either side. Hits behind the origin are ignored.
+    pub fn intersect_triangle(&self, a: &Vec3, b: &Vec3, c: &Vec3) -> Option<TriangleHit> {
+        const EPSILON: f32 = 1e-7;
+        let edge1 = math::sub(b, a);
+        let edge2 = math::sub(c, a);
+        let p = math::cross(&self.direction, &edge2);
+        let det = math::dot(&edge1, &p);
+        if det.abs() < EPSILON {
+            // Parallel to the triangle's plane.
+            return None;
+        }
+        let inverse_det = 1.0 / det;
+        let s = math::sub(&self.origin, a);
+        let u = math::dot(&s, &p) * inverse_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = math::cross(&s, &edge1);
+        let v = math::dot(&self.direction, &q) * inverse_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = math::dot(&edge2, &q) * inverse_det;
+        if distance < 0.0 {
+            return None;
+        }
+        Some(TriangleHit { distance, u, v })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Vec3, b: &Vec3) {
+        for axis in 0..3 {
+            assert!((a[axis] - b[axis]).abs() < 1e-4, "{:?} != {:?}", a, b);
+        }
+    }
+
+    const A: Vec3 = [-1.0, -1.0, 0.0];
+    const B: Vec3 = [1.0, -1.0, 0.0];
+    const C: Vec3 = [0.0, 1.0, 0.0];
+
+    #[test]
+    fn hits_triangle_in_front() {
+        let ray = Ray::new([0.0, 0.0, 5.0], [0.0, 0.0, -2.0]);
+        let hit = ray.intersect_triangle(&A, &B, &C).unwrap();
+        assert!((hit.distance - 5.0).abs() < 1e-5);
+        assert_close(&ray.at(hit.distance), &[0.0, 0.0, 0.0]);
+        // The hit point is rebuilt from the barycentric weights.
+        let w = 1.0 - hit.u - hit.v;
+        let point = [
+            w * A[0] + hit.u * B[0] + hit.v * C[0],
+            w * A[1] + hit.u * B[1] + hit.v * C[1],
+            0.0,
+        ];
+        assert_close(&point, &[0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn hits_back_face() {
+        let ray = Ray::new([0.0, 0.0, -5.0], [0.0, 0.0, 1.0]);
+        assert!(ray.intersect_triangle(&A, &B, &C).is_some());
+    }
+
+    #[test]
+    fn misses_outside_triangle() {
+        let ray = Ray::new([2.0, 2.0, 5.0], [0.0, 0.0, -1.0]);
+        assert!(ray.intersect_triangle(&A, &B, &C).is_none());
+    }
+
+    #[test]
+    fn ignores_triangle_behind_origin() {
+        let ray = Ray::new([0.0, 0.0, 5.0], [0.0, 0.0, 1.0]);
+        assert!(ray.intersect_triangle(&A, &B, &C).is_none());
+    }
+
+    #[test]
+    fn ignores_parallel_ray() {
+        let ray = Ray::new([0.0, 0.0, 1.0], [1.0, 0.0, 0.0]);
+        assert!(ray.intersect_triangle(&A, &B, &C).is_none());
+    }
+
+    fn camera() -> (Mat4, Mat4) {
+        let mut projection = mat4::new_zero();
+        mat4::perspective(
+            &mut projection,
+            &(45.0 * std::f32::consts::PI / 180.0),
+            &(900.0 / 700.0),
+            &1.0,
+            &100.0,
+        );
+        let mut view = mat4::new_identity();
+        let identity = view;
+        mat4::translate(&mut view, &identity, &[0.0, 0.0, -6.0]);
+        (projection, view)
+    }
+
+    #[test]
+    fn screen_center_looks_down_negative_z() {
+        let (projection, view) = camera();
+        let ray = Ray::from_screen(450.0, 350.0, 900.0, 700.0, &projection, &view).unwrap();
+        assert_close(&ray.direction, &[0.0, 0.0, -1.0]);
+        // The camera sits at z = 6, so the near plane is at z = 5.
+        assert_close(&ray.origin, &[0.0, 0.0, 5.0]);
+    }
+
+    #[test]
+    fn unprojected_ray_passes_through_projected_point() {
+        let (projection, view) = camera();
+        let point = [0.7, -0.4, 0.3];
+        let clip = math::transform_point(&math::mul(&projection, &view), &point);
+        let x = (clip[0] + 1.0) * 0.5 * 900.0;
+        let y = (1.0 - clip[1]) * 0.5 * 700.0;
+        let ray = Ray::from_screen(x, y, 900.0, 700.0, &projection, &view).unwrap();
+        let to_point = math::sub(&point, &ray.origin);
+        let along = math::dot(&to_point, &ray.direction);
+        assert_close(&ray.at(along), &point);
+    }
+
+    #[test]
+    fn top_of_screen_points_up() {
+        let (projection, view) = camera();
+        let ray = Ray::from_screen(450.0, 0.0, 900.0, 700.0, &projection, &view).unwrap();
+        assert!(ray.direction[1] > 0.0);
+    }
+}
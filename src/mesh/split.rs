@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+/// The most vertices a sub-mesh may reference and still be drawn with
+/// 16-bit indices. Index 0xFFFF is left unused since WebGL2 always
+/// treats it as a primitive restart.
+pub const MAX_U16_VERTICES: usize = 65_535;
+
+/// One piece of a mesh split by `split_mesh`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubMesh {
+    /// For each vertex of the sub-mesh, its index in the original mesh.
+    pub vertices: Vec<u32>,
+    /// Triangles, indexing into `vertices`.
+    pub indices: Vec<u16>,
+}
+
+impl SubMesh {
+    /// Pick this sub-mesh's vertices out of an attribute array of the
+    /// original mesh with `components` values per vertex.
+    pub fn gather<T: Copy>(&self, attribute: &[T], components: usize) -> Vec<T> {
+        let mut gathered = Vec::with_capacity(self.vertices.len() * components);
+        for &vertex in self.vertices.iter() {
+            let start = vertex as usize * components;
+            gathered.extend_from_slice(&attribute[start..start + components]);
+        }
+        gathered
+    }
+}
+
+/// Split an indexed triangle mesh into pieces that each reference at most
+/// `max_vertices` distinct vertices, so every piece can be uploaded with
+/// `buffer_u16_indices` on contexts without `OES_element_index_uint`.
+///
+/// Triangles are kept whole and in their original order; vertices shared
+/// by triangles in different pieces are duplicated.
+pub fn split_mesh(indices: &[u32], max_vertices: usize) -> Result<Vec<SubMesh>, String> {
+    if !indices.len().is_multiple_of(3) {
+        return Err(format!(
+            "index count {} is not a multiple of 3",
+            indices.len()
+        ));
+    }
+    if !(3..=MAX_U16_VERTICES).contains(&max_vertices) {
+        return Err(format!(
+            "max_vertices must be between 3 and {}, got {}",
+            MAX_U16_VERTICES, max_vertices
+        ));
+    }
+
+    let mut sub_meshes = Vec::new();
+    let mut current = SubMesh {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+    };
+    let mut remap: HashMap<u32, u16> = HashMap::new();
+
+    for triangle in indices.chunks(3) {
+        let mut added = 0;
+        for (i, vertex) in triangle.iter().enumerate() {
+            if !remap.contains_key(vertex) && !triangle[..i].contains(vertex) {
+                added += 1;
+            }
+        }
+        if current.vertices.len() + added > max_vertices {
+            sub_meshes.push(current);
+            current = SubMesh {
+                vertices: Vec::new(),
+                indices: Vec::new(),
+            };
+            remap.clear();
+        }
+        for &vertex in triangle {
+            let vertices = &mut current.vertices;
+            let local = *remap.entry(vertex).or_insert_with(|| {
+                vertices.push(vertex);
+                (vertices.len() - 1) as u16
+            });
+            current.indices.push(local);
+        }
+    }
+    if !current.indices.is_empty() {
+        sub_meshes.push(current);
+    }
+    Ok(sub_meshes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Map every sub-mesh index back to the original vertex.
+    fn resolve(sub_meshes: &[SubMesh]) -> Vec<u32> {
+        sub_meshes
+            .iter()
+            .flat_map(|sub_mesh| {
+                sub_mesh
+                    .indices
+                    .iter()
+                    .map(move |&index| sub_mesh.vertices[index as usize])
+            })
+            .collect()
+    }
+
+    /// A strip of `quads` quads along x: 2 * (quads + 1) vertices.
+    fn strip(quads: u32) -> Vec<u32> {
+        let mut indices = Vec::new();
+        for quad in 0..quads {
+            let a = quad * 2;
+            indices.extend_from_slice(&[a, a + 1, a + 2, a + 1, a + 3, a + 2]);
+        }
+        indices
+    }
+
+    #[test]
+    fn small_mesh_stays_whole() {
+        let indices = [0, 1, 2, 0, 2, 3];
+        let sub_meshes = split_mesh(&indices, MAX_U16_VERTICES).unwrap();
+        assert_eq!(sub_meshes.len(), 1);
+        assert_eq!(sub_meshes[0].vertices, vec![0, 1, 2, 3]);
+        assert_eq!(sub_meshes[0].indices, vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn pieces_respect_the_vertex_limit() {
+        let indices = strip(100);
+        let sub_meshes = split_mesh(&indices, 10).unwrap();
+        assert!(sub_meshes.len() > 1);
+        for sub_mesh in sub_meshes.iter() {
+            assert!(sub_mesh.vertices.len() <= 10);
+            assert_eq!(sub_mesh.indices.len() % 3, 0);
+            assert!(sub_mesh
+                .indices
+                .iter()
+                .all(|&index| (index as usize) < sub_mesh.vertices.len()));
+        }
+    }
+
+    #[test]
+    fn remapped_indices_reproduce_the_original_triangles() {
+        let indices = strip(100);
+        let sub_meshes = split_mesh(&indices, 10).unwrap();
+        assert_eq!(resolve(&sub_meshes), indices);
+    }
+
+    #[test]
+    fn large_mesh_splits_at_u16_limit() {
+        // 2 * 40_001 vertices, more than a u16 can index.
+        let indices = strip(40_000);
+        let sub_meshes = split_mesh(&indices, MAX_U16_VERTICES).unwrap();
+        assert_eq!(sub_meshes.len(), 2);
+        assert!(sub_meshes
+            .iter()
+            .all(|sub_mesh| sub_mesh.vertices.len() <= MAX_U16_VERTICES));
+        assert_eq!(resolve(&sub_meshes), indices);
+    }
+
+    #[test]
+    fn shared_vertices_are_duplicated_across_pieces() {
+        let indices = strip(3);
+        let sub_meshes = split_mesh(&indices, 4).unwrap();
+        let total: usize = sub_meshes.iter().map(|sub_mesh| sub_mesh.vertices.len()).sum();
+        assert!(total > 8);
+        assert_eq!(resolve(&sub_meshes), indices);
+    }
+
+    #[test]
+    fn degenerate_triangle_counts_each_vertex_once() {
+        let indices = [0, 0, 1, 1, 2, 2];
+        let sub_meshes = split_mesh(&indices, 3).unwrap();
+        assert_eq!(sub_meshes.len(), 1);
+        assert_eq!(sub_meshes[0].vertices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn gather_picks_attributes_in_sub_mesh_order() {
+        let sub_mesh = SubMesh {
+            vertices: vec![2, 0],
+            indices: vec![0, 1, 0],
+        };
+        let positions = [0.0, 0.5, 1.0, 1.5, 2.0, 2.5];
+        assert_eq!(sub_mesh.gather(&positions, 2), vec![2.0, 2.5, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(split_mesh(&[0, 1], MAX_U16_VERTICES).is_err());
+        assert!(split_mesh(&[0, 1, 2], 2).is_err());
+        assert!(split_mesh(&[0, 1, 2], MAX_U16_VERTICES + 1).is_err());
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::math::{self, Vec3};
+
+use super::bytes::{ByteReader, Endian};
+use super::TriangleMesh;
+
+/// Distance within which `read_stl` callers usually merge corners: well
+/// under any printable feature, well over float noise in CAD exports.
+pub const DEFAULT_WELD_TOLERANCE: f32 = 1e-5;
+
+/// Bytes of a binary STL before its triangles: the 80-byte header and the
+/// triangle count.
+const BINARY_HEADER_LEN: usize = 84;
+
+/// Bytes of each triangle of a binary STL.
+const BINARY_TRIANGLE_LEN: usize = 50;
+
+/// Read a binary or ASCII STL into an indexed mesh.
+///
+/// STL stores every triangle's corners separately, so corners closer than
+/// `weld_tolerance` are merged into one vertex (a tolerance of zero merges
+/// only identical ones) and triangles that collapse doing so are dropped.
+/// Triangles wound against the normal stored with them are turned round.
+/// The stored normals are otherwise ignored and smooth ones are computed
+/// from the welded mesh. Corners that are infinite or NaN fail the read.
+pub fn read_stl(data: &[u8], weld_tolerance: f32) -> Result<TriangleMesh, String> {
+    let corners = if is_binary(data) {
+        read_binary(data)?
+    } else {
+        read_ascii(data)?
+    };
+    let (positions, mut indices) = weld_vertices(&corners, weld_tolerance);
+    let mut kept = 0;
+    for triangle in 0..indices.len() / 3 {
+        let [a, b, c] = [0, 1, 2].map(|corner| indices[triangle * 3 + corner]);
+        if a != b && b != c && c != a {
+            indices.copy_within(triangle * 3..triangle * 3 + 3, kept * 3);
+            kept += 1;
+        }
+    }
+    indices.truncate(kept * 3);
+
+    let mut mesh = TriangleMesh {
+        positions,
+        indices,
+        ..TriangleMesh::default()
+    };
+    mesh.recompute_normals();
+    Ok(mesh)
+}
+
+/// Whether `data` is a binary STL. ASCII files start with "solid", but so
+/// do the headers of many binary ones, so a size that matches the stored
+/// triangle count decides.
+fn is_binary(data: &[u8]) -> bool {
+    if data.len() >= BINARY_HEADER_LEN {
+        let mut count = [0; 4];
+        count.copy_from_slice(&data[80..BINARY_HEADER_LEN]);
+        let count = u32::from_le_bytes(count) as usize;
+        if count
+            .checked_mul(BINARY_TRIANGLE_LEN)
+            .and_then(|len| len.checked_add(BINARY_HEADER_LEN))
+            == Some(data.len())
+        {
+            return true;
+        }
+    }
+    !data.trim_ascii_start().starts_with(b"solid")
+}
+
+/// The triangles' corners, nine floats each, wound to agree with the
+/// normal stored alongside unless that normal is zero.
+fn push_triangle(corners: &mut Vec<f32>, normal: Vec3, triangle: [Vec3; 3]) {
+    let [a, mut b, mut c] = triangle;
+    let winding = math::cross(&math::sub(&b, &a), &math::sub(&c, &a));
+    if math::dot(&winding, &normal) < 0.0 {
+        std::mem::swap(&mut b, &mut c);
+    }
+    for corner in [a, b, c] {
+        corners.extend_from_slice(&corner);
+    }
+}
+
+fn read_binary(data: &[u8]) -> Result<Vec<f32>, String> {
+    let mut reader = ByteReader::new(data);
+    reader.skip(80)?;
+    let count = reader.u32(Endian::Little)? as usize;
+    let mut corners = Vec::with_capacity(count.min(data.len() / BINARY_TRIANGLE_LEN) * 9);
+    let vector = |reader: &mut ByteReader| -> Result<Vec3, String> {
+        Ok([
+            reader.f32(Endian::Little)?,
+            reader.f32(Endian::Little)?,
+            reader.f32(Endian::Little)?,
+        ])
+    };
+    for index in 0..count {
+        let normal = vector(&mut reader)?;
+        let triangle = [
+            vector(&mut reader)?,
+            vector(&mut reader)?,
+            vector(&mut reader)?,
+        ];
+        // The attribute byte count, which nothing agrees on the meaning of.
+        reader.skip(2)?;
+        if !triangle.iter().flatten().all(|value| value.is_finite()) {
+            return Err(format!(
+                "STL triangle {} has a corner that is not finite",
+                index
+            ));
+        }
+        push_triangle(&mut corners, normal, triangle);
+    }
+    Ok(corners)
+}
+
+fn read_ascii(data: &[u8]) -> Result<Vec<f32>, String> {
+    let text = std::str::from_utf8(data).map_err(|_| "ASCII STL is not text".to_string())?;
+    let mut corners = Vec::new();
+    let mut normal = [0.0; 3];
+    let mut triangle = Vec::with_capacity(3);
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("STL line {}: {}", number + 1, message);
+        let vector = |words: &[&str]| -> Result<Vec3, String> {
+            match words {
+                [x, y, z] => {
+                    let parse =
+                        |word: &str| word.parse::<f32>().map_err(|_| error("invalid number"));
+                    Ok([parse(x)?, parse(y)?, parse(z)?])
+                }
+                _ => Err(error("expected three numbers")),
+            }
+        };
+        let words: Vec<_> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["facet", "normal", rest @ ..] => {
+                normal = vector(rest)?;
+                // Some exporters write NaN for normals they never computed;
+                // treat those like a zero normal and keep the stored winding.
+                if !normal.iter().all(|value| value.is_finite()) {
+                    normal = [0.0; 3];
+                }
+                triangle.clear();
+            }
+            ["vertex", rest @ ..] => {
+                if triangle.len() == 3 {
+                    return Err(error("facet has more than three vertices"));
+                }
+                let corner = vector(rest)?;
+                if !corner.iter().all(|value| value.is_finite()) {
+                    return Err(error("vertex is not finite"));
+                }
+                triangle.push(corner);
+            }
+            ["endfacet"] => {
+                if triangle.len() != 3 {
+                    return Err(error("facet has fewer than three vertices"));
+                }
+                push_triangle(
+                    &mut corners,
+                    normal,
+                    [triangle[0], triangle[1], triangle[2]],
+                );
+                triangle.clear();
+            }
+            ["solid", ..] | ["endsolid", ..] | ["outer", "loop"] | ["endloop"] | [] => {}
+            _ => return Err(error("unexpected text")),
+        }
+    }
+    Ok(corners)
+}
+
+/// Merge the points of `positions`, xyz each, that lie within `tolerance`
+/// of an earlier one, returning the remaining points and, for every input
+/// point, the index of the one it became.
+pub fn weld_vertices(positions: &[f32], tolerance: f32) -> (Vec<f32>, Vec<u32>) {
+    let mut welded = Vec::new();
+    let mut indices = Vec::with_capacity(positions.len() / 3);
+    if tolerance <= 0.0 {
+        // Only identical points merge, with both zeros alike.
+        let mut seen = HashMap::new();
+        for point in positions.chunks_exact(3) {
+            let key = [0, 1, 2].map(|axis| (point[axis] + 0.0).to_bits());
+            let index = *seen.entry(key).or_insert_with(|| {
+                welded.extend_from_slice(point);
+                (welded.len() / 3 - 1) as u32
+            });
+            indices.push(index);
+        }
+        return (welded, indices);
+    }
+
+    // Points are bucketed in cells as wide as the tolerance, so any point
+    // near enough to merge is in the same cell or a neighbouring one.
+    let mut cells: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+    let cell_of = |point: &Vec3| point.map(|value| (value / tolerance).floor() as i64);
+    let tolerance_squared = tolerance * tolerance;
+    for point in positions.chunks_exact(3) {
+        let point = [point[0], point[1], point[2]];
+        let cell = cell_of(&point);
+        let mut found = None;
+        'search: for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    // Far or infinite points sit in the outermost cells.
+                    let neighbour = [
+                        cell[0].saturating_add(dx),
+                        cell[1].saturating_add(dy),
+                        cell[2].saturating_add(dz),
+                    ];
+                    for &index in cells.get(&neighbour).into_iter().flatten() {
+                        let other = &welded[index as usize * 3..index as usize * 3 + 3];
+                        let offset = math::sub(&point, &[other[0], other[1], other[2]]);
+                        if math::dot(&offset, &offset) <= tolerance_squared {
+                            found = Some(index);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+        let index = found.unwrap_or_else(|| {
+            let index = (welded.len() / 3) as u32;
+            welded.extend_from_slice(&point);
+            cells.entry(cell).or_default().push(index);
+            index
+        });
+        indices.push(index);
+    }
+    (welded, indices)
+}
+
+/// `mesh` as a binary STL, each triangle with its normal by winding.
+pub fn write_stl(mesh: &TriangleMesh) -> Vec<u8> {
+    let count = mesh.triangle_count();
+    let mut data = Vec::with_capacity(BINARY_HEADER_LEN + count * BINARY_TRIANGLE_LEN);
+    // The header must not start with "solid" or readers take it for text.
+    let mut header = [0; 80];
+    let label = b"binary STL";
+    header[..label.len()].copy_from_slice(label);
+    data.extend_from_slice(&header);
+    data.extend_from_slice(&(count as u32).to_le_bytes());
+    for triangle in 0..count {
+        let normal = mesh.face_normal(triangle);
+        let corners = mesh.triangle(triangle).map(|vertex| mesh.position(vertex));
+        for vector in std::iter::once(&normal).chain(&corners) {
+            for value in vector {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        data.extend_from_slice(&[0, 0]);
+    }
+    data
+}
+
+/// `mesh` as an ASCII STL solid called `name`. Numbers are written in
+/// full so the file reads back to the same positions.
+pub fn write_stl_ascii(mesh: &TriangleMesh, name: &str) -> String {
+    let mut text = String::new();
+    // Writing to a String cannot fail.
+    let _ = writeln!(text, "solid {}", name);
+    for triangle in 0..mesh.triangle_count() {
+        let [x, y, z] = mesh.face_normal(triangle);
+        let _ = writeln!(text, "  facet normal {} {} {}", x, y, z);
+        text.push_str("    outer loop\n");
+        for vertex in mesh.triangle(triangle) {
+            let [x, y, z] = mesh.position(vertex);
+            let _ = writeln!(text, "      vertex {} {} {}", x, y, z);
+        }
+        text.push_str("    endloop\n  endfacet\n");
+    }
+    let _ = writeln!(text, "endsolid {}", name);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::tests::fixture;
+
+    fn cube() -> TriangleMesh {
+        read_stl(&fixture("cube_ascii.stl"), DEFAULT_WELD_TOLERANCE).unwrap()
+    }
+
+    #[test]
+    fn ascii_cube_welds_into_eight_corners() {
+        let mesh = cube();
+        assert_eq!(mesh.vertex_count(), 8);
+        assert_eq!(mesh.triangle_count(), 12);
+        assert_eq!(mesh.validate(), Ok(()));
+        // Smooth normals at the corners point out of the cube.
+        for vertex in 0..mesh.vertex_count() {
+            let normal = [0, 1, 2].map(|axis| mesh.normals[vertex * 3 + axis]);
+            let outward = math::sub(&mesh.position(vertex), &[0.5; 3]);
+            assert!((math::length(&normal) - 1.0).abs() < 1e-5);
+            for axis in 0..3 {
+                assert!(normal[axis] * outward[axis] > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn binary_cube_matches_ascii() {
+        // Its header starts with "solid", as many exporters write.
+        let data = fixture("cube_binary.stl");
+        assert!(data.starts_with(b"solid"));
+        assert_eq!(read_stl(&data, DEFAULT_WELD_TOLERANCE).unwrap(), cube());
+    }
+
+    #[test]
+    fn round_trips_through_both_formats() {
+        let mesh = cube();
+        assert_eq!(read_stl(&write_stl(&mesh), 0.0).unwrap(), mesh);
+        let text = write_stl_ascii(&mesh, "cube");
+        assert!(text.starts_with("solid cube\n"));
+        assert_eq!(read_stl(text.as_bytes(), 0.0).unwrap(), mesh);
+    }
+
+    #[test]
+    fn welding_respects_the_tolerance() {
+        let positions = [
+            0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.000_001, 0.0, 0.0, -0.0, 0.0, 0.0,
+        ];
+        let (welded, indices) = weld_vertices(&positions, 1e-5);
+        assert_eq!(welded.len(), 6);
+        assert_eq!(indices, [0, 1, 1, 0]);
+        let (welded, indices) = weld_vertices(&positions, 0.0);
+        assert_eq!(welded.len(), 9);
+        assert_eq!(indices, [0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn non_finite_corners_are_rejected() {
+        let (welded, indices) = weld_vertices(&[f32::INFINITY, 0.0, 0.0, 1.0, 0.0, 0.0], 1e-5);
+        assert_eq!((welded.len(), indices.len()), (6, 2));
+        let text = "solid t\nfacet normal 0 0 1\nouter loop\nvertex inf 0 0\n";
+        assert!(read_stl(text.as_bytes(), DEFAULT_WELD_TOLERANCE).is_err());
+        let mut binary = write_stl(&cube());
+        let first_corner = BINARY_HEADER_LEN + 12;
+        binary[first_corner..first_corner + 4].copy_from_slice(&f32::NAN.to_le_bytes());
+        let error = read_stl(&binary, DEFAULT_WELD_TOLERANCE).unwrap_err();
+        assert!(error.contains("not finite"));
+    }
+
+    #[test]
+    fn triangles_follow_their_stored_normals() {
+        // Wound clockwise seen from +z, where its normal points.
+        let text = "solid t\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 0 1 0\n\
+            vertex 1 0 0\nendloop\nendfacet\nendsolid t\n";
+        let mesh = read_stl(text.as_bytes(), 0.0).unwrap();
+        assert_eq!(mesh.face_normal(0), [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn ascii_nan_normals_keep_the_stored_winding() {
+        let text = "solid t\nfacet normal nan nan nan\nouter loop\nvertex 0 0 0\n\
+            vertex 0 1 0\nvertex 1 0 0\nendloop\nendfacet\nendsolid t\n";
+        let mesh = read_stl(text.as_bytes(), 0.0).unwrap();
+        assert_eq!(mesh.face_normal(0), [0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn collapsed_triangles_are_dropped() {
+        let text = "solid t\nfacet normal 0 0 0\nouter loop\nvertex 0 0 0\n\
+            vertex 0.000001 0 0\nvertex 1 0 0\nendloop\nendfacet\nendsolid t\n";
+        assert_eq!(read_stl(text.as_bytes(), 1e-5).unwrap().triangle_count(), 0);
+        assert_eq!(read_stl(text.as_bytes(), 0.0).unwrap().triangle_count(), 1);
+    }
+
+    #[test]
+    fn malformed_files_are_reported() {
+        let short = "solid t\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nendloop\nendfacet\n";
+        assert!(read_stl(short.as_bytes(), 0.0)
+            .unwrap_err()
+            .contains("line 6"));
+        assert!(read_stl(b"solid t\nvertex 0 0\n", 0.0).is_err());
+        let mut binary = write_stl(&cube());
+        binary.truncate(binary.len() - 10);
+        assert!(read_stl(&binary, 0.0).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use crate::math::{self, Vec3};
+
+use super::normals::vertex_normals;
+use super::Bounds;
+
+/// An indexed triangle mesh on the CPU, as loaded from a file and before
+/// it is uploaded. Every attribute but `positions` may be left empty;
+/// otherwise each holds one entry per vertex.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TriangleMesh {
+    /// xyz per vertex.
+    pub positions: Vec<f32>,
+    /// xyz per vertex, unit length.
+    pub normals: Vec<f32>,
+    /// uv per vertex.
+    pub texcoords: Vec<f32>,
+    /// RGBA bytes per vertex.
+    pub colors: Vec<u8>,
+    /// Three per triangle, counter-clockwise seen from the front.
+    pub indices: Vec<u32>,
+}
+
+impl TriangleMesh {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len() / 3
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn position(&self, index: usize) -> Vec3 {
+        let point = &self.positions[index * 3..index * 3 + 3];
+        [point[0], point[1], point[2]]
+    }
+
+    /// The vertex indices of triangle `index`.
+    pub fn triangle(&self, index: usize) -> [usize; 3] {
+        let triangle = &self.indices[index * 3..index * 3 + 3];
+        [
+            triangle[0] as usize,
+            triangle[1] as usize,
+            triangle[2] as usize,
+        ]
+    }
+
+    /// The unit normal of triangle `index` by its winding, or zero if the
+    /// triangle is degenerate.
+    pub fn face_normal(&self, index: usize) -> Vec3 {
+        let [a, b, c] = self.triangle(index).map(|vertex| self.position(vertex));
+        math::normalize(&math::cross(&math::sub(&b, &a), &math::sub(&c, &a)))
+    }
+
+    /// Replace the normals with smooth ones, keeping the vertices as they
+    /// are: each vertex gets the average of the normals of the triangles
+    /// around it, weighted by their angle at the vertex. See
+    /// `smooth_normals` for hard edges.
+    pub fn recompute_normals(&mut self) {
+        self.normals = vertex_normals(self);
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        Bounds::from_positions(&self.positions)
+    }
+
+    /// Check that the attributes agree on the vertex count and that the
+    /// indices form whole triangles over existing vertices.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.positions.len().is_multiple_of(3) {
+            return Err(format!(
+                "{} coordinates are not whole xyz positions",
+                self.positions.len()
+            ));
+        }
+        let count = self.vertex_count();
+        for (name, len, components) in [
+            ("normals", self.normals.len(), 3),
+            ("texcoords", self.texcoords.len(), 2),
+            ("colors", self.colors.len(), 4),
+        ] {
+            if len != 0 && len != count * components {
+                return Err(format!(
+                    "{} {} values do not match {} vertices",
+                    len, name, count
+                ));
+            }
+        }
+        if !self.indices.len().is_multiple_of(3) {
+            return Err(format!(
+                "index count {} is not a multiple of 3",
+                self.indices.len()
+            ));
+        }
+        match self.indices.iter().find(|&&index| index as usize >= count) {
+            Some(index) => Err(format!("index {} is past the {} vertices", index, count)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> TriangleMesh {
+        TriangleMesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            indices: vec![0, 1, 2],
+            ..TriangleMesh::default()
+        }
+    }
+
+    #[test]
+    fn attributes_must_cover_every_vertex() {
+        assert_eq!(triangle().validate(), Ok(()));
+        let mut mesh = triangle();
+        mesh.normals = vec![0.0, 0.0, 1.0];
+        assert!(mesh.validate().is_err());
+        mesh.normals = [0.0, 0.0, 1.0].repeat(3);
+        mesh.colors = vec![255; 12];
+        assert_eq!(mesh.validate(), Ok(()));
+    }
+
+    #[test]
+    fn indices_must_be_whole_triangles_in_range() {
+        let mut mesh = triangle();
+        mesh.indices = vec![0, 1];
+        assert!(mesh.validate().is_err());
+        mesh.indices = vec![0, 1, 3];
+        assert!(mesh.validate().is_err());
+    }
+
+    #[test]
+    fn normals_follow_the_winding() {
+        let mut mesh = triangle();
+        assert_eq!(mesh.face_normal(0), [0.0, 0.0, 1.0]);
+        mesh.recompute_normals();
+        assert_eq!(mesh.normals, [0.0, 0.0, 1.0].repeat(3));
+        // A second triangle folded up along the x axis tilts the shared
+        // edge's normals halfway.
+        mesh.positions.extend_from_slice(&[0.0, 0.0, -1.0]);
+        mesh.indices.extend_from_slice(&[0, 1, 3]);
+        mesh.recompute_normals();
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((mesh.normals[1] - half).abs() < 1e-6);
+        assert!((mesh.normals[2] - half).abs() < 1e-6);
+        assert_eq!(&mesh.normals[6..9], [0.0, 0.0, 1.0]);
+    }
+}
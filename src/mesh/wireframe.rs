@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+
+use crate::renderer::PrimitiveMode;
+
+/// The unique edges of a triangle list as a line list: two indices per
+/// edge, each edge once however many triangles share it, in the order
+/// they are first met. Edges collapsed to a point are left out.
+pub fn wireframe_edges(indices: &[u32]) -> Result<Vec<u32>, String> {
+    if !indices.len().is_multiple_of(3) {
+        return Err(format!("index count {} is not a multiple of 3", indices.len()));
+    }
+    let mut seen = HashSet::new();
+    let mut edges = Vec::new();
+    for triangle in indices.chunks_exact(3) {
+        for (a, b) in [
+            (triangle[0], triangle[1]),
+            (triangle[1], triangle[2]),
+            (triangle[2], triangle[0]),
+        ] {
+            if a != b && seen.insert((a.min(b), a.max(b))) {
+                edges.extend_from_slice(&[a, b]);
+            }
+        }
+    }
+    Ok(edges)
+}
+
+/// The triangles of a triangle list, strip or fan as a list, keeping
+/// every triangle's winding. Fails for point and line primitives.
+pub fn triangle_list(indices: &[u32], primitive: PrimitiveMode) -> Result<Vec<u32>, String> {
+    if !primitive.is_valid_count(indices.len()) {
+        return Err(format!(
+            "{} indices do not make whole {:?} primitives",
+            indices.len(),
+            primitive
+        ));
+    }
+    let count = primitive.primitive_count(indices.len());
+    match primitive {
+        PrimitiveMode::Triangles => Ok(indices.to_vec()),
+        // Every other triangle of a strip is wound the other way round.
+        PrimitiveMode::TriangleStrip => Ok((0..count)
+            .flat_map(|i| {
+                if i.is_multiple_of(2) {
+                    [indices[i], indices[i + 1], indices[i + 2]]
+                } else {
+                    [indices[i + 1], indices[i], indices[i + 2]]
+                }
+            })
+            .collect()),
+        PrimitiveMode::TriangleFan => Ok((0..count)
+            .flat_map(|i| [indices[0], indices[i + 1], indices[i + 2]])
+            .collect()),
+        PrimitiveMode::Points | PrimitiveMode::Lines | PrimitiveMode::LineStrip => {
+            Err(format!("{:?} primitives have no triangles", primitive))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge_set(edges: &[u32]) -> HashSet<(u32, u32)> {
+        edges
+            .chunks_exact(2)
+            .map(|edge| (edge[0].min(edge[1]), edge[0].max(edge[1])))
+            .collect()
+    }
+
+    #[test]
+    fn shared_edges_appear_once() {
+        // A quad split along its diagonal: four sides and the diagonal.
+        let edges = wireframe_edges(&[0, 1, 2, 0, 2, 3]).unwrap();
+        assert_eq!(edges.len(), 10);
+        let expected = [(0, 1), (1, 2), (0, 2), (2, 3), (0, 3)];
+        assert_eq!(edge_set(&edges), expected.iter().copied().collect());
+    }
+
+    #[test]
+    fn edges_keep_the_order_first_met() {
+        assert_eq!(wireframe_edges(&[4, 5, 6]).unwrap(), [4, 5, 5, 6, 6, 4]);
+    }
+
+    #[test]
+    fn closed_mesh_follows_euler() {
+        // An indexed cube with shared corners: 12 triangles, 8 vertices,
+        // so V - E + F = 2 gives 18 edges.
+        let indices = [
+            0, 1, 2, 0, 2, 3, 4, 6, 5, 4, 7, 6, 0, 4, 5, 0, 5, 1, //
+            1, 5, 6, 1, 6, 2, 2, 6, 7, 2, 7, 3, 3, 7, 4, 3, 4, 0,
+        ];
+        let edges = wireframe_edges(&indices).unwrap();
+        assert_eq!(edges.len() / 2, 18);
+        assert_eq!(edge_set(&edges).len(), 18);
+    }
+
+    #[test]
+    fn degenerate_edges_are_dropped() {
+        assert_eq!(wireframe_edges(&[0, 0, 1]).unwrap(), [0, 1]);
+    }
+
+    #[test]
+    fn rejects_partial_triangles() {
+        assert!(wireframe_edges(&[0, 1]).is_err());
+    }
+
+    #[test]
+    fn strips_alternate_winding() {
+        let list = triangle_list(&[0, 1, 2, 3, 4], PrimitiveMode::TriangleStrip).unwrap();
+        assert_eq!(list, [0, 1, 2, 2, 1, 3, 2, 3, 4]);
+    }
+
+    #[test]
+    fn fans_share_the_first_index() {
+        let list = triangle_list(&[0, 1, 2, 3], PrimitiveMode::TriangleFan).unwrap();
+        assert_eq!(list, [0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn lines_have_no_triangles() {
+        assert!(triangle_list(&[0, 1], PrimitiveMode::Lines).is_err());
+        assert!(triangle_list(&[0, 1, 2, 3], PrimitiveMode::Triangles).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::material::{Material, MaterialParam, ProgramCache};
+use crate::renderer::{ColorFormat, RenderTarget, ResourceManager};
+
+use super::{shaders, FullscreenPass, PostEffect};
+
+/// Glow around bright areas: extract the bright pixels into a half
+/// resolution target, blur them horizontally then vertically, and add the
+/// result back on top of the input. The blur targets hold half floats
+/// where the context can render to them, like the stack's own.
+#[derive(Debug)]
+pub struct Bloom {
+    enabled: bool,
+    bright_pass: Material,
+    blur: Material,
+    composite: Material,
+    ping: RenderTarget,
+    pong: RenderTarget,
+}
+
+impl Bloom {
+    pub fn new(resources: &ResourceManager, cache: &mut ProgramCache) -> Result<Bloom, JsValue> {
+        let context = resources.context();
+        let mut bright_pass = Material::named(
+            context,
+            cache,
+            "bloom_bright_pass",
+            shaders::FULLSCREEN_VERT,
+            shaders::BRIGHT_PASS_FRAG,
+        )?;
+        bright_pass.set_param("threshold", MaterialParam::Float(0.8));
+        let blur = Material::named(
+            context,
+            cache,
+            "bloom_blur",
+            shaders::FULLSCREEN_VERT,
+            shaders::BLUR_FRAG,
+        )?;
+        let mut composite = Material::named(
+            context,
+            cache,
+            "bloom_composite",
+            shaders::FULLSCREEN_VERT,
+            shaders::BLOOM_COMPOSITE_FRAG,
+        )?;
+        composite.set_param("intensity", MaterialParam::Float(1.0));
+
+        let format = ColorFormat::hdr(context)?;
+        Ok(Bloom {
+            enabled: false,
+            bright_pass,
+            blur,
+            composite,
+            ping: RenderTarget::with_format(resources, 1, 1, format)?,
+            pong: RenderTarget::with_format(resources, 1, 1, format)?,
+        })
+    }
+}
+
+impl PostEffect for Bloom {
+    fn name(&self) -> &str {
+        "bloom"
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn restore(&mut self, _context: &GL) -> Result<(), JsValue> {
+        self.ping.restore()?;
+        self.pong.restore()
+    }
+
+    fn set_param(&mut self, name: &str, value: MaterialParam) -> bool {
+        match name {
+            "threshold" => self.bright_pass.set_param(name, value),
+            "intensity" => self.composite.set_param(name, value),
+            _ => return false,
+        }
+        true
+    }
+
+    fn render(
+        &mut self,
+        context: &GL,
+        pass: &FullscreenPass,
+        input: &RenderTarget,
+        output: Option<&RenderTarget>,
+    ) -> Result<(), JsValue> {
+        let width = (input.width() / 2).max(1);
+        let height = (input.height() / 2).max(1);
+        self.ping.resize(width, height)?;
+        self.pong.resize(width, height)?;
+
+        self.bright_pass.set_param(
+            "input_texture",
+            MaterialParam::Texture(input.color_texture().clone()),
+        );
+        pass.draw(context, &self.bright_pass, Some(&self.ping))?;
+
+        self.blur.set_param(
+            "input_texture",
+            MaterialParam::Texture(self.ping.color_texture().clone()),
+        );
+        self.blur.set_param("direction", MaterialParam::Vec2([1.0 / width as f32, 0.0]));
+        pass.draw(context, &self.blur, Some(&self.pong))?;
+
+        self.blur.set_param(
+            "input_texture",
+            MaterialParam::Texture(self.pong.color_texture().clone()),
+        );
+        self.blur.set_param("direction", MaterialParam::Vec2([0.0, 1.0 / height as f32]));
+        pass.draw(context, &self.blur, Some(&self.ping))?;
+
+        self.composite.set_param(
+            "input_texture",
+            MaterialParam::Texture(input.color_texture().clone()),
+        );
+        self.composite.set_param(
+            "bloom_texture",
+            MaterialParam::Texture(self.ping.color_texture().clone()),
+        );
+        pass.draw(context, &self.composite, output)
+    }
+}
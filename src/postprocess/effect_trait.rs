@@ -0,0 +1,39 @@
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::material::MaterialParam;
+use crate::renderer::RenderTarget;
+
+use super::FullscreenPass;
+
+/// One stage of the post-processing stack.
+pub trait PostEffect {
+    /// The name JS uses to toggle and tune the effect.
+    fn name(&self) -> &str;
+
+    fn enabled(&self) -> bool;
+
+    fn set_enabled(&mut self, enabled: bool);
+
+    /// Set a shader parameter, e.g. `exposure` for tone mapping.
+    /// Returns `false` if the effect has no such parameter.
+    fn set_param(&mut self, name: &str, value: MaterialParam) -> bool;
+
+    /// Recreate any GL objects the effect owns after the context was
+    /// restored. Programs and render target attachments are restored by
+    /// the `ResourceManager`, so effects that only hold materials have
+    /// nothing to do.
+    fn restore(&mut self, _context: &GL) -> Result<(), JsValue> {
+        Ok(())
+    }
+
+    /// Read `input` and draw the result into `output`, or onto the canvas
+    /// when `output` is `None`.
+    fn render(
+        &mut self,
+        context: &GL,
+        pass: &FullscreenPass,
+        input: &RenderTarget,
+        output: Option<&RenderTarget>,
+    ) -> Result<(), JsValue>;
+}
@@ -0,0 +1,58 @@
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::material::Material;
+use crate::renderer::{BufferHandle, RenderTarget, ResourceManager};
+
+/// Draws a single triangle covering the whole destination, which is all a
+/// post-processing shader needs to touch every pixel once.
+#[derive(Debug)]
+pub struct FullscreenPass {
+    buffer: BufferHandle,
+}
+
+impl FullscreenPass {
+    pub fn new(resources: &ResourceManager) -> Result<FullscreenPass, JsValue> {
+        // Twice the size of clip space in x and y, so the [-1, 1] square
+        // is fully inside the triangle.
+        let positions: [f32; 6] = [
+            -1.0, -1.0, //
+            3.0, -1.0, //
+            -1.0, 3.0, //
+        ];
+        let buffer = resources.create_buffer(GL::ARRAY_BUFFER, &positions, GL::STATIC_DRAW)?;
+        Ok(FullscreenPass { buffer })
+    }
+
+    /// Run `material` over every pixel of `output`, or of the canvas when
+    /// `output` is `None`. The material's program must take a `vec2
+    /// position` attribute, like `shaders::FULLSCREEN_VERT`.
+    pub fn draw(
+        &self,
+        context: &GL,
+        material: &Material,
+        output: Option<&RenderTarget>,
+    ) -> Result<(), JsValue> {
+        match output {
+            Some(target) => target.bind(context),
+            None => {
+                let canvas = context
+                    .canvas()
+                    .ok_or("context has no canvas")?
+                    .dyn_into::<web_sys::HtmlCanvasElement>()?;
+                RenderTarget::unbind(context);
+                context.viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
+            }
+        }
+        context.disable(GL::DEPTH_TEST);
+
+        material.apply(context);
+        if let Some(position) = material.shader().attrib_location("position") {
+            context.bind_buffer(GL::ARRAY_BUFFER, self.buffer.get().as_ref());
+            context.vertex_attrib_pointer_with_i32(position, 2, GL::FLOAT, false, 0, 0);
+            context.enable_vertex_attrib_array(position);
+        }
+        context.draw_arrays(GL::TRIANGLES, 0, 3);
+        Ok(())
+    }
+}
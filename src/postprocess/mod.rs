@@ -0,0 +1,154 @@
+mod bloom;
+mod effect_trait;
+mod fullscreen_pass;
+mod shader_effect;
+pub mod shaders;
+
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::material::{MaterialParam, ProgramCache};
+use crate::renderer::{ColorFormat, RenderTarget, ResourceManager};
+
+pub use self::bloom::Bloom;
+pub use self::effect_trait::PostEffect;
+pub use self::fullscreen_pass::FullscreenPass;
+pub use self::shader_effect::{ShaderEffect, ToneMapping};
+
+/// An ordered chain of effects run after the scene pass.
+///
+/// While any effect is enabled the scene is drawn into `scene_target`
+/// instead of the canvas; `finish` then feeds it through each enabled
+/// effect in turn, ping-ponging between two targets, and the last effect
+/// writes to the canvas.
+///
+/// The targets hold half floats where the context can render to them, so
+/// bloom and tone mapping see the scene's brightness above 1, and RGBA8
+/// otherwise.
+pub struct PostProcessStack {
+    pass: FullscreenPass,
+    targets: [RenderTarget; 2],
+    effects: Vec<Box<dyn PostEffect>>,
+}
+
+impl PostProcessStack {
+    pub fn new(resources: &ResourceManager) -> Result<PostProcessStack, JsValue> {
+        let format = ColorFormat::hdr(resources.context())?;
+        Ok(PostProcessStack {
+            pass: FullscreenPass::new(resources)?,
+            targets: [
+                RenderTarget::for_canvas_with_format(resources, format)?,
+                RenderTarget::for_canvas_with_format(resources, format)?,
+            ],
+            effects: Vec::new(),
+        })
+    }
+
+    /// A stack with every built-in effect, all disabled, in the order they
+    /// should run: bloom and tone mapping on linear color, then grading,
+    /// gamma, FXAA on the final gamma-space image, and vignette last.
+    pub fn with_builtin_effects(
+        resources: &ResourceManager,
+        cache: &mut ProgramCache,
+    ) -> Result<PostProcessStack, JsValue> {
+        let context = resources.context();
+        let mut stack = PostProcessStack::new(resources)?;
+        stack.push(Box::new(Bloom::new(resources, cache)?));
+        stack.push(Box::new(ShaderEffect::tone_mapping(context, cache, ToneMapping::Aces)?));
+        stack.push(Box::new(ShaderEffect::color_grading(context, cache)?));
+        stack.push(Box::new(ShaderEffect::gamma(context, cache, 2.2)?));
+        stack.push(Box::new(ShaderEffect::fxaa(context, cache)?));
+        stack.push(Box::new(ShaderEffect::vignette(context, cache)?));
+        Ok(stack)
+    }
+
+    pub fn push(&mut self, effect: Box<dyn PostEffect>) {
+        self.effects.push(effect);
+    }
+
+    pub fn effect_mut(&mut self, name: &str) -> Option<&mut (dyn PostEffect + 'static)> {
+        self.effects
+            .iter_mut()
+            .find(|effect| effect.name() == name)
+            .map(|effect| effect.as_mut())
+    }
+
+    /// Returns `false` if there is no effect called `name`.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.effect_mut(name) {
+            Some(effect) => {
+                effect.set_enabled(enabled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns `false` if there is no such effect or parameter.
+    pub fn set_param(&mut self, name: &str, param: &str, value: MaterialParam) -> bool {
+        self.effect_mut(name)
+            .map(|effect| effect.set_param(param, value))
+            .unwrap_or(false)
+    }
+
+    pub fn effect_names(&self) -> Vec<&str> {
+        self.effects.iter().map(|effect| effect.name()).collect()
+    }
+
+    /// Whether the scene has to go through `scene_target` this frame.
+    pub fn is_active(&self) -> bool {
+        self.effects.iter().any(|effect| effect.enabled())
+    }
+
+    /// Recreate the targets' framebuffers and every effect's own GL
+    /// objects after the context was restored. The full-screen triangle
+    /// and the targets' attachments come back with the `ResourceManager`,
+    /// which has to be restored first.
+    pub fn restore(&mut self, context: &GL) -> Result<(), JsValue> {
+        for target in self.targets.iter_mut() {
+            target.restore()?;
+        }
+        for effect in self.effects.iter_mut() {
+            effect.restore(context)?;
+        }
+        Ok(())
+    }
+
+    /// Match the targets to the canvas and return the one to draw the
+    /// scene into.
+    pub fn begin(&mut self) -> Result<&RenderTarget, JsValue> {
+        for target in self.targets.iter_mut() {
+            target.resize_to_canvas()?;
+        }
+        Ok(&self.targets[0])
+    }
+
+    /// Run the enabled effects over what was drawn into the scene target
+    /// and present the result on the canvas.
+    pub fn finish(&mut self, context: &GL) -> Result<(), JsValue> {
+        let PostProcessStack {
+            pass,
+            targets,
+            effects,
+        } = self;
+        let mut enabled: Vec<&mut Box<dyn PostEffect>> = effects
+            .iter_mut()
+            .filter(|effect| effect.enabled())
+            .collect();
+        let last = enabled.len().saturating_sub(1);
+
+        let mut source = 0;
+        for (index, effect) in enabled.iter_mut().enumerate() {
+            let (input, output) = if source == 0 {
+                (&targets[0], &targets[1])
+            } else {
+                (&targets[1], &targets[0])
+            };
+            let output = if index == last { None } else { Some(output) };
+            effect.render(context, pass, input, output)?;
+            source = 1 - source;
+        }
+        RenderTarget::unbind(context);
+        Ok(())
+    }
+}
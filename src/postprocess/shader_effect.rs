@@ -0,0 +1,132 @@
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::material::{Material, MaterialParam, ProgramCache};
+use crate::renderer::RenderTarget;
+
+use super::{shaders, FullscreenPass, PostEffect};
+
+/// Which curve `ShaderEffect::tone_mapping` compresses colors with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapping {
+    Reinhard = 0,
+    Aces = 1,
+}
+
+/// An effect made of a single full-screen shader reading the previous
+/// stage from `input_texture`.
+#[derive(Debug)]
+pub struct ShaderEffect {
+    name: String,
+    enabled: bool,
+    material: Material,
+}
+
+impl ShaderEffect {
+    /// Build an effect from a fragment shader. The program is registered
+    /// under `name`, so it can be hot reloaded like any other.
+    pub fn new(
+        context: &GL,
+        cache: &mut ProgramCache,
+        name: &str,
+        frag_shader: &str,
+    ) -> Result<ShaderEffect, JsValue> {
+        Ok(ShaderEffect {
+            name: name.to_string(),
+            enabled: false,
+            material: Material::named(
+                context,
+                cache,
+                name,
+                shaders::FULLSCREEN_VERT,
+                frag_shader,
+            )?,
+        })
+    }
+
+    pub fn fxaa(context: &GL, cache: &mut ProgramCache) -> Result<ShaderEffect, JsValue> {
+        ShaderEffect::new(context, cache, "fxaa", shaders::FXAA_FRAG)
+    }
+
+    pub fn tone_mapping(
+        context: &GL,
+        cache: &mut ProgramCache,
+        operator: ToneMapping,
+    ) -> Result<ShaderEffect, JsValue> {
+        let mut effect =
+            ShaderEffect::new(context, cache, "tone_mapping", shaders::TONE_MAPPING_FRAG)?;
+        effect.set_param("exposure", MaterialParam::Float(1.0));
+        effect.set_param("tone_operator", MaterialParam::Int(operator as i32));
+        Ok(effect)
+    }
+
+    pub fn color_grading(
+        context: &GL,
+        cache: &mut ProgramCache,
+    ) -> Result<ShaderEffect, JsValue> {
+        let mut effect =
+            ShaderEffect::new(context, cache, "color_grading", shaders::COLOR_GRADING_FRAG)?;
+        effect.set_param("brightness", MaterialParam::Float(0.0));
+        effect.set_param("contrast", MaterialParam::Float(1.0));
+        effect.set_param("saturation", MaterialParam::Float(1.0));
+        Ok(effect)
+    }
+
+    pub fn gamma(
+        context: &GL,
+        cache: &mut ProgramCache,
+        gamma: f32,
+    ) -> Result<ShaderEffect, JsValue> {
+        let mut effect = ShaderEffect::new(context, cache, "gamma", shaders::GAMMA_FRAG)?;
+        effect.set_param("gamma", MaterialParam::Float(gamma));
+        Ok(effect)
+    }
+
+    pub fn vignette(context: &GL, cache: &mut ProgramCache) -> Result<ShaderEffect, JsValue> {
+        let mut effect = ShaderEffect::new(context, cache, "vignette", shaders::VIGNETTE_FRAG)?;
+        effect.set_param("radius", MaterialParam::Float(0.45));
+        effect.set_param("softness", MaterialParam::Float(0.35));
+        effect.set_param("strength", MaterialParam::Float(0.6));
+        Ok(effect)
+    }
+}
+
+impl PostEffect for ShaderEffect {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn set_param(&mut self, name: &str, value: MaterialParam) -> bool {
+        if self.material.shader().uniform_location(name).is_none() {
+            return false;
+        }
+        self.material.set_param(name, value);
+        true
+    }
+
+    fn render(
+        &mut self,
+        context: &GL,
+        pass: &FullscreenPass,
+        input: &RenderTarget,
+        output: Option<&RenderTarget>,
+    ) -> Result<(), JsValue> {
+        self.material.set_param(
+            "input_texture",
+            MaterialParam::Texture(input.color_texture().clone()),
+        );
+        self.material.set_param(
+            "resolution",
+            MaterialParam::Vec2([input.width() as f32, input.height() as f32]),
+        );
+        pass.draw(context, &self.material, output)
+    }
+}
@@ -0,0 +1,210 @@
+//! GLSL for the full-screen pass and the built-in effects.
+//!
+//! Every fragment shader samples its input from `input_texture` at `vUv`;
+//! `resolution` holds the size in pixels of that input.
+
+/// Covers the viewport with one oversized triangle; the part outside
+/// clip space is discarded by the rasterizer.
+pub const FULLSCREEN_VERT: &str = r#"
+    attribute vec2 position;
+
+    varying highp vec2 vUv;
+
+    void main() {
+        vUv = position * 0.5 + 0.5;
+        gl_Position = vec4(position, 0.0, 1.0);
+    }
+"#;
+
+/// The low-quality FXAA variant from Timothy Lottes' paper: one edge
+/// direction estimate from the 4 diagonal neighbours, two blend taps.
+pub const FXAA_FRAG: &str = r#"
+    precision mediump float;
+
+    #define FXAA_REDUCE_MIN (1.0 / 128.0)
+    #define FXAA_REDUCE_MUL (1.0 / 8.0)
+    #define FXAA_SPAN_MAX 8.0
+
+    uniform sampler2D input_texture;
+    uniform vec2 resolution;
+
+    varying highp vec2 vUv;
+
+    void main() {
+        vec2 texel = 1.0 / resolution;
+        vec3 rgbNW = texture2D(input_texture, vUv + vec2(-1.0, -1.0) * texel).rgb;
+        vec3 rgbNE = texture2D(input_texture, vUv + vec2(1.0, -1.0) * texel).rgb;
+        vec3 rgbSW = texture2D(input_texture, vUv + vec2(-1.0, 1.0) * texel).rgb;
+        vec3 rgbSE = texture2D(input_texture, vUv + vec2(1.0, 1.0) * texel).rgb;
+        vec4 rgbaM = texture2D(input_texture, vUv);
+
+        vec3 luma = vec3(0.299, 0.587, 0.114);
+        float lumaNW = dot(rgbNW, luma);
+        float lumaNE = dot(rgbNE, luma);
+        float lumaSW = dot(rgbSW, luma);
+        float lumaSE = dot(rgbSE, luma);
+        float lumaM = dot(rgbaM.rgb, luma);
+        float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+        float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+        vec2 dir;
+        dir.x = -((lumaNW + lumaNE) - (lumaSW + lumaSE));
+        dir.y = ((lumaNW + lumaSW) - (lumaNE + lumaSE));
+
+        float dirReduce = max(
+            (lumaNW + lumaNE + lumaSW + lumaSE) * (0.25 * FXAA_REDUCE_MUL),
+            FXAA_REDUCE_MIN);
+        float rcpDirMin = 1.0 / (min(abs(dir.x), abs(dir.y)) + dirReduce);
+        dir = min(vec2(FXAA_SPAN_MAX), max(vec2(-FXAA_SPAN_MAX), dir * rcpDirMin)) * texel;
+
+        vec3 rgbA = 0.5 * (
+            texture2D(input_texture, vUv + dir * (1.0 / 3.0 - 0.5)).rgb +
+            texture2D(input_texture, vUv + dir * (2.0 / 3.0 - 0.5)).rgb);
+        vec3 rgbB = rgbA * 0.5 + 0.25 * (
+            texture2D(input_texture, vUv + dir * -0.5).rgb +
+            texture2D(input_texture, vUv + dir * 0.5).rgb);
+
+        float lumaB = dot(rgbB, luma);
+        if (lumaB < lumaMin || lumaB > lumaMax) {
+            gl_FragColor = vec4(rgbA, rgbaM.a);
+        } else {
+            gl_FragColor = vec4(rgbB, rgbaM.a);
+        }
+    }
+"#;
+
+/// Keeps only the pixels brighter than `threshold`, for bloom.
+pub const BRIGHT_PASS_FRAG: &str = r#"
+    precision mediump float;
+
+    uniform sampler2D input_texture;
+    uniform float threshold;
+
+    varying highp vec2 vUv;
+
+    void main() {
+        vec4 color = texture2D(input_texture, vUv);
+        float brightness = dot(color.rgb, vec3(0.2126, 0.7152, 0.0722));
+        gl_FragColor = vec4(color.rgb * smoothstep(threshold, threshold + 0.1, brightness), 1.0);
+    }
+"#;
+
+/// One axis of a separable 9-tap Gaussian, using linear filtering to
+/// fetch two texels per tap. `direction` is one texel along the axis.
+pub const BLUR_FRAG: &str = r#"
+    precision mediump float;
+
+    uniform sampler2D input_texture;
+    uniform vec2 direction;
+
+    varying highp vec2 vUv;
+
+    void main() {
+        vec2 off1 = direction * 1.3846153846;
+        vec2 off2 = direction * 3.2307692308;
+        vec4 sum = texture2D(input_texture, vUv) * 0.2270270270;
+        sum += (texture2D(input_texture, vUv + off1) + texture2D(input_texture, vUv - off1)) * 0.3162162162;
+        sum += (texture2D(input_texture, vUv + off2) + texture2D(input_texture, vUv - off2)) * 0.0702702703;
+        gl_FragColor = sum;
+    }
+"#;
+
+pub const BLOOM_COMPOSITE_FRAG: &str = r#"
+    precision mediump float;
+
+    uniform sampler2D input_texture;
+    uniform sampler2D bloom_texture;
+    uniform float intensity;
+
+    varying highp vec2 vUv;
+
+    void main() {
+        vec4 color = texture2D(input_texture, vUv);
+        vec3 bloom = texture2D(bloom_texture, vUv).rgb;
+        gl_FragColor = vec4(color.rgb + bloom * intensity, color.a);
+    }
+"#;
+
+/// `tone_operator` 0 is Reinhard, 1 is the Narkowicz fit of the ACES filmic curve.
+pub const TONE_MAPPING_FRAG: &str = r#"
+    precision mediump float;
+
+    uniform sampler2D input_texture;
+    uniform float exposure;
+    uniform int tone_operator;
+
+    varying highp vec2 vUv;
+
+    vec3 reinhard(vec3 x) {
+        return x / (1.0 + x);
+    }
+
+    vec3 aces(vec3 x) {
+        const float a = 2.51;
+        const float b = 0.03;
+        const float c = 2.43;
+        const float d = 0.59;
+        const float e = 0.14;
+        return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+    }
+
+    void main() {
+        vec4 color = texture2D(input_texture, vUv);
+        vec3 exposed = color.rgb * exposure;
+        vec3 mapped = tone_operator == 1 ? aces(exposed) : reinhard(exposed);
+        gl_FragColor = vec4(mapped, color.a);
+    }
+"#;
+
+pub const COLOR_GRADING_FRAG: &str = r#"
+    precision mediump float;
+
+    uniform sampler2D input_texture;
+    uniform float brightness;
+    uniform float contrast;
+    uniform float saturation;
+
+    varying highp vec2 vUv;
+
+    void main() {
+        vec4 color = texture2D(input_texture, vUv);
+        vec3 graded = color.rgb + brightness;
+        graded = (graded - 0.5) * contrast + 0.5;
+        float luma = dot(graded, vec3(0.2126, 0.7152, 0.0722));
+        graded = mix(vec3(luma), graded, saturation);
+        gl_FragColor = vec4(clamp(graded, 0.0, 1.0), color.a);
+    }
+"#;
+
+pub const GAMMA_FRAG: &str = r#"
+    precision mediump float;
+
+    uniform sampler2D input_texture;
+    uniform float gamma;
+
+    varying highp vec2 vUv;
+
+    void main() {
+        vec4 color = texture2D(input_texture, vUv);
+        gl_FragColor = vec4(pow(color.rgb, vec3(1.0 / gamma)), color.a);
+    }
+"#;
+
+/// Darkens towards the corners, starting at `radius` from the center and
+/// fading in over `softness`.
+pub const VIGNETTE_FRAG: &str = r#"
+    precision mediump float;
+
+    uniform sampler2D input_texture;
+    uniform float radius;
+    uniform float softness;
+    uniform float strength;
+
+    varying highp vec2 vUv;
+
+    void main() {
+        vec4 color = texture2D(input_texture, vUv);
+        float falloff = smoothstep(radius, radius + softness, distance(vUv, vec2(0.5)));
+        gl_FragColor = vec4(color.rgb * (1.0 - falloff * strength), color.a);
+    }
+"#;
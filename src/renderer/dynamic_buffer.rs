@@ -0,0 +1,298 @@
+use std::ops::Range;
+
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use super::{upload, BufferHandle, ResourceManager};
+
+/// How often a `DynamicBuffer` is expected to change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferUsage {
+    /// Rewritten now and then, drawn many times in between.
+    Dynamic,
+    /// Rewritten about once per draw, such as streamed sensor data.
+    Stream,
+}
+
+impl BufferUsage {
+    pub fn gl_usage(self) -> u32 {
+        match self {
+            BufferUsage::Dynamic => GL::DYNAMIC_DRAW,
+            BufferUsage::Stream => GL::STREAM_DRAW,
+        }
+    }
+}
+
+/// A float buffer that is updated in place rather than recreated.
+///
+/// Writes go to a CPU-side copy and mark the touched range dirty; `upload`
+/// then sends only the dirty ranges with `bufferSubData`. When the data
+/// outgrows the GPU allocation it is reallocated at (at least) twice the
+/// size, so a buffer that keeps growing is reallocated only a logarithmic
+/// number of times.
+///
+/// The GPU allocation is owned by a `ResourceManager`, which counts it and
+/// deletes it with the last handle. After a context loss the manager
+/// recreates it empty; `restore` then has the next `upload` refill it.
+#[derive(Debug)]
+pub struct DynamicBuffer {
+    resources: ResourceManager,
+    buffer: BufferHandle,
+    target: u32,
+    usage: BufferUsage,
+    data: Vec<f32>,
+    /// Floats allocated on the GPU.
+    capacity: usize,
+    dirty: DirtyRanges,
+}
+
+impl DynamicBuffer {
+    /// An empty buffer bound to `target`, usually `ARRAY_BUFFER`.
+    pub fn new(
+        resources: &ResourceManager,
+        target: u32,
+        usage: BufferUsage,
+    ) -> Result<DynamicBuffer, JsValue> {
+        DynamicBuffer::with_capacity(resources, target, usage, 0)
+    }
+
+    /// Like `new`, with room for `capacity` floats allocated up front.
+    pub fn with_capacity(
+        resources: &ResourceManager,
+        target: u32,
+        usage: BufferUsage,
+        capacity: usize,
+    ) -> Result<DynamicBuffer, JsValue> {
+        let buffer = resources.create_buffer_storage(
+            target,
+            capacity * upload::element_size::<f32>(),
+            usage.gl_usage(),
+        )?;
+        Ok(DynamicBuffer {
+            resources: resources.clone(),
+            buffer,
+            target,
+            usage,
+            data: Vec::with_capacity(capacity),
+            capacity,
+            dirty: DirtyRanges::default(),
+        })
+    }
+
+    pub fn buffer(&self) -> &BufferHandle {
+        &self.buffer
+    }
+
+    pub fn usage(&self) -> BufferUsage {
+        self.usage
+    }
+
+    /// The CPU-side copy, including changes not uploaded yet.
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Floats the GPU allocation can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Ranges written since the last `upload`.
+    pub fn dirty_ranges(&self) -> &[Range<usize>] {
+        self.dirty.ranges()
+    }
+
+    /// Overwrite the floats starting at `offset`, growing the buffer if
+    /// `values` runs past the end. Any gap is filled with zeros.
+    pub fn write(&mut self, offset: usize, values: &[f32]) {
+        let end = offset + values.len();
+        if end > self.data.len() {
+            self.data.resize(end, 0.0);
+        }
+        self.data[offset..end].copy_from_slice(values);
+        self.dirty.mark(offset..end);
+    }
+
+    /// Append `values` at the end.
+    pub fn push(&mut self, values: &[f32]) {
+        let offset = self.data.len();
+        self.write(offset, values);
+    }
+
+    /// Replace the whole contents.
+    pub fn set(&mut self, values: &[f32]) {
+        self.data.clear();
+        self.dirty.clear();
+        self.write(0, values);
+    }
+
+    /// Drop everything past `len` floats. The GPU allocation is kept.
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+        self.dirty.truncate(len);
+    }
+
+    /// Mark all the data dirty once the `ResourceManager` has been
+    /// restored, so the next `upload` sends it again.
+    pub fn restore(&mut self) {
+        self.dirty.mark(0..self.data.len());
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Send pending changes to the GPU, reallocating first if the data no
+    /// longer fits. Leaves the buffer bound to its target.
+    pub fn upload(&mut self) -> Result<(), JsValue> {
+        let context = self.resources.context();
+        if self.data.len() > self.capacity {
+            // A reallocation discards the old contents, so everything is
+            // sent again.
+            self.capacity = self.data.len().max(self.capacity * 2);
+            self.resources.resize_buffer(
+                &self.buffer,
+                self.target,
+                self.capacity * upload::element_size::<f32>(),
+                self.usage.gl_usage(),
+            )?;
+            self.dirty.clear();
+            self.dirty.mark(0..self.data.len());
+        } else {
+            self.bind();
+        }
+        for range in self.dirty.take() {
+            upload::buffer_sub_data(
+                context,
+                self.target,
+                range.start * upload::element_size::<f32>(),
+                &self.data[range],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn bind(&self) {
+        self.resources
+            .state()
+            .bind_buffer(self.target, self.buffer.get().as_ref());
+    }
+}
+
+/// Sorted, non-overlapping ranges of elements not yet uploaded.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct DirtyRanges {
+    ranges: Vec<Range<usize>>,
+}
+
+impl DirtyRanges {
+    fn ranges(&self) -> &[Range<usize>] {
+        &self.ranges
+    }
+
+    /// Add `range`, merging it with any range it overlaps or touches.
+    fn mark(&mut self, mut range: Range<usize>) {
+        if range.start >= range.end {
+            return;
+        }
+        let mut merged = Vec::with_capacity(self.ranges.len() + 1);
+        for existing in self.ranges.drain(..) {
+            if existing.end < range.start || range.end < existing.start {
+                merged.push(existing);
+            } else {
+                range.start = range.start.min(existing.start);
+                range.end = range.end.max(existing.end);
+            }
+        }
+        let position = merged
+            .iter()
+            .position(|existing| existing.start > range.start)
+            .unwrap_or(merged.len());
+        merged.insert(position, range);
+        self.ranges = merged;
+    }
+
+    /// Forget everything at or past `len`.
+    fn truncate(&mut self, len: usize) {
+        for range in self.ranges.iter_mut() {
+            range.end = range.end.min(len);
+        }
+        self.ranges.retain(|range| range.start < range.end);
+    }
+
+    fn clear(&mut self) {
+        self.ranges.clear();
+    }
+
+    /// Hand over the ranges, leaving none.
+    fn take(&mut self) -> Vec<Range<usize>> {
+        std::mem::take(&mut self.ranges)
+    }
+}
+
+#[cfg(test)]
+// The vectors of one range are exactly what is being compared.
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    fn marked(ranges: &[Range<usize>]) -> Vec<Range<usize>> {
+        let mut dirty = DirtyRanges::default();
+        for range in ranges {
+            dirty.mark(range.clone());
+        }
+        dirty.take()
+    }
+
+    #[test]
+    fn disjoint_ranges_stay_apart_in_order() {
+        assert_eq!(marked(&[8..10, 0..2, 4..6]), vec![0..2, 4..6, 8..10]);
+    }
+
+    #[test]
+    fn overlapping_ranges_merge() {
+        assert_eq!(marked(&[0..4, 2..6]), vec![0..6]);
+        assert_eq!(marked(&[2..6, 0..4]), vec![0..6]);
+        assert_eq!(marked(&[0..10, 3..5]), vec![0..10]);
+    }
+
+    #[test]
+    fn adjacent_ranges_merge() {
+        assert_eq!(marked(&[0..4, 4..8]), vec![0..8]);
+        assert_eq!(marked(&[4..8, 0..4]), vec![0..8]);
+    }
+
+    #[test]
+    fn a_range_can_bridge_several() {
+        assert_eq!(marked(&[0..2, 4..6, 8..10, 1..9]), vec![0..10]);
+        assert_eq!(marked(&[0..2, 4..6, 8..10, 2..4]), vec![0..6, 8..10]);
+    }
+
+    #[test]
+    fn empty_ranges_are_ignored() {
+        assert_eq!(marked(&[3..3, 5..5]), vec![]);
+        assert_eq!(marked(&[3..3, 0..2]), vec![0..2]);
+    }
+
+    #[test]
+    fn truncate_clips_and_drops_ranges() {
+        let mut dirty = DirtyRanges::default();
+        dirty.mark(0..2);
+        dirty.mark(4..8);
+        dirty.mark(10..12);
+        dirty.truncate(6);
+        assert_eq!(dirty.ranges(), &[0..2, 4..6]);
+        dirty.truncate(4);
+        assert_eq!(dirty.ranges(), &[0..2]);
+        dirty.truncate(0);
+        assert!(dirty.ranges().is_empty());
+    }
+}
@@ -0,0 +1,400 @@
+use std::{cell::RefCell, rc::Rc};
+
+use web_sys::{WebGlBuffer, WebGlProgram, WebGlRenderingContext as GL};
+
+/// GL state calls made through a `GlState`, split into those passed on to
+/// the context and those skipped because they would not change anything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StateCounters {
+    pub issued: u32,
+    pub skipped: u32,
+}
+
+impl StateCounters {
+    pub fn total(&self) -> u32 {
+        self.issued + self.skipped
+    }
+}
+
+/// The last value set for each piece of GL state, so setting the same
+/// value again can be skipped. `None` means unknown: the next call always
+/// goes through.
+///
+/// Buffers and programs are generic so the bookkeeping can be exercised
+/// without a context; `GlState` uses the web-sys objects.
+#[derive(Debug, Clone)]
+pub struct StateCache<B, P> {
+    array_buffer: Option<Option<B>>,
+    element_array_buffer: Option<Option<B>>,
+    program: Option<Option<P>>,
+    capabilities: Vec<(u32, bool)>,
+    blend_func: Option<(u32, u32)>,
+    blend_equation: Option<u32>,
+    depth_func: Option<u32>,
+    depth_mask: Option<bool>,
+    cull_face: Option<u32>,
+    color_mask: Option<[bool; 4]>,
+    polygon_offset: Option<(f32, f32)>,
+    viewport: Option<[i32; 4]>,
+    clear_color: Option<[f32; 4]>,
+    clear_depth: Option<f32>,
+    counters: StateCounters,
+}
+
+impl<B, P> Default for StateCache<B, P> {
+    fn default() -> StateCache<B, P> {
+        StateCache {
+            array_buffer: None,
+            element_array_buffer: None,
+            program: None,
+            capabilities: Vec::new(),
+            blend_func: None,
+            blend_equation: None,
+            depth_func: None,
+            depth_mask: None,
+            cull_face: None,
+            color_mask: None,
+            polygon_offset: None,
+            viewport: None,
+            clear_color: None,
+            clear_depth: None,
+            counters: StateCounters::default(),
+        }
+    }
+}
+
+/// Each setter records the value and returns whether the GL call has to
+/// be made.
+impl<B: PartialEq, P: PartialEq> StateCache<B, P> {
+    pub fn new() -> StateCache<B, P> {
+        StateCache::default()
+    }
+
+    /// Forget everything, e.g. after code that talks to the context
+    /// directly has run or the context was restored.
+    pub fn invalidate(&mut self) {
+        let counters = self.counters;
+        *self = StateCache::default();
+        self.counters = counters;
+    }
+
+    pub fn counters(&self) -> StateCounters {
+        self.counters
+    }
+
+    pub fn reset_counters(&mut self) {
+        self.counters = StateCounters::default();
+    }
+
+    /// Bindings to targets other than the array and element array
+    /// buffers are not tracked and always go through.
+    pub fn bind_buffer(&mut self, target: u32, buffer: Option<B>) -> bool {
+        let slot = match target {
+            GL::ARRAY_BUFFER => &mut self.array_buffer,
+            GL::ELEMENT_ARRAY_BUFFER => &mut self.element_array_buffer,
+            _ => return self.issue(),
+        };
+        update(slot, buffer, &mut self.counters)
+    }
+
+    pub fn use_program(&mut self, program: Option<P>) -> bool {
+        update(&mut self.program, program, &mut self.counters)
+    }
+
+    /// `enable` or `disable` a capability such as `DEPTH_TEST`.
+    pub fn set_capability(&mut self, capability: u32, enabled: bool) -> bool {
+        match self.capabilities.iter_mut().find(|(cap, _)| *cap == capability) {
+            Some((_, current)) if *current == enabled => self.skip(),
+            Some((_, current)) => {
+                *current = enabled;
+                self.issue()
+            }
+            None => {
+                self.capabilities.push((capability, enabled));
+                self.issue()
+            }
+        }
+    }
+
+    pub fn blend_func(&mut self, source: u32, destination: u32) -> bool {
+        update(&mut self.blend_func, (source, destination), &mut self.counters)
+    }
+
+    pub fn blend_equation(&mut self, mode: u32) -> bool {
+        update(&mut self.blend_equation, mode, &mut self.counters)
+    }
+
+    pub fn depth_func(&mut self, func: u32) -> bool {
+        update(&mut self.depth_func, func, &mut self.counters)
+    }
+
+    pub fn depth_mask(&mut self, write: bool) -> bool {
+        update(&mut self.depth_mask, write, &mut self.counters)
+    }
+
+    pub fn cull_face(&mut self, mode: u32) -> bool {
+        update(&mut self.cull_face, mode, &mut self.counters)
+    }
+
+    pub fn color_mask(&mut self, red: bool, green: bool, blue: bool, alpha: bool) -> bool {
+        update(&mut self.color_mask, [red, green, blue, alpha], &mut self.counters)
+    }
+
+    pub fn polygon_offset(&mut self, factor: f32, units: f32) -> bool {
+        update(&mut self.polygon_offset, (factor, units), &mut self.counters)
+    }
+
+    pub fn viewport(&mut self, x: i32, y: i32, width: i32, height: i32) -> bool {
+        update(&mut self.viewport, [x, y, width, height], &mut self.counters)
+    }
+
+    pub fn clear_color(&mut self, red: f32, green: f32, blue: f32, alpha: f32) -> bool {
+        update(&mut self.clear_color, [red, green, blue, alpha], &mut self.counters)
+    }
+
+    pub fn clear_depth(&mut self, depth: f32) -> bool {
+        update(&mut self.clear_depth, depth, &mut self.counters)
+    }
+
+    fn issue(&mut self) -> bool {
+        self.counters.issued += 1;
+        true
+    }
+
+    fn skip(&mut self) -> bool {
+        self.counters.skipped += 1;
+        false
+    }
+}
+
+fn update<T: PartialEq>(slot: &mut Option<T>, value: T, counters: &mut StateCounters) -> bool {
+    if slot.as_ref() == Some(&value) {
+        counters.skipped += 1;
+        false
+    } else {
+        *slot = Some(value);
+        counters.issued += 1;
+        true
+    }
+}
+
+/// The context behind a `StateCache`: state calls made through it only
+/// reach GL when they change something.
+///
+/// Like `ResourceManager` this is a shared reference, so every clone sees
+/// the same cache. Code that changes tracked state on the context directly
+/// must call `invalidate` afterwards, or the cache may skip a call that
+/// was needed.
+#[derive(Debug, Clone)]
+pub struct GlState {
+    context: GL,
+    cache: Rc<RefCell<StateCache<WebGlBuffer, WebGlProgram>>>,
+}
+
+impl GlState {
+    pub fn new(context: &GL) -> GlState {
+        GlState {
+            context: context.clone(),
+            cache: Rc::new(RefCell::new(StateCache::new())),
+        }
+    }
+
+    pub fn context(&self) -> &GL {
+        &self.context
+    }
+
+    pub fn invalidate(&self) {
+        self.cache.borrow_mut().invalidate();
+    }
+
+    pub fn counters(&self) -> StateCounters {
+        self.cache.borrow().counters()
+    }
+
+    pub fn reset_counters(&self) {
+        self.cache.borrow_mut().reset_counters();
+    }
+
+    pub fn bind_buffer(&self, target: u32, buffer: Option<&WebGlBuffer>) {
+        if self.cache.borrow_mut().bind_buffer(target, buffer.cloned()) {
+            self.context.bind_buffer(target, buffer);
+        }
+    }
+
+    pub fn use_program(&self, program: Option<&WebGlProgram>) {
+        if self.cache.borrow_mut().use_program(program.cloned()) {
+            self.context.use_program(program);
+        }
+    }
+
+    pub fn enable(&self, capability: u32) {
+        if self.cache.borrow_mut().set_capability(capability, true) {
+            self.context.enable(capability);
+        }
+    }
+
+    pub fn disable(&self, capability: u32) {
+        if self.cache.borrow_mut().set_capability(capability, false) {
+            self.context.disable(capability);
+        }
+    }
+
+    pub fn blend_func(&self, source: u32, destination: u32) {
+        if self.cache.borrow_mut().blend_func(source, destination) {
+            self.context.blend_func(source, destination);
+        }
+    }
+
+    pub fn blend_equation(&self, mode: u32) {
+        if self.cache.borrow_mut().blend_equation(mode) {
+            self.context.blend_equation(mode);
+        }
+    }
+
+    pub fn depth_func(&self, func: u32) {
+        if self.cache.borrow_mut().depth_func(func) {
+            self.context.depth_func(func);
+        }
+    }
+
+    pub fn depth_mask(&self, write: bool) {
+        if self.cache.borrow_mut().depth_mask(write) {
+            self.context.depth_mask(write);
+        }
+    }
+
+    pub fn cull_face(&self, mode: u32) {
+        if self.cache.borrow_mut().cull_face(mode) {
+            self.context.cull_face(mode);
+        }
+    }
+
+    pub fn color_mask(&self, red: bool, green: bool, blue: bool, alpha: bool) {
+        if self.cache.borrow_mut().color_mask(red, green, blue, alpha) {
+            self.context.color_mask(red, green, blue, alpha);
+        }
+    }
+
+    pub fn polygon_offset(&self, factor: f32, units: f32) {
+        if self.cache.borrow_mut().polygon_offset(factor, units) {
+            self.context.polygon_offset(factor, units);
+        }
+    }
+
+    pub fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+        if self.cache.borrow_mut().viewport(x, y, width, height) {
+            self.context.viewport(x, y, width, height);
+        }
+    }
+
+    pub fn clear_color(&self, red: f32, green: f32, blue: f32, alpha: f32) {
+        if self.cache.borrow_mut().clear_color(red, green, blue, alpha) {
+            self.context.clear_color(red, green, blue, alpha);
+        }
+    }
+
+    pub fn clear_depth(&self, depth: f32) {
+        if self.cache.borrow_mut().clear_depth(depth) {
+            self.context.clear_depth(depth);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Cache = StateCache<u32, u32>;
+
+    #[test]
+    fn repeated_values_are_skipped() {
+        let mut cache = Cache::new();
+        assert!(cache.use_program(Some(1)));
+        assert!(!cache.use_program(Some(1)));
+        assert!(cache.use_program(Some(2)));
+        assert!(cache.use_program(None));
+        assert!(!cache.use_program(None));
+        assert_eq!(cache.counters(), StateCounters { issued: 3, skipped: 2 });
+    }
+
+    #[test]
+    fn buffer_targets_are_tracked_separately() {
+        let mut cache = Cache::new();
+        assert!(cache.bind_buffer(GL::ARRAY_BUFFER, Some(1)));
+        assert!(cache.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(1)));
+        assert!(!cache.bind_buffer(GL::ARRAY_BUFFER, Some(1)));
+        assert!(!cache.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(1)));
+        assert!(cache.bind_buffer(GL::ARRAY_BUFFER, Some(2)));
+        // Other targets are not tracked.
+        let uniform_buffer = web_sys::WebGl2RenderingContext::UNIFORM_BUFFER;
+        assert!(cache.bind_buffer(uniform_buffer, Some(1)));
+        assert!(cache.bind_buffer(uniform_buffer, Some(1)));
+    }
+
+    #[test]
+    fn capabilities_are_tracked_separately() {
+        let mut cache = Cache::new();
+        assert!(cache.set_capability(GL::DEPTH_TEST, true));
+        assert!(cache.set_capability(GL::BLEND, false));
+        assert!(!cache.set_capability(GL::DEPTH_TEST, true));
+        assert!(!cache.set_capability(GL::BLEND, false));
+        assert!(cache.set_capability(GL::BLEND, true));
+        assert!(!cache.set_capability(GL::DEPTH_TEST, true));
+    }
+
+    #[test]
+    fn fixed_function_state_is_compared_as_a_whole() {
+        let mut cache = Cache::new();
+        assert!(cache.viewport(0, 0, 640, 480));
+        assert!(!cache.viewport(0, 0, 640, 480));
+        assert!(cache.viewport(0, 0, 640, 481));
+        assert!(cache.blend_func(GL::SRC_ALPHA, GL::ONE_MINUS_SRC_ALPHA));
+        assert!(cache.blend_func(GL::ONE, GL::ONE_MINUS_SRC_ALPHA));
+        assert!(!cache.blend_func(GL::ONE, GL::ONE_MINUS_SRC_ALPHA));
+        assert!(cache.clear_color(0.0, 0.0, 0.0, 1.0));
+        assert!(!cache.clear_color(0.0, 0.0, 0.0, 1.0));
+        assert!(cache.depth_mask(false));
+        assert!(!cache.depth_mask(false));
+        assert!(cache.depth_func(GL::LEQUAL));
+        assert!(!cache.depth_func(GL::LEQUAL));
+    }
+
+    #[test]
+    fn invalidate_forgets_state_but_keeps_counters() {
+        let mut cache = Cache::new();
+        cache.set_capability(GL::DEPTH_TEST, true);
+        cache.clear_depth(1.0);
+        cache.clear_depth(1.0);
+        cache.invalidate();
+        assert_eq!(cache.counters(), StateCounters { issued: 2, skipped: 1 });
+        assert!(cache.set_capability(GL::DEPTH_TEST, true));
+        assert!(cache.clear_depth(1.0));
+        cache.reset_counters();
+        assert_eq!(cache.counters(), StateCounters::default());
+    }
+
+    #[test]
+    fn steady_frames_only_pay_once() {
+        // The calls a frame of the scene makes, repeated: after the first
+        // frame nothing needs to reach GL.
+        let mut cache = Cache::new();
+        let frame = |cache: &mut Cache| {
+            cache.viewport(0, 0, 640, 480);
+            cache.clear_color(0.0, 0.0, 0.0, 1.0);
+            cache.clear_depth(1.0);
+            cache.set_capability(GL::DEPTH_TEST, true);
+            cache.use_program(Some(7));
+            cache.bind_buffer(GL::ARRAY_BUFFER, Some(1));
+            cache.bind_buffer(GL::ARRAY_BUFFER, Some(2));
+            cache.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(3));
+        };
+        frame(&mut cache);
+        assert_eq!(cache.counters(), StateCounters { issued: 8, skipped: 0 });
+        cache.reset_counters();
+        frame(&mut cache);
+        frame(&mut cache);
+        // Position and color buffers take turns on ARRAY_BUFFER.
+        assert_eq!(cache.counters(), StateCounters { issued: 4, skipped: 12 });
+        assert_eq!(cache.counters().total(), 16);
+    }
+}
@@ -0,0 +1,279 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{WebGl2RenderingContext, WebGlRenderingContext as GL};
+
+use super::{BufferHandle, GlState, ResourceManager};
+
+/// Element type of an index buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexType {
+    U8,
+    U16,
+    U32,
+}
+
+impl IndexType {
+    /// The smallest type that can hold `max_index`. Each type's largest
+    /// value is left out: WebGL2 treats it as the primitive restart index,
+    /// so a vertex stored there would never be drawn.
+    pub fn for_max_index(max_index: u32) -> IndexType {
+        if max_index < u8::MAX as u32 {
+            IndexType::U8
+        } else if max_index < u16::MAX as u32 {
+            IndexType::U16
+        } else {
+            IndexType::U32
+        }
+    }
+
+    /// The `type` argument for `draw_elements`.
+    pub fn gl_type(self) -> u32 {
+        match self {
+            IndexType::U8 => GL::UNSIGNED_BYTE,
+            IndexType::U16 => GL::UNSIGNED_SHORT,
+            IndexType::U32 => GL::UNSIGNED_INT,
+        }
+    }
+
+    /// Size of one index in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            IndexType::U8 => 1,
+            IndexType::U16 => 2,
+            IndexType::U32 => 4,
+        }
+    }
+}
+
+/// How a mesh's indices are assembled into primitives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrimitiveMode {
+    Points,
+    /// Every pair of indices is a separate segment.
+    Lines,
+    /// Each index after the first continues the line.
+    LineStrip,
+    /// Every three indices are a separate triangle.
+    Triangles,
+    /// Each index after the second adds a triangle with the two before.
+    TriangleStrip,
+    /// Each index after the second adds a triangle with the first and the
+    /// one before.
+    TriangleFan,
+}
+
+impl PrimitiveMode {
+    /// The `mode` argument for `draw_elements`.
+    pub fn gl_mode(self) -> u32 {
+        match self {
+            PrimitiveMode::Points => GL::POINTS,
+            PrimitiveMode::Lines => GL::LINES,
+            PrimitiveMode::LineStrip => GL::LINE_STRIP,
+            PrimitiveMode::Triangles => GL::TRIANGLES,
+            PrimitiveMode::TriangleStrip => GL::TRIANGLE_STRIP,
+            PrimitiveMode::TriangleFan => GL::TRIANGLE_FAN,
+        }
+    }
+
+    /// How many primitives `count` indices make. Indices left over at the
+    /// end of a list are ignored by GL, as here.
+    pub fn primitive_count(self, count: usize) -> usize {
+        match self {
+            PrimitiveMode::Points => count,
+            PrimitiveMode::Lines => count / 2,
+            PrimitiveMode::LineStrip => count.saturating_sub(1),
+            PrimitiveMode::Triangles => count / 3,
+            PrimitiveMode::TriangleStrip | PrimitiveMode::TriangleFan => count.saturating_sub(2),
+        }
+    }
+
+    /// Whether `count` indices form whole primitives with none left over.
+    pub fn is_valid_count(self, count: usize) -> bool {
+        match self {
+            PrimitiveMode::Points => true,
+            PrimitiveMode::Lines => count.is_multiple_of(2),
+            PrimitiveMode::LineStrip => count != 1,
+            PrimitiveMode::Triangles => count.is_multiple_of(3),
+            PrimitiveMode::TriangleStrip | PrimitiveMode::TriangleFan => count == 0 || count >= 3,
+        }
+    }
+}
+
+/// Whether the context can draw with 32-bit indices: always on WebGL2,
+/// through `OES_element_index_uint` on WebGL1. Asking for the extension
+/// also enables it.
+pub fn supports_u32_indices(context: &GL) -> bool {
+    context.dyn_ref::<WebGl2RenderingContext>().is_some()
+        || context
+            .get_extension("OES_element_index_uint")
+            .ok()
+            .flatten()
+            .is_some()
+}
+
+/// An uploaded element array buffer that remembers how many indices it
+/// holds, of which type and how they form primitives, so draw calls never
+/// have to be told.
+#[derive(Debug, Clone)]
+pub struct IndexBuffer {
+    buffer: BufferHandle,
+    count: i32,
+    index_type: IndexType,
+    primitive: PrimitiveMode,
+}
+
+impl IndexBuffer {
+    /// Upload `indices` in the smallest type that fits the largest one,
+    /// as a triangle list. Fails if 32-bit indices are needed but
+    /// unsupported.
+    pub fn new(resources: &ResourceManager, indices: &[u32]) -> Result<IndexBuffer, JsValue> {
+        IndexBuffer::with_primitive(resources, indices, PrimitiveMode::Triangles)
+    }
+
+    /// Like `new`, for indices forming `primitive`s. Fails if they do not
+    /// make whole primitives.
+    pub fn with_primitive(
+        resources: &ResourceManager,
+        indices: &[u32],
+        primitive: PrimitiveMode,
+    ) -> Result<IndexBuffer, JsValue> {
+        if !primitive.is_valid_count(indices.len()) {
+            return Err(format!(
+                "{} indices do not make whole {:?} primitives",
+                indices.len(),
+                primitive
+            )
+            .into());
+        }
+        let max_index = indices.iter().copied().max().unwrap_or(0);
+        let index_type = IndexType::for_max_index(max_index);
+        let target = GL::ELEMENT_ARRAY_BUFFER;
+        let buffer = match index_type {
+            IndexType::U8 => {
+                let narrowed: Vec<u8> = indices.iter().map(|&index| index as u8).collect();
+                resources.create_buffer(target, &narrowed, GL::STATIC_DRAW)?
+            }
+            IndexType::U16 => {
+                let narrowed: Vec<u16> = indices.iter().map(|&index| index as u16).collect();
+                resources.create_buffer(target, &narrowed, GL::STATIC_DRAW)?
+            }
+            IndexType::U32 => {
+                if !supports_u32_indices(resources.context()) {
+                    return Err(format!(
+                        "index {} needs 32-bit indices, but OES_element_index_uint is unavailable",
+                        max_index
+                    )
+                    .into());
+                }
+                resources.create_buffer(target, indices, GL::STATIC_DRAW)?
+            }
+        };
+        Ok(IndexBuffer {
+            buffer,
+            count: indices.len() as i32,
+            index_type,
+            primitive,
+        })
+    }
+
+    /// Upload 16-bit indices of a triangle list as they are.
+    pub fn from_u16(resources: &ResourceManager, indices: &[u16]) -> Result<IndexBuffer, JsValue> {
+        Ok(IndexBuffer {
+            buffer: resources.create_buffer(GL::ELEMENT_ARRAY_BUFFER, indices, GL::STATIC_DRAW)?,
+            count: indices.len() as i32,
+            index_type: IndexType::U16,
+            primitive: PrimitiveMode::Triangles,
+        })
+    }
+
+    pub fn buffer(&self) -> &BufferHandle {
+        &self.buffer
+    }
+
+    pub fn count(&self) -> i32 {
+        self.count
+    }
+
+    pub fn index_type(&self) -> IndexType {
+        self.index_type
+    }
+
+    pub fn primitive(&self) -> PrimitiveMode {
+        self.primitive
+    }
+
+    /// Draw the same indices as other primitives, e.g. a triangle strip's
+    /// indices as points. Fails if they do not make whole ones.
+    pub fn set_primitive(&mut self, primitive: PrimitiveMode) -> Result<(), JsValue> {
+        if !primitive.is_valid_count(self.count as usize) {
+            return Err(format!(
+                "{} indices do not make whole {:?} primitives",
+                self.count, primitive
+            )
+            .into());
+        }
+        self.primitive = primitive;
+        Ok(())
+    }
+
+    /// Bind the indices through `state`, so the cache knows about it.
+    pub fn bind(&self, state: &GlState) {
+        state.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, self.buffer.get().as_ref());
+    }
+
+    /// Bind and draw every index, only rebinding the buffer if `state` has
+    /// another one bound. Does nothing once the buffer has been deleted.
+    pub fn draw(&self, state: &GlState) {
+        if let Some(buffer) = self.buffer.get() {
+            state.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(&buffer));
+            self.draw_elements(state.context());
+        }
+    }
+
+    fn draw_elements(&self, context: &GL) {
+        let mode = self.primitive.gl_mode();
+        context.draw_elements_with_i32(mode, self.count, self.index_type.gl_type(), 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_type_fits_the_largest_index() {
+        assert_eq!(IndexType::for_max_index(254), IndexType::U8);
+        assert_eq!(IndexType::for_max_index(256), IndexType::U16);
+        assert_eq!(IndexType::for_max_index(65_534), IndexType::U16);
+        assert_eq!(IndexType::for_max_index(65_536), IndexType::U32);
+    }
+
+    #[test]
+    fn primitive_restart_values_move_up_a_type() {
+        assert_eq!(IndexType::for_max_index(255), IndexType::U16);
+        assert_eq!(IndexType::for_max_index(65_535), IndexType::U32);
+    }
+
+    #[test]
+    fn primitive_counts() {
+        assert_eq!(PrimitiveMode::Points.primitive_count(5), 5);
+        assert_eq!(PrimitiveMode::Lines.primitive_count(6), 3);
+        assert_eq!(PrimitiveMode::LineStrip.primitive_count(6), 5);
+        assert_eq!(PrimitiveMode::LineStrip.primitive_count(0), 0);
+        assert_eq!(PrimitiveMode::Triangles.primitive_count(6), 2);
+        assert_eq!(PrimitiveMode::TriangleStrip.primitive_count(6), 4);
+        assert_eq!(PrimitiveMode::TriangleFan.primitive_count(6), 4);
+        assert_eq!(PrimitiveMode::TriangleFan.primitive_count(1), 0);
+    }
+
+    #[test]
+    fn partial_primitives_are_rejected() {
+        assert!(PrimitiveMode::Lines.is_valid_count(4));
+        assert!(!PrimitiveMode::Lines.is_valid_count(5));
+        assert!(!PrimitiveMode::LineStrip.is_valid_count(1));
+        assert!(PrimitiveMode::Triangles.is_valid_count(0));
+        assert!(!PrimitiveMode::Triangles.is_valid_count(4));
+        assert!(PrimitiveMode::TriangleStrip.is_valid_count(4));
+        assert!(!PrimitiveMode::TriangleStrip.is_valid_count(2));
+    }
+}
@@ -0,0 +1,215 @@
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::shader::Shader;
+
+use super::{BufferHandle, GlState, IndexBuffer, Instancing, ResourceManager};
+
+/// Floats per instance: a 4×4 transform followed by an RGBA color.
+const INSTANCE_FLOATS: usize = 20;
+const INSTANCE_STRIDE: i32 = (INSTANCE_FLOATS * 4) as i32;
+/// Byte offset of the color within an instance, right after the transform.
+const COLOR_OFFSET: i32 = 16 * 4;
+
+/// Byte offset of `column` of the transform within an instance. Matrices
+/// are column-major, so each column is four consecutive floats.
+fn column_offset(column: u32) -> i32 {
+    (column * 4 * 4) as i32
+}
+
+/// Interleave `instances` into `data` in the layout `draw` points the
+/// attributes at.
+fn pack_instances(instances: &[Instance], data: &mut Vec<f32>) {
+    data.clear();
+    data.reserve(instances.len() * INSTANCE_FLOATS);
+    for instance in instances {
+        data.extend_from_slice(&instance.transform);
+        data.extend_from_slice(&instance.color);
+    }
+}
+
+/// Per-instance data for an `InstancedMesh`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instance {
+    pub transform: [f32; 16],
+    pub color: [f32; 4],
+}
+
+/// One mesh drawn many times with a single draw call.
+///
+/// Every instance's transform and color live interleaved in one attribute
+/// buffer, stepped once per instance. The program reads them through a
+/// `mat4 instance_matrix` and a `vec4 instance_color` attribute (see
+/// `builtin::INSTANCED_VERT`).
+#[derive(Debug)]
+pub struct InstancedMesh {
+    position_buffer: BufferHandle,
+    index_buffer: IndexBuffer,
+    instance_buffer: BufferHandle,
+    instance_data: Vec<f32>,
+}
+
+impl InstancedMesh {
+    /// Upload a mesh made of `positions` (xyz) and triangle `indices`.
+    pub fn new(
+        resources: &ResourceManager,
+        positions: &[f32],
+        indices: &[u32],
+    ) -> Result<InstancedMesh, JsValue> {
+        let position_buffer =
+            resources.create_buffer(GL::ARRAY_BUFFER, positions, GL::STATIC_DRAW)?;
+        let index_buffer = IndexBuffer::new(resources, indices)?;
+        InstancedMesh::from_buffers(resources, position_buffer, index_buffer)
+    }
+
+    /// Instance a mesh whose buffers already exist, such as the positions
+    /// and indices `upload_buffers` made for `Model::cube`.
+    pub fn from_buffers(
+        resources: &ResourceManager,
+        position_buffer: BufferHandle,
+        index_buffer: IndexBuffer,
+    ) -> Result<InstancedMesh, JsValue> {
+        let instance_buffer =
+            resources.create_buffer::<f32>(GL::ARRAY_BUFFER, &[], GL::DYNAMIC_DRAW)?;
+        Ok(InstancedMesh {
+            position_buffer,
+            index_buffer,
+            instance_buffer,
+            instance_data: Vec::new(),
+        })
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.instance_data.len() / INSTANCE_FLOATS
+    }
+
+    /// Replace every instance and upload them in one go.
+    pub fn set_instances(
+        &mut self,
+        resources: &ResourceManager,
+        instances: &[Instance],
+    ) -> Result<(), JsValue> {
+        pack_instances(instances, &mut self.instance_data);
+        resources.update_buffer(
+            &self.instance_buffer,
+            GL::ARRAY_BUFFER,
+            &self.instance_data,
+            GL::DYNAMIC_DRAW,
+        )
+    }
+
+    /// Draw every instance with `shader`, which must already be current.
+    /// Buffers are bound through `state`, so its cache stays in step.
+    pub fn draw(&self, state: &GlState, instancing: &Instancing, shader: &Shader) {
+        let context = state.context();
+        let instance_count = self.instance_count() as i32;
+        if instance_count == 0 {
+            return;
+        }
+
+        if let Some(position) = shader.attrib_location("position") {
+            state.bind_buffer(GL::ARRAY_BUFFER, self.position_buffer.get().as_ref());
+            context.vertex_attrib_pointer_with_i32(position, 3, GL::FLOAT, false, 0, 0);
+            context.enable_vertex_attrib_array(position);
+        }
+
+        // A mat4 attribute takes four consecutive locations, one per column.
+        let mut instanced = Vec::new();
+        state.bind_buffer(GL::ARRAY_BUFFER, self.instance_buffer.get().as_ref());
+        if let Some(matrix) = shader.attrib_location("instance_matrix") {
+            for column in 0..4 {
+                let location = matrix + column;
+                context.vertex_attrib_pointer_with_i32(
+                    location,
+                    4,
+                    GL::FLOAT,
+                    false,
+                    INSTANCE_STRIDE,
+                    column_offset(column),
+                );
+                context.enable_vertex_attrib_array(location);
+                instancing.vertex_attrib_divisor(location, 1);
+                instanced.push(location);
+            }
+        }
+        if let Some(color) = shader.attrib_location("instance_color") {
+            context.vertex_attrib_pointer_with_i32(
+                color,
+                4,
+                GL::FLOAT,
+                false,
+                INSTANCE_STRIDE,
+                COLOR_OFFSET,
+            );
+            context.enable_vertex_attrib_array(color);
+            instancing.vertex_attrib_divisor(color, 1);
+            instanced.push(color);
+        }
+
+        self.index_buffer.bind(state);
+        instancing.draw_elements_instanced(
+            self.index_buffer.primitive().gl_mode(),
+            self.index_buffer.count(),
+            self.index_buffer.index_type().gl_type(),
+            0,
+            instance_count,
+        );
+
+        // Divisors are global attribute state; put them back so later
+        // non-instanced draws step per vertex again.
+        for location in instanced {
+            instancing.vertex_attrib_divisor(location, 0);
+            context.disable_vertex_attrib_array(location);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(seed: f32) -> Instance {
+        let mut transform = [0.0; 16];
+        for (index, value) in transform.iter_mut().enumerate() {
+            *value = seed + index as f32;
+        }
+        Instance {
+            transform,
+            color: [seed, seed + 0.25, seed + 0.5, 1.0],
+        }
+    }
+
+    #[test]
+    fn instances_are_packed_transform_then_color() {
+        let instances = [instance(100.0), instance(200.0)];
+        let mut data = vec![7.0; 3];
+        pack_instances(&instances, &mut data);
+
+        assert_eq!(data.len(), 2 * INSTANCE_FLOATS);
+        for (index, instance) in instances.iter().enumerate() {
+            let packed = &data[index * INSTANCE_FLOATS..(index + 1) * INSTANCE_FLOATS];
+            assert_eq!(&packed[..16], &instance.transform[..]);
+            assert_eq!(&packed[16..], &instance.color[..]);
+        }
+    }
+
+    #[test]
+    fn attribute_offsets_match_the_packing() {
+        let mut data = Vec::new();
+        pack_instances(&[instance(0.0), instance(50.0)], &mut data);
+        let float_at = |byte_offset: i32| data[byte_offset as usize / 4];
+
+        assert_eq!(INSTANCE_STRIDE as usize, INSTANCE_FLOATS * 4);
+        // Each matrix column starts at element column * 4 of the transform.
+        for column in 0..4 {
+            assert_eq!(float_at(column_offset(column)), (column * 4) as f32);
+            assert_eq!(
+                float_at(INSTANCE_STRIDE + column_offset(column)),
+                50.0 + (column * 4) as f32
+            );
+        }
+        assert_eq!(float_at(COLOR_OFFSET), 0.0);
+        assert_eq!(float_at(COLOR_OFFSET + 4), 0.25);
+        assert_eq!(float_at(INSTANCE_STRIDE + COLOR_OFFSET + 12), 1.0);
+    }
+}
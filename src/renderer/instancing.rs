@@ -0,0 +1,55 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{AngleInstancedArrays, WebGl2RenderingContext, WebGlRenderingContext as GL};
+
+/// Access to instanced drawing: built into WebGL2, an extension on WebGL1.
+#[derive(Debug, Clone)]
+pub enum Instancing {
+    WebGl2(WebGl2RenderingContext),
+    Angle(AngleInstancedArrays),
+}
+
+impl Instancing {
+    /// Use WebGL2 if `context` is really a WebGL2 context, otherwise the
+    /// `ANGLE_instanced_arrays` extension.
+    pub fn new(context: &GL) -> Result<Instancing, JsValue> {
+        if let Some(context) = context.dyn_ref::<WebGl2RenderingContext>() {
+            return Ok(Instancing::WebGl2(context.clone()));
+        }
+        let extension = context
+            .get_extension("ANGLE_instanced_arrays")?
+            .ok_or("instanced drawing is not supported: no ANGLE_instanced_arrays")?;
+        Ok(Instancing::Angle(extension.unchecked_into::<AngleInstancedArrays>()))
+    }
+
+    /// Advance attribute `index` once every `divisor` instances instead of
+    /// once per vertex; 0 restores per-vertex stepping.
+    pub fn vertex_attrib_divisor(&self, index: u32, divisor: u32) {
+        match self {
+            Instancing::WebGl2(context) => context.vertex_attrib_divisor(index, divisor),
+            Instancing::Angle(extension) => extension.vertex_attrib_divisor_angle(index, divisor),
+        }
+    }
+
+    pub fn draw_elements_instanced(
+        &self,
+        mode: u32,
+        count: i32,
+        type_: u32,
+        offset: i32,
+        instance_count: i32,
+    ) {
+        match self {
+            Instancing::WebGl2(context) => {
+                context.draw_elements_instanced_with_i32(mode, count, type_, offset, instance_count)
+            }
+            Instancing::Angle(extension) => extension.draw_elements_instanced_angle_with_i32(
+                mode,
+                count,
+                type_,
+                offset,
+                instance_count,
+            ),
+        }
+    }
+}
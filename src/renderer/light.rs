@@ -0,0 +1,68 @@
+use crate::math::{self, Mat4, Vec3};
+
+/// A light that can cast shadows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    /// Parallel rays along `direction`. Shadows are computed for a cube of
+    /// half-size `extent` around `target`.
+    Directional {
+        direction: Vec3,
+        target: Vec3,
+        extent: f32,
+    },
+    /// A cone from `position` along `direction`, `angle` radians wide,
+    /// reaching `range` units.
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        angle: f32,
+        range: f32,
+    },
+}
+
+impl Light {
+    pub fn direction(&self) -> Vec3 {
+        match self {
+            Light::Directional { direction, .. } | Light::Spot { direction, .. } => {
+                math::normalize(direction)
+            }
+        }
+    }
+
+    /// Projection × view of the light, mapping world space to the light's
+    /// clip space.
+    pub fn view_projection(&self) -> Mat4 {
+        let mut projection = mat4::new_zero();
+        let view = match *self {
+            Light::Directional {
+                direction,
+                target,
+                extent,
+            } => {
+                let back = math::scale(&math::normalize(&direction), 2.0 * extent);
+                let eye = math::sub(&target, &back);
+                mat4::orthographic(
+                    &mut projection,
+                    &extent,
+                    &extent,
+                    &-extent,
+                    &-extent,
+                    &0.0,
+                    &(4.0 * extent),
+                );
+                math::look_at(&eye, &target, &math::up_for(&direction))
+            }
+            Light::Spot {
+                position,
+                direction,
+                angle,
+                range,
+            } => {
+                let target = math::add(&position, &math::normalize(&direction));
+                mat4::perspective(&mut projection, &angle, &1.0, &(range * 0.01), &range);
+                math::look_at(&position, &target, &math::up_for(&direction))
+            }
+        };
+        math::mul(&projection, &view)
+    }
+}
@@ -0,0 +1,100 @@
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::mesh::{Polylines, LINE_VERTEX_FLOATS};
+use crate::shader::Shader;
+
+use super::{BufferHandle, GlState, IndexBuffer, ResourceManager};
+
+const LINE_VERTEX_STRIDE: i32 = (LINE_VERTEX_FLOATS * 4) as i32;
+
+/// Attributes of `builtin::LINE_VERT` with their component count and
+/// offset in floats, following the layout of `Polylines`.
+const LINE_ATTRIBUTES: [(&str, i32, i32); 7] = [
+    ("previous", 3, 0),
+    ("start", 3, 3),
+    ("end", 3, 6),
+    ("next", 3, 9),
+    ("corner", 2, 12),
+    ("ends", 2, 14),
+    ("color", 4, 16),
+];
+
+/// Uploaded `Polylines`, drawn as screen-space thick lines with the
+/// "lines" material (see `Material::lines`).
+#[derive(Debug)]
+pub struct LineMesh {
+    vertex_buffer: BufferHandle,
+    index_buffer: IndexBuffer,
+}
+
+impl LineMesh {
+    pub fn new(resources: &ResourceManager, lines: &Polylines) -> Result<LineMesh, JsValue> {
+        Ok(LineMesh {
+            vertex_buffer: resources.create_buffer(
+                GL::ARRAY_BUFFER,
+                lines.vertices(),
+                GL::DYNAMIC_DRAW,
+            )?,
+            index_buffer: IndexBuffer::new(resources, lines.indices())?,
+        })
+    }
+
+    /// Replace the lines, reusing the vertex buffer.
+    pub fn set_lines(
+        &mut self,
+        resources: &ResourceManager,
+        lines: &Polylines,
+    ) -> Result<(), JsValue> {
+        resources.update_buffer(
+            &self.vertex_buffer,
+            GL::ARRAY_BUFFER,
+            lines.vertices(),
+            GL::DYNAMIC_DRAW,
+        )?;
+        // The index type may change with the vertex count.
+        self.index_buffer = IndexBuffer::new(resources, lines.indices())?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index_buffer.count() == 0
+    }
+
+    /// Draw every line with `shader`, which must already be current with
+    /// its matrices and `line_width` set. `viewport_size` is the size in
+    /// pixels of the target being drawn into.
+    pub fn draw(&self, state: &GlState, shader: &Shader, viewport_size: [f32; 2]) {
+        let buffer = match self.vertex_buffer.get() {
+            Some(buffer) if !self.is_empty() => buffer,
+            _ => return,
+        };
+        let context = state.context();
+        let viewport_location = shader.uniform_location("viewport_size");
+        context.uniform2fv_with_f32_array(viewport_location, &viewport_size);
+
+        state.bind_buffer(GL::ARRAY_BUFFER, Some(&buffer));
+        let mut enabled = Vec::with_capacity(LINE_ATTRIBUTES.len());
+        for &(name, components, offset) in LINE_ATTRIBUTES.iter() {
+            if let Some(location) = shader.attrib_location(name) {
+                context.vertex_attrib_pointer_with_i32(
+                    location,
+                    components,
+                    GL::FLOAT,
+                    false,
+                    LINE_VERTEX_STRIDE,
+                    offset * 4,
+                );
+                context.enable_vertex_attrib_array(location);
+                enabled.push(location);
+            }
+        }
+        self.index_buffer.draw(state);
+
+        // More arrays are enabled than other programs read; leave only the
+        // usual ones behind.
+        for location in enabled {
+            context.disable_vertex_attrib_array(location);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+mod dynamic_buffer;
+mod gl_state;
+mod index_buffer;
+mod instanced_mesh;
+mod instancing;
+mod light;
+mod line_mesh;
+mod picker;
+mod point_cloud_mesh;
+mod render_queue;
+mod render_target;
+mod renderer_trait;
+mod resources;
+mod shadow_map;
+pub mod upload;
+
+pub use self::dynamic_buffer::{BufferUsage, DynamicBuffer};
+pub use self::gl_state::{GlState, StateCache, StateCounters};
+pub use self::index_buffer::{supports_u32_indices, IndexBuffer, IndexType, PrimitiveMode};
+pub use self::instanced_mesh::{Instance, InstancedMesh};
+pub use self::instancing::Instancing;
+pub use self::light::Light;
+pub use self::line_mesh::LineMesh;
+pub use self::picker::{color_to_id, id_to_color, Picker, MAX_NODE_ID};
+pub use self::point_cloud_mesh::{PointCloudMesh, PointCloudStats, DEFAULT_POINT_BUDGET};
+pub use self::render_queue::{
+    view_depth, DrawItem, DrawKey, QueueStats, RenderQueue, StateChanges,
+};
+pub use self::render_target::{ColorFormat, RenderTarget};
+pub use self::renderer_trait::Renderer;
+pub use self::resources::{
+    Backend, BufferHandle, MemoryStats, ProgramHandle, RenderbufferHandle, ResourceManager,
+    TextureFormat, TextureHandle,
+};
+pub use self::shadow_map::{ShadowMap, ShadowSettings, SHADOW_TEXTURE_UNIT};
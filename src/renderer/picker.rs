@@ -0,0 +1,168 @@
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::material::{Material, ProgramCache};
+use crate::shader::builtin;
+
+use super::{RenderTarget, ResourceManager};
+
+/// The largest node ID a `Picker` can tell apart: the ID is spread over
+/// the 24 bits of RGB, and 0 is kept for "nothing".
+pub const MAX_NODE_ID: u32 = 0xff_ffff;
+
+/// The color node `id` is drawn with in the picking pass, or `None` if
+/// the ID is 0 or above `MAX_NODE_ID` and cannot be told apart.
+pub fn id_to_color(id: u32) -> Option<[f32; 4]> {
+    if id == 0 || id > MAX_NODE_ID {
+        return None;
+    }
+    Some([
+        ((id >> 16) & 0xff) as f32 / 255.0,
+        ((id >> 8) & 0xff) as f32 / 255.0,
+        (id & 0xff) as f32 / 255.0,
+        1.0,
+    ])
+}
+
+/// The node ID behind a pixel read back from the picking pass, or `None`
+/// for the background.
+pub fn color_to_id(pixel: [u8; 4]) -> Option<u32> {
+    let id = (pixel[0] as u32) << 16 | (pixel[1] as u32) << 8 | pixel[2] as u32;
+    if pixel[3] == 0 || id == 0 {
+        None
+    } else {
+        Some(id)
+    }
+}
+
+/// Finds which node is under a pixel by drawing every node in a flat color
+/// encoding its ID into an offscreen target and reading that pixel back.
+///
+/// A pick is `begin`, then for each node `draw_node` followed by its draw
+/// call, then `read`.
+#[derive(Debug)]
+pub struct Picker {
+    target: RenderTarget,
+    material: Material,
+}
+
+impl Picker {
+    pub fn new(resources: &ResourceManager, cache: &mut ProgramCache) -> Result<Picker, JsValue> {
+        Ok(Picker {
+            target: RenderTarget::for_canvas(resources)?,
+            material: Material::named(
+                resources.context(),
+                cache,
+                "picking",
+                builtin::PICKING_VERT,
+                builtin::PICKING_FRAG,
+            )?,
+        })
+    }
+
+    /// Recreate the ID target after the context and the
+    /// `ResourceManager` were restored.
+    pub fn restore(&mut self) -> Result<(), JsValue> {
+        self.target.restore()
+    }
+
+    /// Match the ID target to the canvas, clear it to "nothing" and make
+    /// the picking program current. The caller sets `projection_matrix`
+    /// and `model_view_matrix` and binds `position` on the returned
+    /// material's shader.
+    pub fn begin(&mut self, context: &GL) -> Result<&Material, JsValue> {
+        self.target.resize_to_canvas()?;
+        self.target.bind(context);
+        context.clear_color(0.0, 0.0, 0.0, 0.0);
+        context.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT);
+        context.enable(GL::DEPTH_TEST);
+        // Blending or dithering would change the ID colors.
+        context.disable(GL::BLEND);
+        context.disable(GL::DITHER);
+        self.material.apply(context);
+        Ok(&self.material)
+    }
+
+    /// Set the color for node `id`; the next draw call is tagged with it.
+    /// IDs must be in `1..=MAX_NODE_ID`.
+    pub fn draw_node(&self, context: &GL, id: u32) -> Result<(), JsValue> {
+        let [r, g, b, a] = id_to_color(id)
+            .ok_or_else(|| format!("node ID {} out of range 1..={}", id, MAX_NODE_ID))?;
+        context.uniform4f(
+            self.material.shader().uniform_location("pick_color"),
+            r,
+            g,
+            b,
+            a,
+        );
+        Ok(())
+    }
+
+    /// Read the node at (`x`, `y`) in drawing-buffer pixels from the top
+    /// left, and go back to drawing on the canvas.
+    pub fn read(&self, context: &GL, x: i32, y: i32) -> Result<Option<u32>, JsValue> {
+        context.enable(GL::DITHER);
+        if x < 0 || y < 0 || x >= self.target.width() || y >= self.target.height() {
+            RenderTarget::unbind(context);
+            return Ok(None);
+        }
+        let mut pixel = [0u8; 4];
+        // GL counts rows from the bottom.
+        let result = context.read_pixels_with_opt_u8_array(
+            x,
+            self.target.height() - 1 - y,
+            1,
+            1,
+            GL::RGBA,
+            GL::UNSIGNED_BYTE,
+            Some(&mut pixel),
+        );
+        RenderTarget::unbind(context);
+        result?;
+        Ok(color_to_id(pixel))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The pixel `read` gets back for a color, as the framebuffer stores it.
+    fn stored(color: [f32; 4]) -> [u8; 4] {
+        let mut pixel = [0u8; 4];
+        for (byte, channel) in pixel.iter_mut().zip(color.iter()) {
+            *byte = (channel * 255.0).round() as u8;
+        }
+        pixel
+    }
+
+    #[test]
+    fn ids_survive_the_round_trip() {
+        for &id in &[1, 2, 0xff, 0x100, 0x1234, 0xab_cdef, MAX_NODE_ID] {
+            let color = id_to_color(id).unwrap();
+            assert_eq!(color_to_id(stored(color)), Some(id), "id {:#x}", id);
+        }
+    }
+
+    #[test]
+    fn zero_is_the_background() {
+        assert_eq!(id_to_color(0), None);
+        assert_eq!(color_to_id([0, 0, 0, 255]), None);
+        // The cleared target is transparent black.
+        assert_eq!(color_to_id([0, 0, 0, 0]), None);
+        // Anything transparent is background, whatever its color.
+        assert_eq!(color_to_id([1, 2, 3, 0]), None);
+    }
+
+    #[test]
+    fn the_largest_id_is_white() {
+        assert_eq!(id_to_color(0xff_ffff), Some([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(color_to_id([255, 255, 255, 255]), Some(0xff_ffff));
+    }
+
+    #[test]
+    fn ids_past_24_bits_are_rejected() {
+        assert_eq!(id_to_color(MAX_NODE_ID + 1), None);
+        assert_eq!(id_to_color(u32::MAX), None);
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::{HashMap, HashSet};
+
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::math::Vec3;
+use crate::mesh::{Frustum, PointOctree};
+use crate::shader::Shader;
+
+use super::{BufferHandle, GlState, ResourceManager};
+
+/// Points a `PointCloudMesh` draws at most unless told otherwise.
+pub const DEFAULT_POINT_BUDGET: usize = 2_000_000;
+
+/// What a `PointCloudMesh` drew and streamed in its last update.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PointCloudStats {
+    /// Nodes selected for drawing, uploaded or not.
+    pub selected_nodes: u32,
+    pub selected_points: u32,
+    /// Nodes with buffers on the GPU.
+    pub resident_nodes: u32,
+    pub resident_points: u32,
+    /// Points sent to the GPU in the update.
+    pub uploaded_points: u32,
+}
+
+/// One octree node's points on the GPU.
+#[derive(Debug)]
+struct NodeBuffers {
+    positions: BufferHandle,
+    /// RGBA bytes, if the cloud has colors.
+    colors: Option<BufferHandle>,
+    count: i32,
+}
+
+/// A point cloud drawn level of detail by level of detail.
+///
+/// Every `update` picks the octree nodes worth drawing from the camera
+/// within the point budget, frees the buffers of nodes no longer picked
+/// and uploads the missing ones, nearest and coarsest first, a limited
+/// number of points per update so a large cloud streams in over several
+/// frames instead of stalling one. Draw with the "points" material (see
+/// `Material::points`).
+#[derive(Debug)]
+pub struct PointCloudMesh {
+    octree: PointOctree,
+    resident: HashMap<usize, NodeBuffers>,
+    selected: Vec<usize>,
+    point_budget: usize,
+    upload_budget: usize,
+    stats: PointCloudStats,
+}
+
+impl PointCloudMesh {
+    /// Nothing is uploaded until the first `update`.
+    pub fn new(octree: PointOctree) -> PointCloudMesh {
+        PointCloudMesh {
+            octree,
+            resident: HashMap::new(),
+            selected: Vec::new(),
+            point_budget: DEFAULT_POINT_BUDGET,
+            upload_budget: 250_000,
+            stats: PointCloudStats::default(),
+        }
+    }
+
+    pub fn octree(&self) -> &PointOctree {
+        &self.octree
+    }
+
+    pub fn point_budget(&self) -> usize {
+        self.point_budget
+    }
+
+    /// Most points drawn, and held on the GPU, at once.
+    pub fn set_point_budget(&mut self, budget: usize) {
+        self.point_budget = budget;
+    }
+
+    /// Most points uploaded per update. At least one node is uploaded
+    /// whenever one is missing, however large.
+    pub fn set_upload_budget(&mut self, budget: usize) {
+        self.upload_budget = budget;
+    }
+
+    pub fn stats(&self) -> PointCloudStats {
+        self.stats
+    }
+
+    /// Pick the nodes to draw from `eye` inside `frustum`, both in the
+    /// cloud's space, and stream them to the GPU.
+    pub fn update(
+        &mut self,
+        resources: &ResourceManager,
+        frustum: &Frustum,
+        eye: &Vec3,
+    ) -> Result<(), JsValue> {
+        let selection = self.octree.select(frustum, eye, self.point_budget);
+        let selected: HashSet<_> = selection.nodes.iter().copied().collect();
+        // Dropping the handles frees the buffers.
+        self.resident.retain(|id, _| selected.contains(id));
+
+        let cloud = self.octree.cloud();
+        let mut uploaded = 0;
+        for &id in &selection.nodes {
+            if self.resident.contains_key(&id) {
+                continue;
+            }
+            let points = self.octree.node(id).points.clone();
+            if uploaded > 0 && uploaded + points.len() > self.upload_budget {
+                break;
+            }
+            let positions = &cloud.positions()[points.start * 3..points.end * 3];
+            let colors = if cloud.has_colors() {
+                let colors = &cloud.colors()[points.start * 4..points.end * 4];
+                Some(resources.create_buffer(GL::ARRAY_BUFFER, colors, GL::STATIC_DRAW)?)
+            } else {
+                None
+            };
+            let buffers = NodeBuffers {
+                positions: resources.create_buffer(GL::ARRAY_BUFFER, positions, GL::STATIC_DRAW)?,
+                colors,
+                count: points.len() as i32,
+            };
+            self.resident.insert(id, buffers);
+            uploaded += points.len();
+        }
+
+        self.stats = PointCloudStats {
+            selected_nodes: selection.nodes.len() as u32,
+            selected_points: selection.points as u32,
+            resident_nodes: self.resident.len() as u32,
+            resident_points: self.resident.values().map(|node| node.count as u32).sum(),
+            uploaded_points: uploaded as u32,
+        };
+        self.selected = selection.nodes;
+        Ok(())
+    }
+
+    /// Draw the selected nodes that have been uploaded with `shader`,
+    /// which must already be current with its matrices set.
+    /// `viewport_size` is the size in pixels of the target being drawn
+    /// into. Clouds without colors are drawn white.
+    pub fn draw(&self, state: &GlState, shader: &Shader, viewport_size: [f32; 2]) {
+        let context = state.context();
+        let viewport_location = shader.uniform_location("viewport_size");
+        context.uniform2fv_with_f32_array(viewport_location, &viewport_size);
+        let position = shader.attrib_location("position");
+        let color = shader.attrib_location("color");
+
+        for id in &self.selected {
+            let node = match self.resident.get(id) {
+                Some(node) => node,
+                None => continue,
+            };
+            if let Some(location) = position {
+                state.bind_buffer(GL::ARRAY_BUFFER, node.positions.get().as_ref());
+                context.vertex_attrib_pointer_with_i32(location, 3, GL::FLOAT, false, 0, 0);
+                context.enable_vertex_attrib_array(location);
+            }
+            if let Some(location) = color {
+                match &node.colors {
+                    Some(colors) => {
+                        state.bind_buffer(GL::ARRAY_BUFFER, colors.get().as_ref());
+                        context.vertex_attrib_pointer_with_i32(
+                            location,
+                            4,
+                            GL::UNSIGNED_BYTE,
+                            true,
+                            0,
+                            0,
+                        );
+                        context.enable_vertex_attrib_array(location);
+                    }
+                    None => {
+                        context.disable_vertex_attrib_array(location);
+                        context.vertex_attrib4f(location, 1.0, 1.0, 1.0, 1.0);
+                    }
+                }
+            }
+            context.draw_arrays(GL::POINTS, 0, node.count);
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use std::cmp::Ordering;
+
+use wasm_bindgen::prelude::*;
+
+use crate::material::{Material, RenderState};
+use crate::math::{self, Mat4, Vec3};
+
+use super::{BufferHandle, GlState};
+
+/// The GL state a draw needs, ordered from the most to the least
+/// expensive to switch so sorting by it groups the costly changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DrawKey {
+    pub program: usize,
+    pub material: usize,
+    pub buffer: u32,
+}
+
+impl DrawKey {
+    /// The key for drawing `buffer` with `material`. The material is told
+    /// apart from others by address, so it must outlive the queue.
+    pub fn new(material: &Material, buffer: &BufferHandle) -> DrawKey {
+        DrawKey {
+            program: material.program_id(),
+            material: material as *const Material as usize,
+            buffer: buffer.id(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrawItem<T> {
+    pub key: DrawKey,
+    /// Distance in front of the camera, used to order transparent items.
+    pub depth: f32,
+    pub transparent: bool,
+    /// Whatever the caller needs to issue the draw.
+    pub payload: T,
+}
+
+/// What differs from the previous draw, so only that state is set again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StateChanges {
+    pub program: bool,
+    pub material: bool,
+    pub buffer: bool,
+    /// Set on the first transparent item, where blending goes on.
+    pub blending: bool,
+}
+
+/// How many state changes a frame needed against how many draws it made.
+/// Every draw past the first that did not need a change saved a call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    pub draws: u32,
+    pub program_changes: u32,
+    pub material_changes: u32,
+    pub buffer_changes: u32,
+    pub blending_changes: u32,
+}
+
+/// Draws collected over a frame and issued in an order that keeps GL
+/// state changes down.
+///
+/// Opaque items are sorted by program, then material, then buffer, with
+/// nearer items first among equals to make the most of the depth test.
+/// Transparent items follow, farthest first so they blend over whatever
+/// is behind them, with blending on and depth writes off.
+#[derive(Debug, Clone)]
+pub struct RenderQueue<T> {
+    opaque: Vec<DrawItem<T>>,
+    transparent: Vec<DrawItem<T>>,
+}
+
+impl<T> Default for RenderQueue<T> {
+    fn default() -> RenderQueue<T> {
+        RenderQueue::new()
+    }
+}
+
+impl<T> RenderQueue<T> {
+    pub fn new() -> RenderQueue<T> {
+        RenderQueue {
+            opaque: Vec::new(),
+            transparent: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, item: DrawItem<T>) {
+        if item.transparent {
+            self.transparent.push(item);
+        } else {
+            self.opaque.push(item);
+        }
+    }
+
+    pub fn push_opaque(&mut self, key: DrawKey, depth: f32, payload: T) {
+        self.push(DrawItem {
+            key,
+            depth,
+            transparent: false,
+            payload,
+        });
+    }
+
+    pub fn push_transparent(&mut self, key: DrawKey, depth: f32, payload: T) {
+        self.push(DrawItem {
+            key,
+            depth,
+            transparent: true,
+            payload,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.opaque.len() + self.transparent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every item, keeping the allocations for the next frame.
+    pub fn clear(&mut self) {
+        self.opaque.clear();
+        self.transparent.clear();
+    }
+
+    /// Put the items in drawing order.
+    pub fn sort(&mut self) {
+        self.opaque
+            .sort_by(|a, b| a.key.cmp(&b.key).then_with(|| compare_depth(a.depth, b.depth)));
+        // Stable, so equally distant items keep the order they came in.
+        self.transparent
+            .sort_by(|a, b| compare_depth(b.depth, a.depth));
+    }
+
+    /// Sort, then hand every item to `draw` in order along with the state
+    /// it has to change, and clear the queue. Blending is assumed off at
+    /// the start and is switched on once, for the first transparent item;
+    /// the caller switches it back off.
+    pub fn execute<E>(
+        &mut self,
+        mut draw: impl FnMut(&DrawItem<T>, StateChanges) -> Result<(), E>,
+    ) -> Result<QueueStats, E> {
+        self.sort();
+        let mut stats = QueueStats::default();
+        let mut previous: Option<&DrawItem<T>> = None;
+        let result = self
+            .opaque
+            .iter()
+            .chain(self.transparent.iter())
+            .try_for_each(|item| {
+                let changed = |field: fn(&DrawKey) -> usize| {
+                    previous.is_none_or(|previous| field(&previous.key) != field(&item.key))
+                };
+                let changes = StateChanges {
+                    program: changed(|key| key.program),
+                    material: changed(|key| key.material),
+                    buffer: changed(|key| key.buffer as usize),
+                    blending: item.transparent
+                        && previous.is_none_or(|previous| !previous.transparent),
+                };
+                stats.record(&changes);
+                previous = Some(item);
+                draw(item, changes)
+            });
+        self.clear();
+        result.map(|()| stats)
+    }
+
+    /// `execute` with blending switched on for the transparent items, in
+    /// case their materials do not say how. `draw` deals with program,
+    /// material and buffer changes, and anything the materials' render
+    /// states change is reset afterwards.
+    pub fn render(
+        &mut self,
+        state: &GlState,
+        mut draw: impl FnMut(&DrawItem<T>, StateChanges) -> Result<(), JsValue>,
+    ) -> Result<QueueStats, JsValue> {
+        let stats = self.execute(|item, changes| {
+            if changes.blending {
+                RenderState::transparent().apply(state);
+            }
+            draw(item, changes)
+        });
+        RenderState::reset(state);
+        stats
+    }
+}
+
+impl QueueStats {
+    fn record(&mut self, changes: &StateChanges) {
+        self.draws += 1;
+        self.program_changes += changes.program as u32;
+        self.material_changes += changes.material as u32;
+        self.buffer_changes += changes.buffer as u32;
+        self.blending_changes += changes.blending as u32;
+    }
+}
+
+/// How far in front of the camera `center`, in model space, sits under
+/// `model_view`. Larger is farther.
+pub fn view_depth(model_view: &Mat4, center: &Vec3) -> f32 {
+    -math::transform_point(model_view, center)[2]
+}
+
+/// IEEE 754 total order, so the sort stays consistent when a depth is
+/// NaN: NaN sorts beyond infinity, as the farthest item, instead of
+/// comparing equal to every depth.
+fn compare_depth(a: f32, b: f32) -> Ordering {
+    a.total_cmp(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(program: usize, material: usize, buffer: u32) -> DrawKey {
+        DrawKey {
+            program,
+            material,
+            buffer,
+        }
+    }
+
+    fn drawn_order(queue: &mut RenderQueue<&'static str>) -> Vec<(&'static str, StateChanges)> {
+        let mut order = Vec::new();
+        queue
+            .execute(|item, changes| {
+                order.push((item.payload, changes));
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+        order
+    }
+
+    #[test]
+    fn opaque_items_are_grouped_by_state() {
+        let mut queue = RenderQueue::new();
+        queue.push_opaque(key(2, 1, 1), 5.0, "b");
+        queue.push_opaque(key(1, 2, 1), 5.0, "c");
+        queue.push_opaque(key(2, 1, 2), 5.0, "d");
+        queue.push_opaque(key(1, 1, 1), 5.0, "a");
+        let names: Vec<_> = drawn_order(&mut queue).into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, ["a", "c", "b", "d"]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn equal_opaque_items_go_front_to_back() {
+        let mut queue = RenderQueue::new();
+        queue.push_opaque(key(1, 1, 1), 9.0, "far");
+        queue.push_opaque(key(1, 1, 1), 1.0, "near");
+        let names: Vec<_> = drawn_order(&mut queue).into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, ["near", "far"]);
+    }
+
+    #[test]
+    fn transparent_items_go_back_to_front_after_opaque() {
+        let mut queue = RenderQueue::new();
+        queue.push_transparent(key(1, 1, 1), 2.0, "near glass");
+        queue.push_opaque(key(5, 5, 5), 50.0, "wall");
+        queue.push_transparent(key(1, 1, 1), 8.0, "far glass");
+        queue.push_transparent(key(0, 0, 0), 4.0, "middle glass");
+        let order = drawn_order(&mut queue);
+        let names: Vec<_> = order.iter().map(|(n, _)| *n).collect();
+        assert_eq!(names, ["wall", "far glass", "middle glass", "near glass"]);
+        let blending: Vec<_> = order.iter().map(|(_, changes)| changes.blending).collect();
+        assert_eq!(blending, [false, true, false, false]);
+    }
+
+    #[test]
+    fn nan_depths_sort_as_the_farthest() {
+        let mut queue = RenderQueue::new();
+        queue.push_opaque(key(1, 1, 1), 3.0, "middle");
+        queue.push_opaque(key(1, 1, 1), f32::NAN, "nan");
+        queue.push_opaque(key(1, 1, 1), 1.0, "near");
+        queue.push_opaque(key(1, 1, 1), 9.0, "far");
+        queue.push_transparent(key(1, 1, 1), 2.0, "near glass");
+        queue.push_transparent(key(1, 1, 1), f32::NAN, "nan glass");
+        queue.push_transparent(key(1, 1, 1), 8.0, "far glass");
+        let names: Vec<_> = drawn_order(&mut queue).into_iter().map(|(n, _)| n).collect();
+        assert_eq!(
+            names,
+            ["near", "middle", "far", "nan", "nan glass", "far glass", "near glass"]
+        );
+    }
+
+    #[test]
+    fn unchanged_state_is_not_reported() {
+        let mut queue = RenderQueue::new();
+        queue.push_opaque(key(1, 1, 1), 0.0, "first");
+        queue.push_opaque(key(1, 1, 2), 1.0, "same material");
+        queue.push_opaque(key(1, 2, 2), 2.0, "same program");
+        let order = drawn_order(&mut queue);
+        assert_eq!(
+            order[0].1,
+            StateChanges {
+                program: true,
+                material: true,
+                buffer: true,
+                blending: false,
+            }
+        );
+        let changed = |changes: &StateChanges| (changes.program, changes.material, changes.buffer);
+        assert_eq!(changed(&order[1].1), (false, false, true));
+        assert_eq!(changed(&order[2].1), (false, true, false));
+    }
+
+    #[test]
+    fn stats_count_state_changes() {
+        let mut queue = RenderQueue::new();
+        for buffer in 0..4 {
+            queue.push_opaque(key(1, 1, buffer), 0.0, ());
+            queue.push_opaque(key(2, 2, buffer), 0.0, ());
+        }
+        queue.push_transparent(key(1, 1, 0), 1.0, ());
+        let stats = queue.execute(|_, _| Ok::<(), ()>(())).unwrap();
+        assert_eq!(
+            stats,
+            QueueStats {
+                draws: 9,
+                program_changes: 3,
+                material_changes: 3,
+                buffer_changes: 9,
+                blending_changes: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn errors_stop_the_walk_and_clear_the_queue() {
+        let mut queue = RenderQueue::new();
+        queue.push_opaque(key(1, 1, 1), 0.0, 1);
+        queue.push_opaque(key(2, 1, 1), 0.0, 2);
+        let mut drawn = Vec::new();
+        let result = queue.execute(|item, _| {
+            drawn.push(item.payload);
+            Err("lost")
+        });
+        assert_eq!(result, Err("lost"));
+        assert_eq!(drawn, [1]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn view_depth_grows_away_from_the_camera() {
+        let mut model_view = mat4::new_identity();
+        let identity = model_view;
+        mat4::translate(&mut model_view, &identity, &[0.0, 0.0, -6.0]);
+        assert_eq!(view_depth(&model_view, &[0.0, 0.0, 0.0]), 6.0);
+        assert_eq!(view_depth(&model_view, &[0.0, 0.0, -1.0]), 7.0);
+    }
+}
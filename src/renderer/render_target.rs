@@ -0,0 +1,246 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{WebGlFramebuffer, WebGlRenderingContext as GL};
+
+use super::{RenderbufferHandle, ResourceManager, TextureFormat, TextureHandle};
+
+/// Storage of a render target's color texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorFormat {
+    /// 8 bits per channel, clamped to [0, 1].
+    Rgba8,
+    /// 16-bit floats per channel, so values above 1 survive until tone
+    /// mapping. Needs `OES_texture_half_float` and
+    /// `EXT_color_buffer_half_float`.
+    HalfFloat,
+}
+
+impl ColorFormat {
+    /// `HalfFloat` if the context can render to half-float textures,
+    /// otherwise `Rgba8`. Enables the extensions it finds.
+    pub fn hdr(context: &GL) -> Result<ColorFormat, JsValue> {
+        let textures = context.get_extension("OES_texture_half_float")?.is_some();
+        let rendering = context
+            .get_extension("EXT_color_buffer_half_float")?
+            .is_some();
+        Ok(if textures && rendering {
+            ColorFormat::HalfFloat
+        } else {
+            ColorFormat::Rgba8
+        })
+    }
+
+    fn texture_format(self) -> TextureFormat {
+        match self {
+            ColorFormat::Rgba8 => TextureFormat::Rgba8,
+            ColorFormat::HalfFloat => TextureFormat::RgbaHalfFloat,
+        }
+    }
+}
+
+/// An offscreen framebuffer with an RGBA color texture and a 16-bit depth
+/// renderbuffer. Whatever is drawn while it is bound ends up in
+/// `color_texture`, ready to be sampled by a later pass.
+///
+/// The attachments are owned by a `ResourceManager`, which counts them and
+/// restores them after a context loss; the framebuffer is deleted when the
+/// target is dropped.
+#[derive(Debug)]
+pub struct RenderTarget {
+    resources: ResourceManager,
+    framebuffer: WebGlFramebuffer,
+    color: TextureHandle,
+    depth: RenderbufferHandle,
+    format: ColorFormat,
+    width: i32,
+    height: i32,
+}
+
+impl RenderTarget {
+    pub fn new(
+        resources: &ResourceManager,
+        width: i32,
+        height: i32,
+    ) -> Result<RenderTarget, JsValue> {
+        RenderTarget::with_format(resources, width, height, ColorFormat::Rgba8)
+    }
+
+    /// Like `new`, with the color texture stored as `format`. Half floats
+    /// are filtered linearly only with `OES_texture_half_float_linear`,
+    /// and sampled nearest otherwise.
+    pub fn with_format(
+        resources: &ResourceManager,
+        width: i32,
+        height: i32,
+        format: ColorFormat,
+    ) -> Result<RenderTarget, JsValue> {
+        let context = resources.context();
+        // A zero sized attachment makes the framebuffer incomplete.
+        let width = width.max(1);
+        let height = height.max(1);
+
+        // Non power-of-two sizes are fine as long as we neither wrap nor
+        // mipmap; the texture is clamped, and filtered linearly where the
+        // format allows.
+        let filter = match format {
+            ColorFormat::Rgba8 => GL::LINEAR,
+            ColorFormat::HalfFloat => {
+                if context
+                    .get_extension("OES_texture_half_float_linear")?
+                    .is_some()
+                {
+                    GL::LINEAR
+                } else {
+                    GL::NEAREST
+                }
+            }
+        };
+        let color =
+            resources.create_texture_as(width, height, format.texture_format(), filter, None)?;
+        context.bind_texture(GL::TEXTURE_2D, None);
+        let depth = resources.create_renderbuffer(GL::DEPTH_COMPONENT16, width, height)?;
+        let framebuffer = context
+            .create_framebuffer()
+            .ok_or("failed to create framebuffer")?;
+
+        let target = RenderTarget {
+            resources: resources.clone(),
+            framebuffer,
+            color,
+            depth,
+            format,
+            width,
+            height,
+        };
+        target.attach()?;
+        Ok(target)
+    }
+
+    /// A target matching the size of the context's canvas.
+    pub fn for_canvas(resources: &ResourceManager) -> Result<RenderTarget, JsValue> {
+        RenderTarget::for_canvas_with_format(resources, ColorFormat::Rgba8)
+    }
+
+    /// Like `for_canvas`, with the color texture stored as `format`.
+    pub fn for_canvas_with_format(
+        resources: &ResourceManager,
+        format: ColorFormat,
+    ) -> Result<RenderTarget, JsValue> {
+        let (width, height) = canvas_size(resources.context())?;
+        RenderTarget::with_format(resources, width, height, format)
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn format(&self) -> ColorFormat {
+        self.format
+    }
+
+    pub fn color_texture(&self) -> &TextureHandle {
+        &self.color
+    }
+
+    pub fn framebuffer(&self) -> &WebGlFramebuffer {
+        &self.framebuffer
+    }
+
+    /// Reallocate the attachments if the size changed.
+    pub fn resize(&mut self, width: i32, height: i32) -> Result<(), JsValue> {
+        let width = width.max(1);
+        let height = height.max(1);
+        if width == self.width && height == self.height {
+            return Ok(());
+        }
+        self.resources.resize_texture(&self.color, width, height)?;
+        self.resources
+            .resize_renderbuffer(&self.depth, width, height)?;
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    /// Follow the canvas size; call once per frame before drawing.
+    pub fn resize_to_canvas(&mut self) -> Result<(), JsValue> {
+        let (width, height) = canvas_size(self.resources.context())?;
+        self.resize(width, height)
+    }
+
+    /// Recreate the framebuffer after the context was lost and restored,
+    /// once the `ResourceManager` has restored the attachments. The
+    /// contents are gone.
+    pub fn restore(&mut self) -> Result<(), JsValue> {
+        self.framebuffer = self
+            .resources
+            .context()
+            .create_framebuffer()
+            .ok_or("failed to create framebuffer")?;
+        self.attach()
+    }
+
+    /// Direct drawing into this target and cover it with the viewport.
+    pub fn bind(&self, context: &GL) {
+        context.bind_framebuffer(GL::FRAMEBUFFER, Some(&self.framebuffer));
+        context.viewport(0, 0, self.width, self.height);
+    }
+
+    /// Go back to drawing on the canvas.
+    pub fn unbind(context: &GL) {
+        context.bind_framebuffer(GL::FRAMEBUFFER, None);
+    }
+
+    /// Attach both images to the framebuffer; resizing only reallocates
+    /// their storage.
+    fn attach(&self) -> Result<(), JsValue> {
+        let context = self.resources.context();
+        let color = self
+            .color
+            .get()
+            .ok_or("render target texture has been deleted")?;
+        let depth = self
+            .depth
+            .get()
+            .ok_or("render target renderbuffer has been deleted")?;
+        context.bind_framebuffer(GL::FRAMEBUFFER, Some(&self.framebuffer));
+        context.framebuffer_texture_2d(
+            GL::FRAMEBUFFER,
+            GL::COLOR_ATTACHMENT0,
+            GL::TEXTURE_2D,
+            Some(&color),
+            0,
+        );
+        context.framebuffer_renderbuffer(
+            GL::FRAMEBUFFER,
+            GL::DEPTH_ATTACHMENT,
+            GL::RENDERBUFFER,
+            Some(&depth),
+        );
+        let status = context.check_framebuffer_status(GL::FRAMEBUFFER);
+        context.bind_framebuffer(GL::FRAMEBUFFER, None);
+        if status != GL::FRAMEBUFFER_COMPLETE {
+            return Err(format!("framebuffer incomplete: 0x{:x}", status).into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        self.resources
+            .context()
+            .delete_framebuffer(Some(&self.framebuffer));
+    }
+}
+
+fn canvas_size(context: &GL) -> Result<(i32, i32), JsValue> {
+    let canvas = context
+        .canvas()
+        .ok_or("context has no canvas")?
+        .dyn_into::<web_sys::HtmlCanvasElement>()?;
+    Ok((canvas.width() as i32, canvas.height() as i32))
+}
@@ -0,0 +1,1015 @@
+use std::{
+    cell::{RefCell, RefMut},
+    collections::HashMap,
+    fmt::Debug,
+    rc::{Rc, Weak},
+};
+
+use wasm_bindgen::prelude::*;
+use web_sys::{
+    WebGlBuffer, WebGlProgram, WebGlRenderbuffer, WebGlRenderingContext as GL, WebGlTexture,
+};
+
+use crate::shader::Shader;
+
+use super::upload::{self, GlElement};
+use super::GlState;
+
+/// `HALF_FLOAT_OES` from `OES_texture_half_float`, which `web_sys` does not
+/// export for WebGL1.
+const HALF_FLOAT_OES: u32 = 0x8D61;
+
+/// GPU objects and bytes currently owned by a `ResourceManager`.
+///
+/// Framebuffers hold no storage of their own and are not counted; their
+/// attachments are, as textures and renderbuffers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub buffers: usize,
+    pub buffer_bytes: usize,
+    pub textures: usize,
+    pub texture_bytes: usize,
+    pub renderbuffers: usize,
+    pub renderbuffer_bytes: usize,
+    pub programs: usize,
+}
+
+impl MemoryStats {
+    /// Buffer, texture and renderbuffer bytes together. Programs are not
+    /// counted, as their size is up to the driver.
+    pub fn total_bytes(&self) -> usize {
+        self.buffer_bytes + self.texture_bytes + self.renderbuffer_bytes
+    }
+}
+
+/// How a managed texture stores its texels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFormat {
+    /// RGBA, 8 bits per channel.
+    Rgba8,
+    /// RGBA half floats; needs `OES_texture_half_float`.
+    RgbaHalfFloat,
+    /// 32-bit depth; needs `WEBGL_depth_texture`.
+    Depth,
+}
+
+impl TextureFormat {
+    pub fn bytes_per_texel(self) -> usize {
+        match self {
+            TextureFormat::Rgba8 | TextureFormat::Depth => 4,
+            TextureFormat::RgbaHalfFloat => 8,
+        }
+    }
+
+    /// The format and type arguments of `texImage2D`.
+    fn gl_format(self) -> (u32, u32) {
+        match self {
+            TextureFormat::Rgba8 => (GL::RGBA, GL::UNSIGNED_BYTE),
+            TextureFormat::RgbaHalfFloat => (GL::RGBA, HALF_FLOAT_OES),
+            TextureFormat::Depth => (GL::DEPTH_COMPONENT, GL::UNSIGNED_INT),
+        }
+    }
+
+    /// Extensions the format relies on, which a restored context starts
+    /// without.
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            TextureFormat::Rgba8 => &[],
+            TextureFormat::RgbaHalfFloat => &[
+                "OES_texture_half_float",
+                "OES_texture_half_float_linear",
+                "EXT_color_buffer_half_float",
+            ],
+            TextureFormat::Depth => &["WEBGL_depth_texture"],
+        }
+    }
+}
+
+/// Bytes per pixel of a renderbuffer in `format`.
+fn renderbuffer_bytes_per_pixel(format: u32) -> usize {
+    match format {
+        GL::STENCIL_INDEX8 => 1,
+        GL::DEPTH_STENCIL => 4,
+        // DEPTH_COMPONENT16, RGBA4, RGB5_A1 and RGB565.
+        _ => 2,
+    }
+}
+
+fn texel_count(width: i32, height: i32) -> usize {
+    width.max(0) as usize * height.max(0) as usize
+}
+
+/// Deletes the GL objects behind handles. Implemented by the WebGL
+/// context; the bookkeeping is generic over it so reference counting and
+/// accounting can be exercised without one.
+pub trait Backend: Debug {
+    type Buffer: Clone + Debug;
+    type Texture: Clone + Debug;
+    type Renderbuffer: Clone + Debug;
+    type Program: Clone + Debug;
+
+    fn delete_buffer(&self, buffer: &Self::Buffer);
+    fn delete_texture(&self, texture: &Self::Texture);
+    fn delete_renderbuffer(&self, renderbuffer: &Self::Renderbuffer);
+    fn delete_program(&self, program: &Self::Program);
+}
+
+impl Backend for GL {
+    type Buffer = WebGlBuffer;
+    type Texture = WebGlTexture;
+    type Renderbuffer = WebGlRenderbuffer;
+    type Program = WebGlProgram;
+
+    fn delete_buffer(&self, buffer: &WebGlBuffer) {
+        GL::delete_buffer(self, Some(buffer));
+    }
+
+    fn delete_texture(&self, texture: &WebGlTexture) {
+        GL::delete_texture(self, Some(texture));
+    }
+
+    fn delete_renderbuffer(&self, renderbuffer: &WebGlRenderbuffer) {
+        GL::delete_renderbuffer(self, Some(renderbuffer));
+    }
+
+    fn delete_program(&self, program: &WebGlProgram) {
+        GL::delete_program(self, Some(program));
+    }
+}
+
+#[derive(Debug)]
+enum Object<G: Backend> {
+    Buffer(G::Buffer),
+    Texture(G::Texture),
+    Renderbuffer(G::Renderbuffer),
+    Program(G::Program),
+}
+
+/// What a resource was made from, kept so it can be made again after the
+/// context is lost.
+#[derive(Debug)]
+enum Source {
+    Buffer {
+        target: u32,
+        usage: u32,
+        data: Vec<u8>,
+    },
+    /// A buffer whose contents are written piecemeal by its owner, so only
+    /// its size is kept.
+    BufferStorage {
+        target: u32,
+        usage: u32,
+        bytes: usize,
+    },
+    Texture {
+        format: TextureFormat,
+        filter: u32,
+        width: i32,
+        height: i32,
+        pixels: Option<Vec<u8>>,
+    },
+    Renderbuffer {
+        format: u32,
+        width: i32,
+        height: i32,
+    },
+    Program {
+        vert_shader: String,
+        frag_shader: String,
+    },
+}
+
+#[derive(Debug)]
+struct Entry<G: Backend> {
+    object: Object<G>,
+    bytes: usize,
+    /// `None` for adopted objects, which cannot be restored.
+    source: Option<Source>,
+}
+
+#[derive(Debug)]
+struct Registry<G: Backend> {
+    backend: G,
+    next_id: u32,
+    entries: HashMap<u32, Entry<G>>,
+}
+
+impl<G: Backend> Registry<G> {
+    /// Delete the GL object behind `id`. Returns false if it was already
+    /// gone.
+    fn release(&mut self, id: u32) -> bool {
+        let entry = match self.entries.remove(&id) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        match &entry.object {
+            Object::Buffer(buffer) => self.backend.delete_buffer(buffer),
+            Object::Texture(texture) => self.backend.delete_texture(texture),
+            Object::Renderbuffer(renderbuffer) => self.backend.delete_renderbuffer(renderbuffer),
+            Object::Program(program) => self.backend.delete_program(program),
+        }
+        true
+    }
+
+    fn stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats::default();
+        for entry in self.entries.values() {
+            match entry.object {
+                Object::Buffer(_) => {
+                    stats.buffers += 1;
+                    stats.buffer_bytes += entry.bytes;
+                }
+                Object::Texture(_) => {
+                    stats.textures += 1;
+                    stats.texture_bytes += entry.bytes;
+                }
+                Object::Renderbuffer(_) => {
+                    stats.renderbuffers += 1;
+                    stats.renderbuffer_bytes += entry.bytes;
+                }
+                Object::Program(_) => stats.programs += 1,
+            }
+        }
+        stats
+    }
+}
+
+/// The registry plus the releases that had to wait for it, shared by a
+/// manager and its handles.
+#[derive(Debug)]
+struct Shared<G: Backend> {
+    registry: RefCell<Registry<G>>,
+    /// IDs whose last handle was dropped while the registry was borrowed.
+    deferred: RefCell<Vec<u32>>,
+}
+
+impl<G: Backend> Shared<G> {
+    fn new(backend: G) -> Rc<Shared<G>> {
+        Rc::new(Shared {
+            registry: RefCell::new(Registry {
+                backend,
+                next_id: 0,
+                entries: HashMap::new(),
+            }),
+            deferred: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// The registry, with the deferred releases carried out first.
+    fn registry_mut(&self) -> RefMut<'_, Registry<G>> {
+        let mut registry = self.registry.borrow_mut();
+        for id in self.deferred.borrow_mut().drain(..) {
+            registry.release(id);
+        }
+        registry
+    }
+
+    fn insert(
+        self: &Rc<Self>,
+        object: Object<G>,
+        bytes: usize,
+        source: Option<Source>,
+    ) -> Rc<HandleInner<G>> {
+        let mut registry = self.registry_mut();
+        registry.next_id += 1;
+        let id = registry.next_id;
+        registry.entries.insert(
+            id,
+            Entry {
+                object,
+                bytes,
+                source,
+            },
+        );
+        Rc::new(HandleInner {
+            id,
+            shared: Rc::downgrade(self),
+        })
+    }
+
+    fn release(&self, id: u32) -> bool {
+        self.registry_mut().release(id)
+    }
+
+    fn stats(&self) -> MemoryStats {
+        self.registry_mut().stats()
+    }
+}
+
+/// Shared by every clone of a handle; dropping the last clone deletes the
+/// GL object.
+#[derive(Debug)]
+struct HandleInner<G: Backend> {
+    id: u32,
+    shared: Weak<Shared<G>>,
+}
+
+impl<G: Backend> Drop for HandleInner<G> {
+    fn drop(&mut self) {
+        if let Some(shared) = self.shared.upgrade() {
+            // A handle may be dropped while the registry is borrowed, e.g.
+            // by code running during an update. Rather than leak the
+            // object, leave it to the next call that borrows the registry.
+            match shared.registry.try_borrow_mut() {
+                Ok(mut registry) => {
+                    registry.release(self.id);
+                }
+                Err(_) => shared.deferred.borrow_mut().push(self.id),
+            }
+        }
+    }
+}
+
+macro_rules! handle {
+    ($(#[$meta:meta])* $name:ident, $object:ident) => {
+        $(#[$meta])*
+        #[derive(Debug)]
+        pub struct $name<G: Backend = GL>(Rc<HandleInner<G>>);
+
+        impl<G: Backend> $name<G> {
+            pub fn id(&self) -> u32 {
+                self.0.id
+            }
+
+            /// How many clones of this handle are alive.
+            pub fn ref_count(&self) -> usize {
+                Rc::strong_count(&self.0)
+            }
+
+            /// The GL object, or `None` once it has been deleted.
+            pub fn get(&self) -> Option<G::$object> {
+                let shared = self.0.shared.upgrade()?;
+                let registry = shared.registry.borrow();
+                match registry.entries.get(&self.0.id).map(|entry| &entry.object) {
+                    Some(Object::$object(object)) => Some(object.clone()),
+                    _ => None,
+                }
+            }
+
+            pub fn is_alive(&self) -> bool {
+                self.0.shared.upgrade().map_or(false, |shared| {
+                    shared.registry.borrow().entries.contains_key(&self.0.id)
+                })
+            }
+        }
+
+        impl<G: Backend> Clone for $name<G> {
+            fn clone(&self) -> $name<G> {
+                $name(self.0.clone())
+            }
+        }
+
+        impl<G: Backend> PartialEq for $name<G> {
+            fn eq(&self, other: &$name<G>) -> bool {
+                Rc::ptr_eq(&self.0, &other.0)
+            }
+        }
+
+        impl<G: Backend> Eq for $name<G> {}
+    };
+}
+
+handle!(
+    /// A reference-counted `WebGlBuffer` owned by a `ResourceManager`.
+    BufferHandle,
+    Buffer
+);
+handle!(
+    /// A reference-counted `WebGlTexture` owned by a `ResourceManager`.
+    TextureHandle,
+    Texture
+);
+handle!(
+    /// A reference-counted `WebGlRenderbuffer` owned by a `ResourceManager`.
+    RenderbufferHandle,
+    Renderbuffer
+);
+handle!(
+    /// A reference-counted `WebGlProgram` owned by a `ResourceManager`.
+    ProgramHandle,
+    Program
+);
+
+/// Owns GPU objects on behalf of typed handles.
+///
+/// Handles are reference counted: cloning one is cheap, and when the last
+/// clone is dropped the GL object is deleted. `delete_*` deletes it
+/// right away instead, after which every clone's `get` returns `None`.
+/// The manager itself is a shared reference and may be cloned freely.
+/// Buffers are bound for uploads through its `GlState`, so the state cache
+/// stays in step with them.
+#[derive(Debug, Clone)]
+pub struct ResourceManager {
+    context: GL,
+    state: GlState,
+    shared: Rc<Shared<GL>>,
+}
+
+impl ResourceManager {
+    pub fn new(context: &GL) -> ResourceManager {
+        ResourceManager {
+            context: context.clone(),
+            state: GlState::new(context),
+            shared: Shared::new(context.clone()),
+        }
+    }
+
+    pub fn context(&self) -> &GL {
+        &self.context
+    }
+
+    /// The state cache shared by everything drawing with these resources.
+    pub fn state(&self) -> &GlState {
+        &self.state
+    }
+
+    /// Create a buffer bound to `target` and fill it with `data`. The
+    /// buffer is left bound.
+    pub fn create_buffer<T: GlElement>(
+        &self,
+        target: u32,
+        data: &[T],
+        usage: u32,
+    ) -> Result<BufferHandle, JsValue> {
+        let data = upload::as_bytes(data);
+        let buffer = create_buffer(&self.state, target, data, usage)?;
+        let source = Source::Buffer {
+            target,
+            usage,
+            data: data.to_vec(),
+        };
+        let inner = self
+            .shared
+            .insert(Object::Buffer(buffer), data.len(), Some(source));
+        Ok(BufferHandle(inner))
+    }
+
+    /// Replace the contents of `handle` with `data`, reallocating it to
+    /// fit. The buffer is left bound to `target`.
+    pub fn update_buffer<T: GlElement>(
+        &self,
+        handle: &BufferHandle,
+        target: u32,
+        data: &[T],
+        usage: u32,
+    ) -> Result<(), JsValue> {
+        let mut registry = self.shared.registry_mut();
+        let entry = registry
+            .entries
+            .get_mut(&handle.id())
+            .ok_or("buffer has been deleted")?;
+        let buffer = match &entry.object {
+            Object::Buffer(buffer) => buffer,
+            _ => return Err("handle is not a buffer".into()),
+        };
+        let data = upload::as_bytes(data);
+        self.state.bind_buffer(target, Some(buffer));
+        upload::buffer_data(&self.context, target, data, usage);
+        entry.bytes = data.len();
+        entry.source = Some(Source::Buffer {
+            target,
+            usage,
+            data: data.to_vec(),
+        });
+        Ok(())
+    }
+
+    /// Create a buffer of `bytes` bytes bound to `target` with undefined
+    /// contents, for the caller to fill with `bufferSubData`. The buffer
+    /// is left bound. After a context loss it is recreated at the same
+    /// size, and its contents are undefined again.
+    pub fn create_buffer_storage(
+        &self,
+        target: u32,
+        bytes: usize,
+        usage: u32,
+    ) -> Result<BufferHandle, JsValue> {
+        let buffer = create_buffer_storage(&self.state, target, bytes, usage)?;
+        let source = Source::BufferStorage {
+            target,
+            usage,
+            bytes,
+        };
+        let inner = self
+            .shared
+            .insert(Object::Buffer(buffer), bytes, Some(source));
+        Ok(BufferHandle(inner))
+    }
+
+    /// Reallocate `handle` to hold `bytes` bytes with undefined contents.
+    /// The buffer is left bound to `target`.
+    pub fn resize_buffer(
+        &self,
+        handle: &BufferHandle,
+        target: u32,
+        bytes: usize,
+        usage: u32,
+    ) -> Result<(), JsValue> {
+        let mut registry = self.shared.registry_mut();
+        let entry = registry
+            .entries
+            .get_mut(&handle.id())
+            .ok_or("buffer has been deleted")?;
+        let buffer = match &entry.object {
+            Object::Buffer(buffer) => buffer,
+            _ => return Err("handle is not a buffer".into()),
+        };
+        self.state.bind_buffer(target, Some(buffer));
+        self.context
+            .buffer_data_with_i32(target, bytes as i32, usage);
+        entry.bytes = bytes;
+        entry.source = Some(Source::BufferStorage {
+            target,
+            usage,
+            bytes,
+        });
+        Ok(())
+    }
+
+    /// Take ownership of a buffer created elsewhere, holding `bytes`.
+    /// Its contents are unknown, so it is not restored after a context
+    /// loss.
+    pub fn adopt_buffer(&self, buffer: WebGlBuffer, bytes: usize) -> BufferHandle {
+        BufferHandle(self.shared.insert(Object::Buffer(buffer), bytes, None))
+    }
+
+    /// Create an RGBA8 texture of `width`×`height`, filled from `pixels`
+    /// or left uninitialized.
+    pub fn create_texture(
+        &self,
+        width: i32,
+        height: i32,
+        pixels: Option<&[u8]>,
+    ) -> Result<TextureHandle, JsValue> {
+        self.create_texture_as(width, height, TextureFormat::Rgba8, GL::LINEAR, pixels)
+    }
+
+    /// Like `create_texture`, stored as `format` and filtered with
+    /// `filter`, e.g. an uninitialized half-float texture to render into.
+    /// The extensions `format` needs must be enabled.
+    pub fn create_texture_as(
+        &self,
+        width: i32,
+        height: i32,
+        format: TextureFormat,
+        filter: u32,
+        pixels: Option<&[u8]>,
+    ) -> Result<TextureHandle, JsValue> {
+        let texture = create_texture(&self.context, format, filter, width, height, pixels)?;
+        let source = Source::Texture {
+            format,
+            filter,
+            width,
+            height,
+            pixels: pixels.map(<[u8]>::to_vec),
+        };
+        let bytes = texel_count(width, height) * format.bytes_per_texel();
+        let inner = self
+            .shared
+            .insert(Object::Texture(texture), bytes, Some(source));
+        Ok(TextureHandle(inner))
+    }
+
+    /// Reallocate `handle` at `width`×`height`, keeping its format. The
+    /// contents are undefined afterwards. Leaves no texture bound.
+    pub fn resize_texture(
+        &self,
+        handle: &TextureHandle,
+        width: i32,
+        height: i32,
+    ) -> Result<(), JsValue> {
+        let mut registry = self.shared.registry_mut();
+        let entry = registry
+            .entries
+            .get_mut(&handle.id())
+            .ok_or("texture has been deleted")?;
+        match (&entry.object, &mut entry.source) {
+            (
+                Object::Texture(texture),
+                Some(Source::Texture {
+                    format,
+                    width: source_width,
+                    height: source_height,
+                    pixels,
+                    ..
+                }),
+            ) => {
+                allocate_texture(&self.context, texture, *format, width, height, None)?;
+                self.context.bind_texture(GL::TEXTURE_2D, None);
+                *source_width = width;
+                *source_height = height;
+                *pixels = None;
+                entry.bytes = texel_count(width, height) * format.bytes_per_texel();
+                Ok(())
+            }
+            _ => Err("handle is not a managed texture".into()),
+        }
+    }
+
+    /// Take ownership of a texture created elsewhere, holding `bytes`.
+    /// Not restored after a context loss.
+    pub fn adopt_texture(&self, texture: WebGlTexture, bytes: usize) -> TextureHandle {
+        TextureHandle(self.shared.insert(Object::Texture(texture), bytes, None))
+    }
+
+    /// Create a renderbuffer of `width`×`height` in `format`, e.g.
+    /// `DEPTH_COMPONENT16` for a render target's depth.
+    pub fn create_renderbuffer(
+        &self,
+        format: u32,
+        width: i32,
+        height: i32,
+    ) -> Result<RenderbufferHandle, JsValue> {
+        let renderbuffer = create_renderbuffer(&self.context, format, width, height)?;
+        let source = Source::Renderbuffer {
+            format,
+            width,
+            height,
+        };
+        let bytes = texel_count(width, height) * renderbuffer_bytes_per_pixel(format);
+        let inner = self
+            .shared
+            .insert(Object::Renderbuffer(renderbuffer), bytes, Some(source));
+        Ok(RenderbufferHandle(inner))
+    }
+
+    /// Reallocate `handle` at `width`×`height`, keeping its format.
+    pub fn resize_renderbuffer(
+        &self,
+        handle: &RenderbufferHandle,
+        width: i32,
+        height: i32,
+    ) -> Result<(), JsValue> {
+        let mut registry = self.shared.registry_mut();
+        let entry = registry
+            .entries
+            .get_mut(&handle.id())
+            .ok_or("renderbuffer has been deleted")?;
+        match (&entry.object, &mut entry.source) {
+            (
+                Object::Renderbuffer(renderbuffer),
+                Some(Source::Renderbuffer {
+                    format,
+                    width: source_width,
+                    height: source_height,
+                }),
+            ) => {
+                allocate_renderbuffer(&self.context, renderbuffer, *format, width, height);
+                *source_width = width;
+                *source_height = height;
+                entry.bytes = texel_count(width, height) * renderbuffer_bytes_per_pixel(*format);
+                Ok(())
+            }
+            _ => Err("handle is not a managed renderbuffer".into()),
+        }
+    }
+
+    /// Compile and link a program.
+    pub fn create_program(
+        &self,
+        vert_shader: &str,
+        frag_shader: &str,
+    ) -> Result<ProgramHandle, JsValue> {
+        let program = Shader::build_program(&self.context, vert_shader, frag_shader)?;
+        let source = Source::Program {
+            vert_shader: vert_shader.to_string(),
+            frag_shader: frag_shader.to_string(),
+        };
+        Ok(ProgramHandle(self.shared.insert(
+            Object::Program(program),
+            0,
+            Some(source),
+        )))
+    }
+
+    /// Take ownership of a program linked elsewhere. Not restored after a
+    /// context loss.
+    pub fn adopt_program(&self, program: WebGlProgram) -> ProgramHandle {
+        ProgramHandle(self.shared.insert(Object::Program(program), 0, None))
+    }
+
+    /// Delete the buffer now, even if other handles still point at it.
+    /// Returns false if it was already deleted.
+    pub fn delete_buffer(&self, handle: &BufferHandle) -> bool {
+        self.shared.release(handle.id())
+    }
+
+    pub fn delete_texture(&self, handle: &TextureHandle) -> bool {
+        self.shared.release(handle.id())
+    }
+
+    pub fn delete_renderbuffer(&self, handle: &RenderbufferHandle) -> bool {
+        self.shared.release(handle.id())
+    }
+
+    pub fn delete_program(&self, handle: &ProgramHandle) -> bool {
+        self.shared.release(handle.id())
+    }
+
+    /// Recreate every resource from its retained source after the context
+    /// was lost and restored. Handles stay valid and resolve to the new
+    /// objects; adopted resources have no source and are dropped, so their
+    /// handles' `get` returns `None` from now on.
+    pub fn restore(&self) -> Result<(), JsValue> {
+        // 32-bit index buffers rely on this extension, which a restored
+        // context starts without.
+        super::supports_u32_indices(&self.context);
+        // So is every piece of state the cache remembers.
+        self.state.invalidate();
+
+        let mut registry = self.shared.registry_mut();
+        let context = &self.context;
+        registry.entries.retain(|_, entry| entry.source.is_some());
+        for entry in registry.entries.values_mut() {
+            entry.object = match &entry.source {
+                Some(Source::Buffer {
+                    target,
+                    usage,
+                    data,
+                }) => Object::Buffer(create_buffer(&self.state, *target, data, *usage)?),
+                Some(Source::BufferStorage {
+                    target,
+                    usage,
+                    bytes,
+                }) => Object::Buffer(create_buffer_storage(&self.state, *target, *bytes, *usage)?),
+                Some(Source::Texture {
+                    format,
+                    filter,
+                    width,
+                    height,
+                    pixels,
+                }) => {
+                    for extension in format.extensions() {
+                        context.get_extension(extension)?;
+                    }
+                    Object::Texture(create_texture(
+                        context,
+                        *format,
+                        *filter,
+                        *width,
+                        *height,
+                        pixels.as_deref(),
+                    )?)
+                }
+                Some(Source::Renderbuffer {
+                    format,
+                    width,
+                    height,
+                }) => Object::Renderbuffer(create_renderbuffer(context, *format, *width, *height)?),
+                Some(Source::Program {
+                    vert_shader,
+                    frag_shader,
+                }) => Object::Program(Shader::build_program(context, vert_shader, frag_shader)?),
+                None => continue,
+            };
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        self.shared.stats()
+    }
+}
+
+fn create_buffer(
+    state: &GlState,
+    target: u32,
+    data: &[u8],
+    usage: u32,
+) -> Result<WebGlBuffer, JsValue> {
+    let buffer = state
+        .context()
+        .create_buffer()
+        .ok_or("failed to create buffer")?;
+    state.bind_buffer(target, Some(&buffer));
+    upload::buffer_data(state.context(), target, data, usage);
+    Ok(buffer)
+}
+
+fn create_buffer_storage(
+    state: &GlState,
+    target: u32,
+    bytes: usize,
+    usage: u32,
+) -> Result<WebGlBuffer, JsValue> {
+    let buffer = state
+        .context()
+        .create_buffer()
+        .ok_or("failed to create buffer")?;
+    state.bind_buffer(target, Some(&buffer));
+    state
+        .context()
+        .buffer_data_with_i32(target, bytes as i32, usage);
+    Ok(buffer)
+}
+
+/// A texture clamped at the edges, so any size works without mipmaps.
+/// Leaves it bound.
+fn create_texture(
+    context: &GL,
+    format: TextureFormat,
+    filter: u32,
+    width: i32,
+    height: i32,
+    pixels: Option<&[u8]>,
+) -> Result<WebGlTexture, JsValue> {
+    let texture = context.create_texture().ok_or("failed to create texture")?;
+    allocate_texture(context, &texture, format, width, height, pixels)?;
+    context.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, filter as i32);
+    context.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, filter as i32);
+    context.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE as i32);
+    context.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE as i32);
+    Ok(texture)
+}
+
+/// Bind `texture` and give it storage. Leaves it bound.
+fn allocate_texture(
+    context: &GL,
+    texture: &WebGlTexture,
+    format: TextureFormat,
+    width: i32,
+    height: i32,
+    pixels: Option<&[u8]>,
+) -> Result<(), JsValue> {
+    let (gl_format, texel_type) = format.gl_format();
+    context.bind_texture(GL::TEXTURE_2D, Some(texture));
+    context.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        GL::TEXTURE_2D,
+        0,
+        gl_format as i32,
+        width,
+        height,
+        0,
+        gl_format,
+        texel_type,
+        pixels,
+    )
+}
+
+fn create_renderbuffer(
+    context: &GL,
+    format: u32,
+    width: i32,
+    height: i32,
+) -> Result<WebGlRenderbuffer, JsValue> {
+    let renderbuffer = context
+        .create_renderbuffer()
+        .ok_or("failed to create renderbuffer")?;
+    allocate_renderbuffer(context, &renderbuffer, format, width, height);
+    Ok(renderbuffer)
+}
+
+fn allocate_renderbuffer(
+    context: &GL,
+    renderbuffer: &WebGlRenderbuffer,
+    format: u32,
+    width: i32,
+    height: i32,
+) {
+    context.bind_renderbuffer(GL::RENDERBUFFER, Some(renderbuffer));
+    context.renderbuffer_storage(GL::RENDERBUFFER, format, width, height);
+    context.bind_renderbuffer(GL::RENDERBUFFER, None);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for the context: objects are numbers, and deleting one
+    /// records it.
+    #[derive(Debug, Clone, Default)]
+    struct Recorder {
+        deleted: Rc<RefCell<Vec<u32>>>,
+    }
+
+    impl Backend for Recorder {
+        type Buffer = u32;
+        type Texture = u32;
+        type Renderbuffer = u32;
+        type Program = u32;
+
+        fn delete_buffer(&self, buffer: &u32) {
+            self.deleted.borrow_mut().push(*buffer);
+        }
+
+        fn delete_texture(&self, texture: &u32) {
+            self.deleted.borrow_mut().push(*texture);
+        }
+
+        fn delete_renderbuffer(&self, renderbuffer: &u32) {
+            self.deleted.borrow_mut().push(*renderbuffer);
+        }
+
+        fn delete_program(&self, program: &u32) {
+            self.deleted.borrow_mut().push(*program);
+        }
+    }
+
+    fn buffer(shared: &Rc<Shared<Recorder>>, object: u32, bytes: usize) -> BufferHandle<Recorder> {
+        BufferHandle(shared.insert(Object::Buffer(object), bytes, None))
+    }
+
+    fn texture(
+        shared: &Rc<Shared<Recorder>>,
+        object: u32,
+        bytes: usize,
+    ) -> TextureHandle<Recorder> {
+        TextureHandle(shared.insert(Object::Texture(object), bytes, None))
+    }
+
+    #[test]
+    fn the_last_clone_deletes_the_object() {
+        let recorder = Recorder::default();
+        let shared = Shared::new(recorder.clone());
+        let handle = buffer(&shared, 7, 64);
+        let clone = handle.clone();
+        assert_eq!(handle.ref_count(), 2);
+        assert_eq!(clone, handle);
+        assert_eq!(clone.get(), Some(7));
+
+        drop(handle);
+        assert!(recorder.deleted.borrow().is_empty());
+        assert_eq!(clone.ref_count(), 1);
+        assert!(clone.is_alive());
+
+        drop(clone);
+        assert_eq!(*recorder.deleted.borrow(), [7]);
+        assert_eq!(shared.stats(), MemoryStats::default());
+    }
+
+    #[test]
+    fn explicit_deletes_happen_once() {
+        let recorder = Recorder::default();
+        let shared = Shared::new(recorder.clone());
+        let handle = buffer(&shared, 3, 16);
+        assert!(shared.release(handle.id()));
+        assert!(!shared.release(handle.id()));
+        assert_eq!(handle.get(), None);
+        assert!(!handle.is_alive());
+        drop(handle);
+        assert_eq!(*recorder.deleted.borrow(), [3]);
+    }
+
+    #[test]
+    fn drops_during_a_borrow_are_deferred_not_leaked() {
+        let recorder = Recorder::default();
+        let shared = Shared::new(recorder.clone());
+        let handle = texture(&shared, 9, 256);
+        {
+            let _registry = shared.registry.borrow_mut();
+            drop(handle);
+            assert!(recorder.deleted.borrow().is_empty());
+        }
+        // Still counted until the registry is next borrowed for writing.
+        assert_eq!(shared.stats().textures, 0);
+        assert_eq!(*recorder.deleted.borrow(), [9]);
+    }
+
+    #[test]
+    fn handles_outliving_the_manager_do_nothing() {
+        let recorder = Recorder::default();
+        let shared = Shared::new(recorder.clone());
+        let handle = buffer(&shared, 1, 4);
+        drop(shared);
+        assert_eq!(handle.get(), None);
+        drop(handle);
+        assert!(recorder.deleted.borrow().is_empty());
+    }
+
+    #[test]
+    fn stats_add_up_each_kind() {
+        let shared = Shared::new(Recorder::default());
+        let _buffers = [buffer(&shared, 1, 100), buffer(&shared, 2, 20)];
+        let _texture = texture(&shared, 3, 4096);
+        let _renderbuffer = RenderbufferHandle(shared.insert(Object::Renderbuffer(4), 512, None));
+        let _program = ProgramHandle(shared.insert(Object::Program(5), 0, None));
+        let stats = shared.stats();
+        assert_eq!(
+            stats,
+            MemoryStats {
+                buffers: 2,
+                buffer_bytes: 120,
+                textures: 1,
+                texture_bytes: 4096,
+                renderbuffers: 1,
+                renderbuffer_bytes: 512,
+                programs: 1,
+            }
+        );
+        assert_eq!(stats.total_bytes(), 4728);
+    }
+
+    #[test]
+    fn texture_sizes_follow_the_format() {
+        assert_eq!(
+            texel_count(4, 2) * TextureFormat::Rgba8.bytes_per_texel(),
+            32
+        );
+        assert_eq!(
+            texel_count(4, 2) * TextureFormat::RgbaHalfFloat.bytes_per_texel(),
+            64
+        );
+        assert_eq!(texel_count(-1, 2), 0);
+        assert_eq!(renderbuffer_bytes_per_pixel(GL::DEPTH_COMPONENT16), 2);
+    }
+}
@@ -0,0 +1,335 @@
+use wasm_bindgen::prelude::*;
+use web_sys::{WebGlFramebuffer, WebGlRenderingContext as GL};
+
+use crate::material::{Material, MaterialParam, ProgramCache};
+use crate::math::{self, Mat4};
+use crate::shader::{builtin, GlslVersion, Preprocessor, Shader};
+
+use super::{
+    GlState, Light, RenderbufferHandle, ResourceManager, TextureFormat, TextureHandle,
+};
+
+/// Texture unit the shadow map is bound to when drawing receivers, kept
+/// clear of the units `Material::apply` hands out from 0 upwards.
+pub const SHADOW_TEXTURE_UNIT: u32 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    /// Width and height of the shadow map in texels.
+    pub resolution: i32,
+    /// Depth offset against shadow acne; larger values lift shadows off
+    /// surfaces facing the light.
+    pub bias: f32,
+    /// Radius of the PCF kernel in texels; 0 gives hard shadows.
+    pub pcf_radius: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> ShadowSettings {
+        ShadowSettings {
+            resolution: 1024,
+            bias: 0.005,
+            pcf_radius: 1,
+        }
+    }
+}
+
+/// Depth of the scene as seen from a light, rendered before the main pass
+/// and sampled by receiving materials.
+///
+/// With `WEBGL_depth_texture` depth is written straight into a depth
+/// texture. Without it depth is packed into the RGBA channels of a color
+/// texture, and receivers are built with `SHADOW_PACKED` to unpack it.
+#[derive(Debug)]
+pub struct ShadowMap {
+    resources: ResourceManager,
+    light: Light,
+    settings: ShadowSettings,
+    enabled: bool,
+    packed: bool,
+    framebuffer: WebGlFramebuffer,
+    texture: TextureHandle,
+    renderbuffer: RenderbufferHandle,
+    preprocessor: Preprocessor,
+    depth_material: Material,
+    view_projection: Mat4,
+}
+
+impl ShadowMap {
+    pub fn new(
+        resources: &ResourceManager,
+        cache: &mut ProgramCache,
+        light: Light,
+        settings: ShadowSettings,
+    ) -> Result<ShadowMap, JsValue> {
+        let context = resources.context();
+        let packed = context.get_extension("WEBGL_depth_texture")?.is_none();
+
+        let mut preprocessor = Preprocessor::new(GlslVersion::WebGl1);
+        preprocessor.register_chunk("shadow_pack", builtin::SHADOW_PACK_CHUNK);
+        preprocessor.register_chunk("shadow_vertex", builtin::SHADOW_VERTEX_CHUNK);
+        preprocessor.register_chunk("shadow_fragment", builtin::SHADOW_FRAGMENT_CHUNK);
+
+        let defines = shadow_defines(packed, &settings);
+        let depth_material = Material::variant(
+            context,
+            cache,
+            &preprocessor,
+            builtin::SHADOW_DEPTH_VERT,
+            builtin::SHADOW_DEPTH_FRAG,
+            &borrow_defines(&defines),
+        )?;
+
+        if settings.resolution <= 0 {
+            return Err(invalid_resolution(settings.resolution));
+        }
+
+        // The texture takes whichever attachment holds depth, the
+        // renderbuffer the other one. Depth is compared, never
+        // interpolated.
+        let size = settings.resolution;
+        let (texture_format, renderbuffer_format) = if packed {
+            (TextureFormat::Rgba8, GL::DEPTH_COMPONENT16)
+        } else {
+            (TextureFormat::Depth, GL::RGBA4)
+        };
+        let texture = resources.create_texture_as(size, size, texture_format, GL::NEAREST, None)?;
+        context.bind_texture(GL::TEXTURE_2D, None);
+        let renderbuffer = resources.create_renderbuffer(renderbuffer_format, size, size)?;
+        let framebuffer = context
+            .create_framebuffer()
+            .ok_or("failed to create shadow framebuffer")?;
+
+        let shadow_map = ShadowMap {
+            resources: resources.clone(),
+            light,
+            settings,
+            enabled: false,
+            packed,
+            framebuffer,
+            texture,
+            renderbuffer,
+            preprocessor,
+            depth_material,
+            view_projection: light.view_projection(),
+        };
+        shadow_map.attach()?;
+        Ok(shadow_map)
+    }
+
+    /// Whether depth is packed into RGBA because `WEBGL_depth_texture` is
+    /// unavailable.
+    pub fn is_packed(&self) -> bool {
+        self.packed
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn light(&self) -> &Light {
+        &self.light
+    }
+
+    pub fn set_light(&mut self, light: Light) {
+        self.light = light;
+    }
+
+    /// The light's projection × view as of the last `begin`, e.g. to cull
+    /// casters the light cannot see.
+    pub fn view_projection(&self) -> &Mat4 {
+        &self.view_projection
+    }
+
+    pub fn settings(&self) -> &ShadowSettings {
+        &self.settings
+    }
+
+    pub fn set_bias(&mut self, bias: f32) {
+        self.settings.bias = bias;
+    }
+
+    /// Reallocate the map at a new resolution, which must be positive.
+    pub fn set_resolution(&mut self, resolution: i32) -> Result<(), JsValue> {
+        if resolution <= 0 {
+            return Err(invalid_resolution(resolution));
+        }
+        if resolution == self.settings.resolution {
+            return Ok(());
+        }
+        self.resources.resize_texture(&self.texture, resolution, resolution)?;
+        self.resources
+            .resize_renderbuffer(&self.renderbuffer, resolution, resolution)?;
+        self.settings.resolution = resolution;
+        Ok(())
+    }
+
+    /// Rebuild the map after the context was restored, keeping the light,
+    /// settings and whether it is enabled. `WEBGL_depth_texture` is asked
+    /// for again, so the map may switch between packed and depth-texture
+    /// storage.
+    pub fn restore(&mut self, cache: &mut ProgramCache) -> Result<(), JsValue> {
+        let enabled = self.enabled;
+        let resources = self.resources.clone();
+        *self = ShadowMap::new(&resources, cache, self.light, self.settings)?;
+        self.enabled = enabled;
+        Ok(())
+    }
+
+    /// Build a material that receives shadows from this map. The sources
+    /// may include "shadow_vertex" and "shadow_fragment" and are built with
+    /// the defines matching this map.
+    pub fn receiver_material(
+        &self,
+        context: &GL,
+        cache: &mut ProgramCache,
+        vert_shader: &str,
+        frag_shader: &str,
+    ) -> Result<Material, JsValue> {
+        let defines = shadow_defines(self.packed, &self.settings);
+        Material::variant(
+            context,
+            cache,
+            &self.preprocessor,
+            vert_shader,
+            frag_shader,
+            &borrow_defines(&defines),
+        )
+    }
+
+    /// The lit material, shadowed by this map's light.
+    pub fn lit_material(
+        &self,
+        context: &GL,
+        cache: &mut ProgramCache,
+    ) -> Result<Material, JsValue> {
+        let mut material = self.receiver_material(
+            context,
+            cache,
+            builtin::LIT_SHADOWED_VERT,
+            builtin::LIT_SHADOWED_FRAG,
+        )?;
+        material.set_param("light_direction", MaterialParam::Vec3(self.light.direction()));
+        material.set_param("ambient_color", MaterialParam::Vec3([0.2, 0.2, 0.2]));
+        Ok(material)
+    }
+
+    /// Start the depth pass: bind and clear the map and make the depth
+    /// program current. Draw every caster with `draw_caster`, then `end`.
+    /// State changes go through `state`, so its cache stays in step.
+    pub fn begin(&mut self, state: &GlState) -> &Material {
+        self.view_projection = self.light.view_projection();
+
+        let context = state.context();
+        context.bind_framebuffer(GL::FRAMEBUFFER, Some(&self.framebuffer));
+        state.viewport(0, 0, self.settings.resolution, self.settings.resolution);
+        // Packed depth reads back as 1.0 (farthest) from a white clear.
+        state.clear_color(1.0, 1.0, 1.0, 1.0);
+        state.clear_depth(1.0);
+        state.enable(GL::DEPTH_TEST);
+        context.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT);
+
+        state.use_program(Some(self.depth_material.shader().program()));
+        self.depth_material.upload_params(context);
+        &self.depth_material
+    }
+
+    /// Set the depth program's transform for a caster with this model
+    /// matrix. The caller binds its positions and issues the draw.
+    pub fn draw_caster(&self, context: &GL, model: &Mat4) {
+        let light_matrix = math::mul(&self.view_projection, model);
+        context.uniform_matrix4fv_with_f32_array(
+            self.depth_material.shader().uniform_location("light_matrix"),
+            false,
+            &light_matrix,
+        );
+    }
+
+    pub fn end(&self, context: &GL) {
+        context.bind_framebuffer(GL::FRAMEBUFFER, None);
+    }
+
+    /// Feed the shadow uniforms of a receiver drawn with `model`. Uniforms
+    /// the program does not use are skipped.
+    pub fn bind_receiver(&self, context: &GL, shader: &Shader, model: &Mat4) {
+        if let Some(location) = shader.uniform_location("shadow_map") {
+            context.active_texture(GL::TEXTURE0 + SHADOW_TEXTURE_UNIT);
+            context.bind_texture(GL::TEXTURE_2D, self.texture.get().as_ref());
+            context.uniform1i(Some(location), SHADOW_TEXTURE_UNIT as i32);
+        }
+        let light_matrix = math::mul(&self.view_projection, model);
+        context.uniform_matrix4fv_with_f32_array(
+            shader.uniform_location("light_matrix"),
+            false,
+            &light_matrix,
+        );
+        context.uniform1f(shader.uniform_location("shadow_bias"), self.settings.bias);
+        let texel = 1.0 / self.settings.resolution as f32;
+        context.uniform2f(shader.uniform_location("shadow_texel_size"), texel, texel);
+    }
+
+    fn attach(&self) -> Result<(), JsValue> {
+        let context = self.resources.context();
+        let texture = self.texture.get().ok_or("shadow texture has been deleted")?;
+        let renderbuffer = self
+            .renderbuffer
+            .get()
+            .ok_or("shadow renderbuffer has been deleted")?;
+        let (texture_attachment, renderbuffer_attachment) = if self.packed {
+            (GL::COLOR_ATTACHMENT0, GL::DEPTH_ATTACHMENT)
+        } else {
+            (GL::DEPTH_ATTACHMENT, GL::COLOR_ATTACHMENT0)
+        };
+        context.bind_framebuffer(GL::FRAMEBUFFER, Some(&self.framebuffer));
+        context.framebuffer_texture_2d(
+            GL::FRAMEBUFFER,
+            texture_attachment,
+            GL::TEXTURE_2D,
+            Some(&texture),
+            0,
+        );
+        context.framebuffer_renderbuffer(
+            GL::FRAMEBUFFER,
+            renderbuffer_attachment,
+            GL::RENDERBUFFER,
+            Some(&renderbuffer),
+        );
+        let status = context.check_framebuffer_status(GL::FRAMEBUFFER);
+        context.bind_framebuffer(GL::FRAMEBUFFER, None);
+        if status != GL::FRAMEBUFFER_COMPLETE {
+            return Err(format!("shadow framebuffer incomplete: 0x{:x}", status).into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ShadowMap {
+    fn drop(&mut self) {
+        self.resources
+            .context()
+            .delete_framebuffer(Some(&self.framebuffer));
+    }
+}
+
+fn invalid_resolution(resolution: i32) -> JsValue {
+    format!("shadow map resolution must be positive, got {}", resolution).into()
+}
+
+fn shadow_defines(packed: bool, settings: &ShadowSettings) -> Vec<(&'static str, String)> {
+    let mut defines = vec![("PCF_RADIUS", settings.pcf_radius.to_string())];
+    if packed {
+        defines.push(("SHADOW_PACKED", String::new()));
+    }
+    defines
+}
+
+fn borrow_defines<'a>(defines: &'a [(&'static str, String)]) -> Vec<(&'a str, &'a str)> {
+    defines
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect()
+}
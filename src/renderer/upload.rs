@@ -0,0 +1,105 @@
+//! Typed uploads from Rust slices into GL buffers.
+//!
+//! A JS typed array over wasm memory is only valid until the next
+//! allocation that grows the memory, which detaches the old `ArrayBuffer`.
+//! The functions here create the view and hand it to GL in the same
+//! expression, so nothing can allocate while it is alive, and they need no
+//! `Result` in the caller.
+
+use std::mem;
+
+use js_sys::Object;
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A Rust type that maps onto a GL component type.
+pub trait GlElement: Copy + sealed::Sealed {
+    /// The matching `type` enum, e.g. `GL::FLOAT` for `f32`.
+    const GL_TYPE: u32;
+
+    /// A typed array viewing `data` in wasm memory.
+    ///
+    /// # Safety
+    ///
+    /// The view must be consumed before anything allocates.
+    #[doc(hidden)]
+    unsafe fn view(data: &[Self]) -> Object;
+}
+
+macro_rules! gl_element {
+    ($type:ty, $array:ty, $gl_type:expr) => {
+        impl sealed::Sealed for $type {}
+
+        impl GlElement for $type {
+            const GL_TYPE: u32 = $gl_type;
+
+            unsafe fn view(data: &[Self]) -> Object {
+                <$array>::view(data).into()
+            }
+        }
+    };
+}
+
+gl_element!(i8, js_sys::Int8Array, GL::BYTE);
+gl_element!(u8, js_sys::Uint8Array, GL::UNSIGNED_BYTE);
+gl_element!(i16, js_sys::Int16Array, GL::SHORT);
+gl_element!(u16, js_sys::Uint16Array, GL::UNSIGNED_SHORT);
+gl_element!(i32, js_sys::Int32Array, GL::INT);
+gl_element!(u32, js_sys::Uint32Array, GL::UNSIGNED_INT);
+gl_element!(f32, js_sys::Float32Array, GL::FLOAT);
+
+/// Size of one `T` in bytes.
+pub fn element_size<T: GlElement>() -> usize {
+    mem::size_of::<T>()
+}
+
+/// The raw bytes of `data`, as GL would read them.
+pub fn as_bytes<T: GlElement>(data: &[T]) -> &[u8] {
+    // Safety: every `GlElement` is a plain number without padding, so all
+    // of its bytes are initialized, and `u8` has no alignment requirement.
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, mem::size_of_val(data)) }
+}
+
+/// `bufferData` for the buffer bound to `target`, (re)allocating it to
+/// hold exactly `data`.
+pub fn buffer_data<T: GlElement>(context: &GL, target: u32, data: &[T], usage: u32) {
+    // Safety: the view is passed straight to GL and dropped with no
+    // allocation in between.
+    unsafe {
+        let view = T::view(data);
+        context.buffer_data_with_array_buffer_view(target, &view, usage);
+    }
+}
+
+/// `bufferSubData` for the buffer bound to `target`, writing `data` at
+/// `byte_offset`. The offset must be a multiple of the element size, or
+/// the data could not be read back as `T`s.
+pub fn buffer_sub_data<T: GlElement>(
+    context: &GL,
+    target: u32,
+    byte_offset: usize,
+    data: &[T],
+) -> Result<(), JsValue> {
+    if !byte_offset.is_multiple_of(element_size::<T>()) {
+        return Err(format!(
+            "byte offset {} is not aligned to {}-byte elements",
+            byte_offset,
+            element_size::<T>()
+        )
+        .into());
+    }
+    // Safety: as in `buffer_data`.
+    unsafe {
+        let view = T::view(data);
+        context.buffer_sub_data_with_i32_and_array_buffer_view(
+            target,
+            byte_offset as i32,
+            &view,
+        );
+    }
+    Ok(())
+}
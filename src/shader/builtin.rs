@@ -0,0 +1,434 @@
+//! Shader sources for the materials the crate ships with.
+//!
+//! Every scene program shares the same `projection_matrix` /
+//! `model_view_matrix` uniforms so `draw_scene` can feed any of them.
+//! The `*_CHUNK`s are meant for `Preprocessor::register_chunk`.
+
+/// Per-vertex colors, no lighting.
+pub const UNLIT_VERT: &str = r#"
+    attribute vec4 position;
+    attribute vec4 color;
+
+    uniform mat4 projection_matrix;
+    uniform mat4 model_view_matrix;
+
+    varying lowp vec4 vColor;
+
+    void main() {
+        gl_Position = projection_matrix * model_view_matrix * position;
+        vColor = color;
+    }
+"#;
+
+pub const UNLIT_FRAG: &str = r#"
+    varying lowp vec4 vColor;
+
+    void main() {
+        gl_FragColor = vColor;
+    }
+"#;
+
+/// Per-vertex colors lit by a single directional light.
+pub const LIT_VERT: &str = r#"
+    attribute vec4 position;
+    attribute vec3 normal;
+    attribute vec4 color;
+
+    uniform mat4 projection_matrix;
+    uniform mat4 model_view_matrix;
+
+    varying lowp vec4 vColor;
+    varying highp vec3 vNormal;
+
+    void main() {
+        gl_Position = projection_matrix * model_view_matrix * position;
+        vNormal = (model_view_matrix * vec4(normal, 0.0)).xyz;
+        vColor = color;
+    }
+"#;
+
+pub const LIT_FRAG: &str = r#"
+    precision mediump float;
+
+    uniform vec3 light_direction;
+    uniform vec3 ambient_color;
+
+    varying lowp vec4 vColor;
+    varying highp vec3 vNormal;
+
+    void main() {
+        float diffuse = max(dot(normalize(vNormal), -normalize(light_direction)), 0.0);
+        gl_FragColor = vec4(vColor.rgb * (ambient_color + diffuse), vColor.a);
+    }
+"#;
+
+/// A single texture sampled with per-vertex coordinates.
+pub const TEXTURED_VERT: &str = r#"
+    attribute vec4 position;
+    attribute vec2 texcoord;
+
+    uniform mat4 projection_matrix;
+    uniform mat4 model_view_matrix;
+
+    varying highp vec2 vTexcoord;
+
+    void main() {
+        gl_Position = projection_matrix * model_view_matrix * position;
+        vTexcoord = texcoord;
+    }
+"#;
+
+pub const TEXTURED_FRAG: &str = r#"
+    precision mediump float;
+
+    uniform sampler2D diffuse_map;
+    uniform vec4 tint;
+
+    varying highp vec2 vTexcoord;
+
+    void main() {
+        gl_FragColor = texture2D(diffuse_map, vTexcoord) * tint;
+    }
+"#;
+
+/// `pack_depth` / `unpack_depth`: spread a [0, 1) depth over the four 8-bit
+/// channels of an RGBA texture, for when `WEBGL_depth_texture` is missing.
+pub const SHADOW_PACK_CHUNK: &str = r#"
+    vec4 pack_depth(float depth) {
+        const vec4 bit_shift = vec4(256.0 * 256.0 * 256.0, 256.0 * 256.0, 256.0, 1.0);
+        const vec4 bit_mask = vec4(0.0, 1.0 / 256.0, 1.0 / 256.0, 1.0 / 256.0);
+        vec4 rgba = fract(depth * bit_shift);
+        return rgba - rgba.xxyz * bit_mask;
+    }
+
+    float unpack_depth(vec4 rgba) {
+        const vec4 bit_shift = vec4(
+            1.0 / (256.0 * 256.0 * 256.0), 1.0 / (256.0 * 256.0), 1.0 / 256.0, 1.0);
+        return dot(rgba, bit_shift);
+    }
+"#;
+
+/// Vertex side of a shadow receiver; call `shadow_vertex(position)` in `main`.
+pub const SHADOW_VERTEX_CHUNK: &str = r#"
+    uniform mat4 light_matrix;
+
+    varying highp vec4 vShadowCoord;
+
+    void shadow_vertex(vec4 position) {
+        vShadowCoord = light_matrix * position;
+    }
+"#;
+
+/// Fragment side of a shadow receiver. `shadow_factor()` is 0 in full
+/// shadow and 1 fully lit, averaged over a (2 * PCF_RADIUS + 1)² kernel.
+/// Define `SHADOW_PACKED` when the map stores packed RGBA depth.
+pub const SHADOW_FRAGMENT_CHUNK: &str = r#"
+    #include "shadow_pack"
+
+    #ifndef PCF_RADIUS
+    #define PCF_RADIUS 1
+    #endif
+
+    uniform sampler2D shadow_map;
+    uniform float shadow_bias;
+    uniform vec2 shadow_texel_size;
+
+    varying highp vec4 vShadowCoord;
+
+    float shadow_depth(vec2 uv) {
+    #ifdef SHADOW_PACKED
+        return unpack_depth(texture2D(shadow_map, uv));
+    #else
+        return texture2D(shadow_map, uv).r;
+    #endif
+    }
+
+    float shadow_factor() {
+        vec3 coord = vShadowCoord.xyz / vShadowCoord.w * 0.5 + 0.5;
+        if (coord.x < 0.0 || coord.x > 1.0 || coord.y < 0.0 || coord.y > 1.0 || coord.z > 1.0) {
+            return 1.0;
+        }
+        float lit = 0.0;
+        for (int x = -PCF_RADIUS; x <= PCF_RADIUS; x++) {
+            for (int y = -PCF_RADIUS; y <= PCF_RADIUS; y++) {
+                vec2 offset = vec2(float(x), float(y)) * shadow_texel_size;
+                lit += coord.z - shadow_bias > shadow_depth(coord.xy + offset) ? 0.0 : 1.0;
+            }
+        }
+        float taps = float((2 * PCF_RADIUS + 1) * (2 * PCF_RADIUS + 1));
+        return lit / taps;
+    }
+"#;
+
+/// Depth pass from the light's point of view. `light_matrix` is the
+/// light's view-projection times the model matrix.
+pub const SHADOW_DEPTH_VERT: &str = r#"
+    attribute vec4 position;
+
+    uniform mat4 light_matrix;
+
+    void main() {
+        gl_Position = light_matrix * position;
+    }
+"#;
+
+pub const SHADOW_DEPTH_FRAG: &str = r#"
+    #include "shadow_pack"
+
+    void main() {
+    #ifdef SHADOW_PACKED
+        gl_FragColor = pack_depth(gl_FragCoord.z);
+    #else
+        gl_FragColor = vec4(1.0);
+    #endif
+    }
+"#;
+
+/// `LIT_*` with shadows from a `ShadowMap`. Needs the shadow chunks, so it
+/// has to go through a `Preprocessor`.
+pub const LIT_SHADOWED_VERT: &str = r#"
+    #include "shadow_vertex"
+
+    attribute vec4 position;
+    attribute vec3 normal;
+    attribute vec4 color;
+
+    uniform mat4 projection_matrix;
+    uniform mat4 model_view_matrix;
+
+    varying lowp vec4 vColor;
+    varying highp vec3 vNormal;
+
+    void main() {
+        gl_Position = projection_matrix * model_view_matrix * position;
+        vNormal = (model_view_matrix * vec4(normal, 0.0)).xyz;
+        vColor = color;
+        shadow_vertex(position);
+    }
+"#;
+
+pub const LIT_SHADOWED_FRAG: &str = r#"
+    #include "shadow_fragment"
+
+    uniform vec3 light_direction;
+    uniform vec3 ambient_color;
+
+    varying lowp vec4 vColor;
+    varying highp vec3 vNormal;
+
+    void main() {
+        float diffuse = max(dot(normalize(vNormal), -normalize(light_direction)), 0.0);
+        gl_FragColor = vec4(vColor.rgb * (ambient_color + diffuse * shadow_factor()), vColor.a);
+    }
+"#;
+
+/// Per-instance transform and color for `InstancedMesh`. `model_view_matrix`
+/// holds only the view here; each instance brings its own model matrix.
+pub const INSTANCED_VERT: &str = r#"
+    attribute vec4 position;
+    attribute mat4 instance_matrix;
+    attribute vec4 instance_color;
+
+    uniform mat4 projection_matrix;
+    uniform mat4 model_view_matrix;
+
+    varying lowp vec4 vColor;
+
+    void main() {
+        gl_Position = projection_matrix * model_view_matrix * instance_matrix * position;
+        vColor = instance_color;
+    }
+"#;
+
+/// Flat `pick_color` per draw, for the color-ID picking pass.
+pub const PICKING_VERT: &str = r#"
+    attribute vec4 position;
+
+    uniform mat4 projection_matrix;
+    uniform mat4 model_view_matrix;
+
+    void main() {
+        gl_Position = projection_matrix * model_view_matrix * position;
+    }
+"#;
+
+pub const PICKING_FRAG: &str = r#"
+    precision mediump float;
+
+    uniform vec4 pick_color;
+
+    void main() {
+        gl_FragColor = pick_color;
+    }
+"#;
+
+/// Thick lines from `Polylines`, `line_width` pixels wide at any distance.
+/// Each quad corner is pushed out from its segment in screen space, meeting
+/// the next segment at a miter or overlapping it with a round end.
+/// `viewport_size` is the drawing buffer's size in pixels. Points behind
+/// the camera are not clipped against the near plane.
+pub const LINE_VERT: &str = r#"
+    attribute vec3 previous;
+    attribute vec3 start;
+    attribute vec3 end;
+    attribute vec3 next;
+    attribute vec2 corner;
+    attribute vec2 ends;
+    attribute vec4 color;
+
+    uniform mat4 projection_matrix;
+    uniform mat4 model_view_matrix;
+    uniform vec2 viewport_size;
+    uniform float line_width;
+    uniform float miter_limit;
+
+    varying highp vec2 vPoint;
+    varying highp vec2 vStart;
+    varying highp vec2 vEnd;
+    varying mediump vec2 vEnds;
+    varying lowp vec4 vColor;
+
+    vec4 to_clip(vec3 point) {
+        return projection_matrix * model_view_matrix * vec4(point, 1.0);
+    }
+
+    vec2 to_screen(vec4 clip) {
+        return (clip.xy / clip.w * 0.5 + 0.5) * viewport_size;
+    }
+
+    vec2 direction_or(vec2 delta, vec2 fallback) {
+        return dot(delta, delta) > 1e-8 ? normalize(delta) : fallback;
+    }
+
+    // A miter join whose miter would be longer than the limit, relative to
+    // the width, is drawn round. Entering along `a` and leaving along `b`,
+    // the miter is 1 / cos(turn / 2) long and |a + b| is 2 cos(turn / 2).
+    float join_style(float style, vec2 a, vec2 b) {
+        if (style > 0.5) {
+            return style;
+        }
+        return length(a + b) * 0.5 * miter_limit < 1.0 ? 3.0 : 0.0;
+    }
+
+    void main() {
+        vec4 clip_start = to_clip(start);
+        vec4 clip_end = to_clip(end);
+        vec2 a = to_screen(clip_start);
+        vec2 b = to_screen(clip_end);
+        vec2 direction = direction_or(b - a, vec2(1.0, 0.0));
+        vec2 before = direction_or(a - to_screen(to_clip(previous)), direction);
+        vec2 after = direction_or(to_screen(to_clip(next)) - b, direction);
+        float start_style = join_style(ends.x, before, direction);
+        float end_style = join_style(ends.y, direction, after);
+
+        bool at_end = corner.x > 0.5;
+        float style = at_end ? end_style : start_style;
+        vec2 normal = vec2(-direction.y, direction.x);
+        // One pixel past the edge for the anti-aliased fringe.
+        float extent = line_width * 0.5 + 1.0;
+        vec2 offset;
+        if (style < 0.5) {
+            vec2 tangent = normalize(at_end ? direction + after : before + direction);
+            vec2 miter = vec2(-tangent.y, tangent.x);
+            offset = miter * corner.y * extent / dot(miter, normal);
+        } else {
+            // Butt caps stop at the end point, apart from the fringe.
+            float along = style < 1.5 ? 1.0 : extent;
+            offset = normal * corner.y * extent + direction * (at_end ? along : -along);
+        }
+
+        vec2 point = (at_end ? b : a) + offset;
+        vec4 clip = at_end ? clip_end : clip_start;
+        gl_Position = vec4((point / viewport_size * 2.0 - 1.0) * clip.w, clip.zw);
+        vPoint = point;
+        vStart = a;
+        vEnd = b;
+        vEnds = vec2(start_style, end_style);
+        vColor = color;
+    }
+"#;
+
+/// Coverage of `LINE_VERT`'s quads from the pixel's distance to the
+/// segment, shaped by the end styles: 0 miter, 1 butt, 2 square, 3 round.
+/// Needs blending for the soft edge.
+pub const LINE_FRAG: &str = r#"
+    precision mediump float;
+
+    uniform float line_width;
+
+    varying highp vec2 vPoint;
+    varying highp vec2 vStart;
+    varying highp vec2 vEnd;
+    varying mediump vec2 vEnds;
+    varying lowp vec4 vColor;
+
+    void main() {
+        highp vec2 segment = vEnd - vStart;
+        highp float segment_length = length(segment);
+        highp vec2 direction = segment_length > 1e-4 ? segment / segment_length : vec2(1.0, 0.0);
+        highp vec2 relative = vPoint - vStart;
+        highp float along = dot(relative, direction);
+        highp float across = abs(dot(relative, vec2(-direction.y, direction.x)));
+
+        bool at_end = along > segment_length * 0.5;
+        float style = at_end ? vEnds.y : vEnds.x;
+        // How far past the nearer end point the pixel lies.
+        highp float beyond = at_end ? along - segment_length : -along;
+        float half_width = line_width * 0.5;
+        highp float edge_distance = across;
+        if (style > 2.5) {
+            edge_distance = beyond > 0.0 ? length(vec2(beyond, across)) : across;
+        } else if (style > 1.5) {
+            edge_distance = max(across, beyond);
+        } else if (style > 0.5) {
+            edge_distance = max(across, beyond + half_width);
+        }
+
+        float coverage = clamp(half_width + 0.5 - edge_distance, 0.0, 1.0);
+        if (coverage <= 0.0) {
+            discard;
+        }
+        gl_FragColor = vec4(vColor.rgb, vColor.a * coverage);
+    }
+"#;
+
+/// Round points with per-point colors for `PointCloudMesh`. With
+/// `size_attenuation` set, `point_size` is in world units and points shrink
+/// with distance; otherwise it is in pixels. `viewport_size` is the drawing
+/// buffer's size in pixels.
+pub const POINTS_VERT: &str = r#"
+    attribute vec4 position;
+    attribute vec4 color;
+
+    uniform mat4 projection_matrix;
+    uniform mat4 model_view_matrix;
+    uniform vec2 viewport_size;
+    uniform float point_size;
+    uniform float size_attenuation;
+
+    varying lowp vec4 vColor;
+
+    void main() {
+        gl_Position = projection_matrix * model_view_matrix * position;
+        // The projected height of point_size at this depth, in pixels.
+        float size = size_attenuation > 0.5
+            ? point_size * projection_matrix[1][1] * viewport_size.y * 0.5 / gl_Position.w
+            : point_size;
+        gl_PointSize = clamp(size, 1.0, 64.0);
+        vColor = color;
+    }
+"#;
+
+pub const POINTS_FRAG: &str = r#"
+    precision mediump float;
+
+    varying lowp vec4 vColor;
+
+    void main() {
+        vec2 offset = gl_PointCoord - 0.5;
+        if (dot(offset, offset) > 0.25) {
+            discard;
+        }
+        gl_FragColor = vColor;
+    }
+"#;
@@ -0,0 +1,6 @@
+mod shader_trait;
+mod preprocessor;
+pub mod builtin;
+
+pub use self::shader_trait::Shader;
+pub use self::preprocessor::{GlslVersion, Preprocessor, ShaderStage};
@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+
+/// The GLSL dialect a preprocessed source is emitted for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlslVersion {
+    /// GLSL ES 1.00, used by `WebGlRenderingContext`.
+    WebGl1,
+    /// GLSL ES 3.00, used by `WebGl2RenderingContext`.
+    WebGl2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+}
+
+/// Source-to-source pass run before a shader is handed to
+/// `Shader::build_program`.
+///
+/// It expands `#include "name"` from the registered chunks, injects
+/// `#define`s for the requested variant and prepends the `#version` and
+/// precision header for the target dialect. Sources are written in
+/// WebGL1 style (`attribute`, `varying`, `texture2D`, `gl_FragColor`);
+/// for WebGL2 the header maps those onto their GLSL ES 3.00 equivalents.
+#[derive(Debug, Clone)]
+pub struct Preprocessor {
+    version: GlslVersion,
+    chunks: HashMap<String, String>,
+}
+
+impl Preprocessor {
+    pub fn new(version: GlslVersion) -> Preprocessor {
+        Preprocessor {
+            version,
+            chunks: HashMap::new(),
+        }
+    }
+
+    pub fn version(&self) -> GlslVersion {
+        self.version
+    }
+
+    /// Make `source` available to `#include "name"`. Registering the same
+    /// name again replaces the chunk.
+    pub fn register_chunk(&mut self, name: &str, source: &str) {
+        self.chunks.insert(name.to_string(), source.to_string());
+    }
+
+    /// Expand `source` for `stage`. Each define is emitted as
+    /// `#define NAME VALUE`, or `#define NAME` when the value is empty.
+    pub fn process(
+        &self,
+        source: &str,
+        stage: ShaderStage,
+        defines: &[(&str, &str)],
+    ) -> Result<String, String> {
+        let mut body = Vec::new();
+        let mut extensions = Vec::new();
+        let mut include_stack = Vec::new();
+        self.expand(source, &mut include_stack, &mut body, &mut extensions)?;
+
+        // `#version` must come first, and `#extension` before any
+        // non-preprocessor token, so the extensions are hoisted between the
+        // version and the precision statements.
+        let mut output = Vec::new();
+        output.extend(self.version_line().map(String::from));
+        output.extend(extensions);
+        output.extend(self.precision(stage).into_iter().map(String::from));
+        for (name, value) in defines {
+            if value.is_empty() {
+                output.push(format!("#define {}", name));
+            } else {
+                output.push(format!("#define {} {}", name, value));
+            }
+        }
+        output.extend(self.compatibility(stage).into_iter().map(String::from));
+        output.extend(body);
+        Ok(output.join("\n"))
+    }
+
+    /// Process a vertex / fragment pair with the same defines.
+    pub fn process_pair(
+        &self,
+        vert_shader: &str,
+        frag_shader: &str,
+        defines: &[(&str, &str)],
+    ) -> Result<(String, String), String> {
+        Ok((
+            self.process(vert_shader, ShaderStage::Vertex, defines)?,
+            self.process(frag_shader, ShaderStage::Fragment, defines)?,
+        ))
+    }
+
+    fn expand(
+        &self,
+        source: &str,
+        include_stack: &mut Vec<String>,
+        body: &mut Vec<String>,
+        extensions: &mut Vec<String>,
+    ) -> Result<(), String> {
+        for (line_number, line) in source.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.starts_with("#version") {
+                // The header decides the version.
+                continue;
+            }
+            if trimmed.starts_with("#extension") {
+                extensions.push(trimmed.to_string());
+                continue;
+            }
+            if let Some(rest) = trimmed.strip_prefix("#include") {
+                let name = parse_include(rest).ok_or_else(|| {
+                    format!("line {}: malformed include `{}`", line_number + 1, trimmed)
+                })?;
+                if include_stack.iter().any(|included| included == name) {
+                    return Err(format!(
+                        "cyclic include: {} -> {}",
+                        include_stack.join(" -> "),
+                        name
+                    ));
+                }
+                let chunk = self.chunks.get(name).ok_or_else(|| {
+                    format!("line {}: unknown chunk \"{}\"", line_number + 1, name)
+                })?;
+                include_stack.push(name.to_string());
+                self.expand(chunk, include_stack, body, extensions)?;
+                include_stack.pop();
+                continue;
+            }
+            body.push(line.to_string());
+        }
+        Ok(())
+    }
+
+    fn version_line(&self) -> Option<&'static str> {
+        match self.version {
+            GlslVersion::WebGl1 => None,
+            GlslVersion::WebGl2 => Some("#version 300 es"),
+        }
+    }
+
+    fn precision(&self, stage: ShaderStage) -> Vec<&'static str> {
+        match (self.version, stage) {
+            (GlslVersion::WebGl1, ShaderStage::Vertex) => vec![],
+            (GlslVersion::WebGl1, ShaderStage::Fragment) => vec![
+                "#ifdef GL_FRAGMENT_PRECISION_HIGH",
+                "precision highp float;",
+                "#else",
+                "precision mediump float;",
+                "#endif",
+            ],
+            (GlslVersion::WebGl2, _) => vec![
+                "precision highp float;",
+                "precision highp int;",
+            ],
+        }
+    }
+
+    fn compatibility(&self, stage: ShaderStage) -> Vec<&'static str> {
+        match (self.version, stage) {
+            (GlslVersion::WebGl1, _) => vec![],
+            (GlslVersion::WebGl2, ShaderStage::Vertex) => vec![
+                "#define attribute in",
+                "#define varying out",
+            ],
+            (GlslVersion::WebGl2, ShaderStage::Fragment) => vec![
+                "#define varying in",
+                "#define texture2D texture",
+                "#define textureCube texture",
+                "out highp vec4 pc_fragColor;",
+                "#define gl_FragColor pc_fragColor",
+            ],
+        }
+    }
+}
+
+/// Pull `name` out of `"name"`.
+fn parse_include(rest: &str) -> Option<&str> {
+    let rest = rest.trim();
+    let name = rest.strip_prefix('"')?.strip_suffix('"')?;
+    if name.is_empty() || name.contains('"') {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webgl1_fragment_gets_precision_header() {
+        let pre = Preprocessor::new(GlslVersion::WebGl1);
+        let out = pre
+            .process("void main() {}", ShaderStage::Fragment, &[])
+            .unwrap();
+        assert!(out.starts_with("#ifdef GL_FRAGMENT_PRECISION_HIGH"));
+        assert!(out.contains("precision mediump float;"));
+        assert!(!out.contains("#version"));
+        assert!(out.ends_with("void main() {}"));
+    }
+
+    #[test]
+    fn webgl1_vertex_is_left_alone() {
+        let pre = Preprocessor::new(GlslVersion::WebGl1);
+        let out = pre
+            .process("void main() {}", ShaderStage::Vertex, &[])
+            .unwrap();
+        assert_eq!(out, "void main() {}");
+    }
+
+    #[test]
+    fn webgl2_version_is_first_line_and_replaces_source_version() {
+        let pre = Preprocessor::new(GlslVersion::WebGl2);
+        let out = pre
+            .process("#version 100\nvoid main() {}", ShaderStage::Vertex, &[])
+            .unwrap();
+        assert_eq!(out.lines().next(), Some("#version 300 es"));
+        assert_eq!(out.matches("#version").count(), 1);
+        assert!(out.contains("#define attribute in"));
+    }
+
+    #[test]
+    fn webgl2_fragment_maps_frag_color() {
+        let pre = Preprocessor::new(GlslVersion::WebGl2);
+        let out = pre
+            .process("void main() { gl_FragColor = vec4(1.0); }", ShaderStage::Fragment, &[])
+            .unwrap();
+        assert!(out.contains("out highp vec4 pc_fragColor;"));
+        assert!(out.contains("#define gl_FragColor pc_fragColor"));
+        assert!(out.contains("#define texture2D texture"));
+    }
+
+    #[test]
+    fn defines_follow_header() {
+        let pre = Preprocessor::new(GlslVersion::WebGl2);
+        let out = pre
+            .process(
+                "void main() {}",
+                ShaderStage::Vertex,
+                &[("HAS_NORMALS", ""), ("NUM_LIGHTS", "4")],
+            )
+            .unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        let version = lines.iter().position(|l| *l == "#version 300 es").unwrap();
+        let has_normals = lines.iter().position(|l| *l == "#define HAS_NORMALS").unwrap();
+        let num_lights = lines.iter().position(|l| *l == "#define NUM_LIGHTS 4").unwrap();
+        let main = lines.iter().position(|l| *l == "void main() {}").unwrap();
+        assert!(version < has_normals);
+        assert!(has_normals < num_lights);
+        assert!(num_lights < main);
+    }
+
+    #[test]
+    fn includes_expand_recursively() {
+        let mut pre = Preprocessor::new(GlslVersion::WebGl1);
+        pre.register_chunk("common", "#include \"consts\"\nfloat sq(float x) { return x * x; }");
+        pre.register_chunk("consts", "const float PI = 3.14159;");
+        let out = pre
+            .process("#include \"common\"\nvoid main() {}", ShaderStage::Vertex, &[])
+            .unwrap();
+        assert_eq!(
+            out,
+            "const float PI = 3.14159;\nfloat sq(float x) { return x * x; }\nvoid main() {}"
+        );
+    }
+
+    #[test]
+    fn same_chunk_may_be_included_twice() {
+        let mut pre = Preprocessor::new(GlslVersion::WebGl1);
+        pre.register_chunk("a", "// a");
+        let out = pre
+            .process("#include \"a\"\n#include \"a\"", ShaderStage::Vertex, &[])
+            .unwrap();
+        assert_eq!(out, "// a\n// a");
+    }
+
+    #[test]
+    fn unknown_chunk_reports_line() {
+        let pre = Preprocessor::new(GlslVersion::WebGl1);
+        let err = pre
+            .process("void f() {}\n#include \"missing\"", ShaderStage::Vertex, &[])
+            .unwrap_err();
+        assert_eq!(err, "line 2: unknown chunk \"missing\"");
+    }
+
+    #[test]
+    fn malformed_include_is_rejected() {
+        let pre = Preprocessor::new(GlslVersion::WebGl1);
+        assert!(pre
+            .process("#include <common>", ShaderStage::Vertex, &[])
+            .unwrap_err()
+            .contains("malformed include"));
+        assert!(pre
+            .process("#include \"\"", ShaderStage::Vertex, &[])
+            .is_err());
+    }
+
+    #[test]
+    fn cyclic_include_is_rejected() {
+        let mut pre = Preprocessor::new(GlslVersion::WebGl1);
+        pre.register_chunk("a", "#include \"b\"");
+        pre.register_chunk("b", "#include \"a\"");
+        let err = pre
+            .process("#include \"a\"", ShaderStage::Vertex, &[])
+            .unwrap_err();
+        assert_eq!(err, "cyclic include: a -> b -> a");
+    }
+
+    #[test]
+    fn extensions_are_hoisted_above_defines() {
+        let mut pre = Preprocessor::new(GlslVersion::WebGl1);
+        pre.register_chunk("derivatives", "#extension GL_OES_standard_derivatives : enable");
+        let out = pre
+            .process(
+                "#include \"derivatives\"\nvoid main() {}",
+                ShaderStage::Fragment,
+                &[("USE_FOG", "")],
+            )
+            .unwrap();
+        let extension = out.find("#extension").unwrap();
+        let define = out.find("#define USE_FOG").unwrap();
+        assert!(extension < define);
+        assert_eq!(out.matches("#extension").count(), 1);
+    }
+
+    #[test]
+    fn webgl2_extensions_directly_follow_version() {
+        let pre = Preprocessor::new(GlslVersion::WebGl2);
+        let out = pre
+            .process(
+                "#extension GL_EXT_shader_texture_lod : enable\nvoid main() {}",
+                ShaderStage::Fragment,
+                &[],
+            )
+            .unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "#version 300 es");
+        assert_eq!(lines[1], "#extension GL_EXT_shader_texture_lod : enable");
+        assert_eq!(lines[2], "precision highp float;");
+    }
+
+    #[test]
+    fn extensions_precede_webgl1_precision() {
+        let pre = Preprocessor::new(GlslVersion::WebGl1);
+        let out = pre
+            .process(
+                "#extension GL_OES_standard_derivatives : enable\nvoid main() {}",
+                ShaderStage::Fragment,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(
+            out.lines().next(),
+            Some("#extension GL_OES_standard_derivatives : enable")
+        );
+    }
+
+    #[test]
+    fn process_pair_uses_stage_specific_headers() {
+        let pre = Preprocessor::new(GlslVersion::WebGl1);
+        let (vert, frag) = pre
+            .process_pair("void main() {}", "void main() {}", &[("HAS_NORMALS", "")])
+            .unwrap();
+        assert!(!vert.contains("precision"));
+        assert!(frag.contains("precision"));
+        assert!(vert.contains("#define HAS_NORMALS"));
+        assert!(frag.contains("#define HAS_NORMALS"));
+    }
+}
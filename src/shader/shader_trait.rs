@@ -1,33 +1,105 @@
-puse wasm_bindgen::prelude::*;
-use web_sys::{WebGlRenderingContext, WebGlShader, WebGlProgram};
+use std::collections::HashMap;
 
-pub trait Shader {
+use wasm_bindgen::prelude::*;
+use web_sys::{WebGlRenderingContext, WebGlShader, WebGlProgram, WebGlUniformLocation};
+
+/// A linked shader program together with the locations of every active
+/// attribute and uniform, looked up once right after linking.
+#[derive(Debug)]
+pub struct Shader {
+    program: WebGlProgram,
+    attributes: HashMap<String, u32>,
+    uniforms: HashMap<String, WebGlUniformLocation>,
+}
+
+impl Shader {
     pub fn new(
         context: &WebGlRenderingContext,
         vert_shader: &str,
         frag_shader: &str,
-    ) -> Result<Shader, JsValue>;
+    ) -> Result<Shader, JsValue> {
+        let program = Shader::build_program(context, vert_shader, frag_shader)?;
+
+        // Ask the program which attributes and uniforms survived linking,
+        // so materials never have to know the names in advance.
+        let mut attributes = HashMap::new();
+        let num_attributes = context
+            .get_program_parameter(&program, WebGlRenderingContext::ACTIVE_ATTRIBUTES)
+            .as_f64()
+            .unwrap_or(0.0) as u32;
+        for index in 0..num_attributes {
+            if let Some(info) = context.get_active_attrib(&program, index) {
+                let location = context.get_attrib_location(&program, &info.name());
+                if location >= 0 {
+                    attributes.insert(info.name(), location as u32);
+                }
+            }
+        }
+
+        let mut uniforms = HashMap::new();
+        let num_uniforms = context
+            .get_program_parameter(&program, WebGlRenderingContext::ACTIVE_UNIFORMS)
+            .as_f64()
+            .unwrap_or(0.0) as u32;
+        for index in 0..num_uniforms {
+            if let Some(info) = context.get_active_uniform(&program, index) {
+                // Arrays are reported as "name[0]"; register them by base name.
+                let name = info.name().trim_end_matches("[0]").to_string();
+                if let Some(location) = context.get_uniform_location(&program, &info.name()) {
+                    uniforms.insert(name, location);
+                }
+            }
+        }
+
+        Ok(Shader {
+            program,
+            attributes,
+            uniforms,
+        })
+    }
+
+    pub fn program(&self) -> &WebGlProgram {
+        &self.program
+    }
+
+    pub fn attrib_location(&self, name: &str) -> Option<u32> {
+        self.attributes.get(name).copied()
+    }
 
-    pub fn program() -> &WebGlProgram;
+    pub fn uniform_location(&self, name: &str) -> Option<&WebGlUniformLocation> {
+        self.uniforms.get(name)
+    }
 
     pub fn build_program(
         context: &WebGlRenderingContext,
         vert_shader: &str,
         frag_shader: &str,
-    ) -> Result<WebGlProgram, String>{
-         let vert_shader = compile_shader(
-             &context,
-             WebGlRenderingContext::VERTEX_SHADER,
-             vert_shader)?;
-        let frag_shader = compile_shader(
-            &context,
+    ) -> Result<WebGlProgram, String> {
+        let vert_shader = Shader::compile_shader(
+            context,
+            WebGlRenderingContext::VERTEX_SHADER,
+            vert_shader)?;
+        let frag_shader = match Shader::compile_shader(
+            context,
             WebGlRenderingContext::FRAGMENT_SHADER,
-            frag_shader)?;
+            frag_shader)
+        {
+            Ok(frag_shader) => frag_shader,
+            Err(error) => {
+                context.delete_shader(Some(&vert_shader));
+                return Err(error);
+            }
+        };
 
-        link_program(&gl, &vert_shader, &frag_shader)
+        let program = Shader::link_program(context, &vert_shader, &frag_shader);
+        // A linked program no longer needs its shader objects, and a
+        // failed link has no use for them either.
+        context.delete_shader(Some(&vert_shader));
+        context.delete_shader(Some(&frag_shader));
+        program
     }
 
-    fn compile_shader(
+    pub fn compile_shader(
         context: &WebGlRenderingContext,
         shader_type: u32,
         source: &str,
@@ -47,13 +119,15 @@ pub trait Shader {
         {
             Ok(shader)
         } else {
-            Err(context
+            let error = context
                 .get_shader_info_log(&shader)
-                .unwrap_or_else(|| "Unknown error creating shader".to_string()))
+                .unwrap_or_else(|| "Unknown error creating shader".to_string());
+            context.delete_shader(Some(&shader));
+            Err(error)
         }
     }
 
-    fn link_program(
+    pub fn link_program(
         context: &WebGlRenderingContext,
         vert_shader: &WebGlShader,
         frag_shader: &WebGlShader,
@@ -65,6 +139,10 @@ pub trait Shader {
         context.attach_shader(&program, vert_shader);
         context.attach_shader(&program, frag_shader);
         context.link_program(&program);
+        // Detached, the shaders are freed as soon as their owner deletes
+        // them instead of living as long as the program.
+        context.detach_shader(&program, vert_shader);
+        context.detach_shader(&program, frag_shader);
 
         if context
             .get_program_parameter(&program, WebGlRenderingContext::LINK_STATUS)
@@ -73,11 +151,12 @@ pub trait Shader {
         {
             Ok(program)
         } else {
-            Err(context
+            let error = context
                 .get_program_info_log(&program)
-                .unwrap_or_else(|| 
-                    "Unknown error creating program object".to_string())
-                )
+                .unwrap_or_else(||
+                    "Unknown error creating program object".to_string());
+            context.delete_program(Some(&program));
+            Err(error)
         }
     }
 }
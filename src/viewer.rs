@@ -0,0 +1,298 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use wasm_bindgen::prelude::*;
+use web_sys::WebGlRenderingContext as GL;
+
+use crate::material::{MaterialParam, ProgramCache};
+use crate::math::Vec3;
+use crate::postprocess::PostProcessStack;
+use crate::renderer::{ResourceManager, ShadowMap};
+use crate::mesh::{read_stl, write_stl, CullStats, PointCloud, DEFAULT_WELD_TOLERANCE};
+use crate::{Model, Route, Scan};
+
+/// Pick the node at CSS pixel coordinates relative to the canvas.
+pub(crate) type PickFn = Rc<dyn Fn(f32, f32) -> Result<Option<u32>, JsValue>>;
+
+/// Cast a ray from CSS pixel coordinates and return the world-space hit
+/// point, normal and distance from the camera's near plane.
+pub(crate) type RaycastFn = Rc<dyn Fn(f32, f32) -> Option<(Vec3, Vec3, f32)>>;
+
+/// Handle returned to JS by `start`, sharing the renderer's state with the
+/// running animation loop.
+#[wasm_bindgen]
+pub struct Viewer {
+    context: GL,
+    resources: ResourceManager,
+    program_cache: Rc<RefCell<ProgramCache>>,
+    post_process: Rc<RefCell<PostProcessStack>>,
+    shadow_map: Rc<RefCell<ShadowMap>>,
+    context_lost: Rc<Cell<bool>>,
+    pick: PickFn,
+    selected: Rc<RefCell<Option<u32>>>,
+    raycast: RaycastFn,
+    cull_stats: Rc<Cell<CullStats>>,
+    wireframe: Rc<Cell<bool>>,
+    model: Rc<RefCell<Model>>,
+    route: Rc<RefCell<Route>>,
+    scan: Rc<RefCell<Scan>>,
+}
+
+impl Viewer {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        context: GL,
+        resources: ResourceManager,
+        program_cache: Rc<RefCell<ProgramCache>>,
+        post_process: Rc<RefCell<PostProcessStack>>,
+        shadow_map: Rc<RefCell<ShadowMap>>,
+        context_lost: Rc<Cell<bool>>,
+        pick: PickFn,
+        selected: Rc<RefCell<Option<u32>>>,
+        raycast: RaycastFn,
+        cull_stats: Rc<Cell<CullStats>>,
+        wireframe: Rc<Cell<bool>>,
+        model: Rc<RefCell<Model>>,
+        route: Rc<RefCell<Route>>,
+        scan: Rc<RefCell<Scan>>,
+    ) -> Viewer {
+        Viewer {
+            context,
+            resources,
+            program_cache,
+            post_process,
+            shadow_map,
+            context_lost,
+            pick,
+            selected,
+            raycast,
+            cull_stats,
+            wireframe,
+            model,
+            route,
+            scan,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Viewer {
+    /// Recompile the shader registered as `name` (e.g. "lit") from new
+    /// sources. If compiling or linking fails the error log is returned
+    /// and the previous program keeps drawing.
+    pub fn reload_shader(&self, name: &str, vert: &str, frag: &str) -> Result<(), JsValue> {
+        self.program_cache
+            .borrow_mut()
+            .reload(&self.context, name, vert, frag)
+    }
+
+    /// Turn a post-processing effect on or off: "bloom", "tone_mapping",
+    /// "color_grading", "gamma", "fxaa" or "vignette".
+    pub fn set_effect_enabled(&self, name: &str, enabled: bool) -> Result<(), JsValue> {
+        if self.post_process.borrow_mut().set_enabled(name, enabled) {
+            Ok(())
+        } else {
+            Err(format!("no post-processing effect named \"{}\"", name).into())
+        }
+    }
+
+    /// Tune a float parameter of an effect, e.g.
+    /// `set_effect_param("tone_mapping", "exposure", 1.5)`.
+    pub fn set_effect_param(&self, name: &str, param: &str, value: f32) -> Result<(), JsValue> {
+        if self
+            .post_process
+            .borrow_mut()
+            .set_param(name, param, MaterialParam::Float(value))
+        {
+            Ok(())
+        } else {
+            Err(format!("effect \"{}\" has no parameter \"{}\"", name, param).into())
+        }
+    }
+
+    /// Names of the effects in the order they run.
+    pub fn effect_names(&self) -> Vec<JsValue> {
+        self.post_process
+            .borrow()
+            .effect_names()
+            .into_iter()
+            .map(JsValue::from)
+            .collect()
+    }
+
+    /// Render the shadow map each frame before the main pass.
+    pub fn set_shadows_enabled(&self, enabled: bool) {
+        self.shadow_map.borrow_mut().set_enabled(enabled);
+    }
+
+    pub fn set_shadow_bias(&self, bias: f32) {
+        self.shadow_map.borrow_mut().set_bias(bias);
+    }
+
+    /// Reallocate the shadow map at `resolution`² texels.
+    pub fn set_shadow_resolution(&self, resolution: i32) -> Result<(), JsValue> {
+        self.shadow_map.borrow_mut().set_resolution(resolution)
+    }
+
+    /// Draw the scene's edges as lines instead of filling its faces. Shadows
+    /// and picking still use the faces.
+    pub fn set_wireframe(&self, enabled: bool) {
+        self.wireframe.set(enabled);
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.wireframe.get()
+    }
+
+    /// Show the model in a binary or ASCII STL file instead of the one
+    /// shown now. Vertices closer than `DEFAULT_WELD_TOLERANCE` are welded
+    /// and the model is centered and scaled to fit the view. On error the
+    /// shown model is kept.
+    pub fn load_stl(&self, data: &[u8]) -> Result<(), JsValue> {
+        let mesh = read_stl(data, DEFAULT_WELD_TOLERANCE)?;
+        let model = Model::fitted(&self.resources, mesh)?;
+        *self.model.borrow_mut() = model;
+        Ok(())
+    }
+
+    /// The displayed model as a binary STL, for printing or for other
+    /// tools. Coordinates are the model's own, as loaded.
+    pub fn export_stl(&self) -> Vec<u8> {
+        write_stl(self.model.borrow().mesh())
+    }
+
+    /// Draw a route through `points`, flat xyz world coordinates, over the
+    /// scene as a thick line. Fewer than two points clear it.
+    pub fn set_route(&self, points: &[f32]) -> Result<(), JsValue> {
+        self.route.borrow_mut().set_points(&self.resources, points)
+    }
+
+    /// Width of the route line in drawing-buffer pixels.
+    pub fn set_route_width(&self, width: f32) {
+        self.route.borrow_mut().set_width(width);
+    }
+
+    /// Show a point cloud of flat xyz world `positions` and RGBA `colors`,
+    /// four bytes per point or none for white, replacing any shown before.
+    /// Only what fits the point budget is drawn, streaming in over the
+    /// following frames.
+    pub fn set_point_cloud(&self, positions: Vec<f32>, colors: Vec<u8>) -> Result<(), JsValue> {
+        let cloud = PointCloud::new(positions, colors)?;
+        self.scan.borrow_mut().set_cloud(&cloud)
+    }
+
+    pub fn clear_point_cloud(&self) {
+        self.scan.borrow_mut().clear();
+    }
+
+    /// Most points of the cloud drawn, and kept on the GPU, at once.
+    pub fn set_point_budget(&self, budget: u32) {
+        self.scan.borrow_mut().set_point_budget(budget as usize);
+    }
+
+    /// Diameter of the cloud's points in world units.
+    pub fn set_point_size(&self, size: f32) {
+        self.scan.borrow_mut().set_point_size(size);
+    }
+
+    /// The point cloud in the last frame, as `{ selected_nodes,
+    /// selected_points, resident_nodes, resident_points, uploaded_points }`:
+    /// what was chosen to draw, what is on the GPU and what was streamed.
+    pub fn point_cloud_stats(&self) -> Result<JsValue, JsValue> {
+        let stats = self.scan.borrow().stats();
+        let object = js_sys::Object::new();
+        for (key, value) in [
+            ("selected_nodes", stats.selected_nodes),
+            ("selected_points", stats.selected_points),
+            ("resident_nodes", stats.resident_nodes),
+            ("resident_points", stats.resident_points),
+            ("uploaded_points", stats.uploaded_points),
+        ] {
+            js_sys::Reflect::set(&object, &key.into(), &value.into())?;
+        }
+        Ok(object.into())
+    }
+
+    /// The ID of the node drawn at (`x`, `y`), in CSS pixels from the
+    /// canvas's top left corner, or `undefined` over the background.
+    pub fn pick(&self, x: f32, y: f32) -> Result<Option<u32>, JsValue> {
+        (self.pick)(x, y)
+    }
+
+    /// The node last clicked on the canvas, if any.
+    pub fn selected(&self) -> Option<u32> {
+        *self.selected.borrow()
+    }
+
+    /// Cast a ray through (`x`, `y`), in CSS pixels from the canvas's top
+    /// left corner, against the scene's geometry on the CPU. Returns
+    /// `[x, y, z, nx, ny, nz, distance]`: the world-space hit point, the
+    /// surface normal facing the camera and the distance along the ray,
+    /// or `undefined` if nothing is hit.
+    pub fn raycast(&self, x: f32, y: f32) -> Option<Vec<f32>> {
+        let (point, normal, distance) = (self.raycast)(x, y)?;
+        let mut hit = Vec::with_capacity(7);
+        hit.extend_from_slice(&point);
+        hit.extend_from_slice(&normal);
+        hit.push(distance);
+        Some(hit)
+    }
+
+    /// True from a `webglcontextlost` event until the context has been
+    /// restored and every resource rebuilt. Nothing is drawn meanwhile.
+    pub fn is_context_lost(&self) -> bool {
+        self.context_lost.get()
+    }
+
+    /// GPU memory held by the viewer's managed resources, as an object
+    /// with `buffers`, `buffer_bytes`, `textures`, `texture_bytes`,
+    /// `renderbuffers`, `renderbuffer_bytes`, `programs` and `total_bytes`.
+    /// Render targets, the shadow map and cached programs are included.
+    pub fn memory_stats(&self) -> Result<JsValue, JsValue> {
+        let stats = self.resources.stats();
+        let object = js_sys::Object::new();
+        for (key, value) in [
+            ("buffers", stats.buffers),
+            ("buffer_bytes", stats.buffer_bytes),
+            ("textures", stats.textures),
+            ("texture_bytes", stats.texture_bytes),
+            ("renderbuffers", stats.renderbuffers),
+            ("renderbuffer_bytes", stats.renderbuffer_bytes),
+            ("programs", stats.programs),
+            ("total_bytes", stats.total_bytes()),
+        ] {
+            js_sys::Reflect::set(&object, &key.into(), &(value as f64).into())?;
+        }
+        Ok(object.into())
+    }
+
+    /// GL state calls since the page loaded, as `{ issued, skipped }`:
+    /// those that reached the context and those dropped by the state cache
+    /// because nothing would have changed.
+    pub fn state_stats(&self) -> Result<JsValue, JsValue> {
+        let counters = self.resources.state().counters();
+        let object = js_sys::Object::new();
+        for (key, value) in [("issued", counters.issued), ("skipped", counters.skipped)] {
+            js_sys::Reflect::set(&object, &key.into(), &value.into())?;
+        }
+        Ok(object.into())
+    }
+
+    /// Frustum culling in the last frame, as `{ tested, culled, visible }`
+    /// draw counts of the main pass. The shadow pass is culled too but not
+    /// counted.
+    pub fn cull_stats(&self) -> Result<JsValue, JsValue> {
+        let stats = self.cull_stats.get();
+        let object = js_sys::Object::new();
+        for (key, value) in [
+            ("tested", stats.tested),
+            ("culled", stats.culled),
+            ("visible", stats.visible()),
+        ] {
+            js_sys::Reflect::set(&object, &key.into(), &value.into())?;
+        }
+        Ok(object.into())
+    }
+}